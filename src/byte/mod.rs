@@ -18,9 +18,9 @@ mod mock;
 pub use self::cat::CatStream;
 pub use self::clip::{ClipStream, ClipperParams};
 pub use self::cut::CutStream;
-pub use self::patch::PatchStream;
+pub use self::patch::{PatchSource, PatchStream};
 pub use self::raw::RawStream;
-pub use self::tee::{TeeStream, TeeStreamReader};
+pub use self::tee::TeeStream;
 pub use self::text::{GaplessTextStream, TextStream};
 pub use self::zero::ZeroStream;
 pub use self::zip::ZipStream;
@@ -175,7 +175,7 @@ where
 
 #[cfg(test)]
 pub mod tester {
-    pub use crate::params::{BLOCK_SIZE, MARGIN_SIZE};
+    pub use crate::params::BLOCK_SIZE;
     pub use rand::Rng;
     pub use std::io::Read;
 
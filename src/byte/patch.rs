@@ -5,21 +5,44 @@
 use super::ByteStream;
 use crate::params::BLOCK_SIZE;
 use crate::streambuf::StreamBuf;
-use crate::text::parser::TextParser;
+use crate::text::parser::{new_record_parser, TextParser};
 use crate::text::InoutFormat;
 use anyhow::{anyhow, Result};
 
+// source of patch records; each record replaces [offset, offset + span) of the stream with the bytes
+pub trait PatchSource: Send {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>>;
+
+    // name of the last record if the format has one; carried to the slices by --guide
+    fn label(&self) -> Option<&[u8]> {
+        None
+    }
+
+    // describes the last records for error messages
+    fn format_cache(&self) -> String;
+}
+
+impl PatchSource for TextParser {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.read_line(buf)
+    }
+
+    fn format_cache(&self) -> String {
+        self.format_cache(true)
+    }
+}
+
 struct PatchFeeder {
-    src: TextParser,
+    src: Box<dyn PatchSource>,
     offset: usize,
     span: usize,
     buf: Vec<u8>,
 }
 
 impl PatchFeeder {
-    fn new(patch: Box<dyn ByteStream>, format: &InoutFormat) -> Self {
+    fn new(src: Box<dyn PatchSource>) -> Self {
         PatchFeeder {
-            src: TextParser::new(patch, format),
+            src,
             offset: 0,
             span: 0,
             buf: Vec::new(),
@@ -33,7 +56,7 @@ impl PatchFeeder {
         // flush the current buffer, then read the next line
         self.buf.clear();
 
-        if let Some((offset, span)) = self.src.read_line(&mut self.buf)? {
+        if let Some((offset, span)) = self.src.read_patch(&mut self.buf)? {
             self.offset = offset;
             self.span = span;
         } else {
@@ -54,7 +77,7 @@ impl PatchFeeder {
             return Err(anyhow!(
                 "patch records must not overlap each other (offset = {}, between {})",
                 offset + span,
-                &self.src.format_cache(true)
+                &self.src.format_cache()
             ));
         }
         Ok(span)
@@ -70,17 +93,21 @@ pub struct PatchStream {
 }
 
 impl PatchStream {
-    pub fn new(src: Box<dyn ByteStream>, patch: Box<dyn ByteStream>, format: &InoutFormat) -> Self {
-        let mut patch = PatchFeeder::new(patch, format);
-        patch.fill_buf().unwrap();
+    pub fn new(src: Box<dyn ByteStream>, patch: Box<dyn ByteStream>, format: &InoutFormat) -> Result<Self> {
+        Self::from_source(src, new_record_parser(patch, format))
+    }
 
-        PatchStream {
+    pub fn from_source(src: Box<dyn ByteStream>, patch: Box<dyn PatchSource>) -> Result<Self> {
+        let mut patch = PatchFeeder::new(patch);
+        patch.fill_buf()?;
+
+        Ok(PatchStream {
             src,
             patch,
             buf: StreamBuf::new(),
             skip: 0,
             offset: 0,
-        }
+        })
     }
 }
 
@@ -166,7 +193,7 @@ mod tests {
     fn test_patch_overlap() {
         let input = Box::new(MockSource::new([0u8; 256].as_slice()));
         let patch = Box::new(MockSource::new(b"0000 03 | 01 02 03 \n0001 03 | 01 02 03"));
-        let mut src = PatchStream::new(input, patch, &InoutFormat::from_str("xxx").unwrap());
+        let mut src = PatchStream::new(input, patch, &InoutFormat::from_str("xxx").unwrap()).unwrap();
         assert!(src.fill_buf(1).is_err());
     }
 
@@ -174,7 +201,7 @@ mod tests {
         ( $inner: ident, $input: expr, $patch: expr, $expected: expr ) => {{
             let input = Box::new(MockSource::new($input.as_slice()));
             let patch = Box::new(MockSource::new($patch.as_slice()));
-            let src = PatchStream::new(input, patch, &InoutFormat::from_str("xxx").unwrap());
+            let src = PatchStream::new(input, patch, &InoutFormat::from_str("xxx").unwrap()).unwrap();
             $inner(src, $expected.as_slice());
         }};
     }
@@ -2,15 +2,15 @@
 // @author Hajime Suzuki
 // @date 2022/2/4
 
-use super::ByteStream;
+use super::{ByteStream, PatchSource};
 use crate::params::BLOCK_SIZE;
 use crate::streambuf::StreamBuf;
-use crate::text::parser::TextParser;
+use crate::text::parser::new_record_parser;
 use crate::text::InoutFormat;
 use anyhow::{anyhow, Result};
 
 pub struct GaplessTextStream {
-    inner: TextParser,
+    inner: Box<dyn PatchSource>,
     buf: StreamBuf,
 }
 
@@ -20,7 +20,7 @@ impl GaplessTextStream {
         assert!(format.is_gapless());
 
         GaplessTextStream {
-            inner: TextParser::new(src, format),
+            inner: new_record_parser(src, format),
             buf: StreamBuf::new_with_align(align, filler),
         }
     }
@@ -28,7 +28,7 @@ impl GaplessTextStream {
 
 impl ByteStream for GaplessTextStream {
     fn fill_buf(&mut self, request: usize) -> Result<(bool, usize)> {
-        self.buf.fill_buf(request, |_, buf| Ok(self.inner.read_line(buf)?.is_none()))
+        self.buf.fill_buf(request, |_, buf| Ok(self.inner.read_patch(buf)?.is_none()))
     }
 
     fn as_slice(&self) -> &[u8] {
@@ -41,7 +41,7 @@ impl ByteStream for GaplessTextStream {
 }
 
 struct TextFeeder {
-    src: TextParser,
+    src: Box<dyn PatchSource>,
     offset: usize,
     span: usize,
     buf: Vec<u8>,
@@ -53,7 +53,7 @@ impl TextFeeder {
         assert!(!format.is_gapless());
 
         TextFeeder {
-            src: TextParser::new(src, format),
+            src: new_record_parser(src, format),
             offset: 0,
             span: 0,
             buf: Vec::new(),
@@ -67,7 +67,7 @@ impl TextFeeder {
         // flush the current buffer, then read the next line
         self.buf.clear();
 
-        if let Some((offset, span)) = self.src.read_line(&mut self.buf)? {
+        if let Some((offset, span)) = self.src.read_patch(&mut self.buf)? {
             self.offset = offset;
             self.span = span;
             Ok((false, offset))
@@ -87,12 +87,10 @@ pub struct TextStream {
 
 impl TextStream {
     pub fn new(src: Box<dyn ByteStream>, align: usize, filler: u8, format: &InoutFormat) -> Self {
-        // read the first line
-        let mut line = TextFeeder::new(src, format);
-        line.fill_buf().unwrap();
-
+        // the feeder starts with an empty record at offset 0, so the first line is read (and
+        // its errors are reported) in the first fill_buf
         TextStream {
-            line,
+            line: TextFeeder::new(src, format),
             buf: StreamBuf::new_with_align(align, filler),
             offset: 0,
         }
@@ -130,7 +128,7 @@ impl ByteStream for TextStream {
                 return Err(anyhow!(
                     "hex records must not overlap each other (offset = {}, between {})",
                     self.offset,
-                    &self.line.src.format_cache(true)
+                    &self.line.src.format_cache()
                 ));
             }
             Ok(is_eof)
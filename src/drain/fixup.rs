@@ -0,0 +1,232 @@
+// @file fixup.rs
+// @author Hajime Suzuki
+// @brief checksum fix-up drain
+
+use super::record::{RecordGenerator, RecordSource};
+use crate::byte::{ByteStream, PatchStream};
+use crate::hash::HashAlgorithm;
+use crate::mapper::SegmentMapper;
+use crate::segment::{Segment, SegmentStream};
+use anyhow::{anyhow, Result};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixupParams {
+    algorithm: HashAlgorithm,
+
+    // range the checksum is computed over, and the range it's written to; relative to the slice
+    range: SegmentMapper,
+    target: SegmentMapper,
+    is_little_endian: bool,
+}
+
+impl FixupParams {
+    // "ALG:S..E:P..Q[:le|be]"
+    pub fn from_str(s: &str) -> Result<Self> {
+        let v: Vec<_> = s.split(':').collect();
+        if v.len() != 3 && v.len() != 4 {
+            return Err(anyhow!("failed to parse {s:?} as ALG:S..E:P..Q[:le|be]"));
+        }
+
+        let is_little_endian = match v.get(3).map(|x| x.trim()) {
+            Some("le") => true,
+            Some("be") | None => false,
+            Some(x) => return Err(anyhow!("unknown byte order {x:?} (le or be expected)")),
+        };
+
+        Ok(FixupParams {
+            algorithm: HashAlgorithm::from_str(v[0])?,
+            range: SegmentMapper::from_str(v[1])?,
+            target: SegmentMapper::from_str(v[2])?,
+            is_little_endian,
+        })
+    }
+}
+
+struct Fixup {
+    params: FixupParams,
+    digest: Vec<u8>,
+}
+
+impl Fixup {
+    // the expressions are evaluated on the absolute positions, as in --extend
+    fn map_range(mapper: &SegmentMapper, stream: &[u8], offset: usize, segment: &Segment) -> Result<(usize, usize)> {
+        let s = [(segment.pos + offset) as isize, (segment.tail() + offset) as isize];
+        let (start, end) = mapper.evaluate(&s, &s, &stream[segment.as_range()])?;
+
+        if start > end || start < s[0] || end > s[1] {
+            return Err(anyhow!(
+                "--fixup range {:#x}..{:#x} is out of the slice {:#x}..{:#x}",
+                start,
+                end,
+                s[0],
+                s[1]
+            ));
+        }
+        Ok((start as usize - offset, end as usize - offset))
+    }
+}
+
+impl RecordGenerator for Fixup {
+    fn generate(&mut self, stream: &[u8], offset: usize, segment: &Segment, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        let (start, end) = Self::map_range(&self.params.range, stream, offset, segment)?;
+        let (pos, tail) = Self::map_range(&self.params.target, stream, offset, segment)?;
+
+        self.digest.clear();
+        self.params.algorithm.digest(&stream[start..end], &mut self.digest);
+
+        // the digest is taken as a big-endian integer; truncated to its lower bytes or
+        // zero-extended to fit the target, then reversed for little endian
+        let width = tail - pos;
+        let offset = buf.len();
+        if width > self.digest.len() {
+            buf.resize(offset + width - self.digest.len(), 0);
+            buf.extend_from_slice(&self.digest);
+        } else {
+            buf.extend_from_slice(&self.digest[self.digest.len() - width..]);
+        }
+        if self.params.is_little_endian {
+            buf[offset..].reverse();
+        }
+        Ok(Some((pos, width)))
+    }
+}
+
+pub struct FixupDrain {
+    patch: PatchStream,
+}
+
+impl FixupDrain {
+    pub fn new(src: Box<dyn SegmentStream>, original: Box<dyn ByteStream>, params: &FixupParams) -> Result<Self> {
+        let fixup = Fixup {
+            params: params.clone(),
+            digest: Vec::new(),
+        };
+        let source = RecordSource::new(src, fixup, None);
+
+        Ok(FixupDrain {
+            patch: PatchStream::from_source(original, Box::new(source))?,
+        })
+    }
+}
+
+impl ByteStream for FixupDrain {
+    fn fill_buf(&mut self, request: usize) -> Result<(bool, usize)> {
+        self.patch.fill_buf(request)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        self.patch.as_slice()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.patch.consume(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FixupDrain, FixupParams};
+    use crate::byte::tester::*;
+    use crate::hash::HashAlgorithm;
+    use crate::mapper::SegmentMapper;
+    use crate::params::BLOCK_SIZE;
+    use crate::segment::ConstSlicer;
+
+    #[test]
+    fn test_fixup_params() {
+        macro_rules! test {
+            ( $input: expr, $algorithm: expr, $range: expr, $target: expr, $is_little_endian: expr ) => {{
+                let params = FixupParams::from_str($input).unwrap();
+                assert_eq!(params.algorithm, $algorithm, "{:?}", $input);
+                assert_eq!(params.range, SegmentMapper::from_str($range).unwrap(), "{:?}", $input);
+                assert_eq!(params.target, SegmentMapper::from_str($target).unwrap(), "{:?}", $input);
+                assert_eq!(params.is_little_endian, $is_little_endian, "{:?}", $input);
+            }};
+        }
+
+        test!("crc32:s+4..e-4:e-4..e", HashAlgorithm::Crc32, "s+4..e-4", "e-4..e", false);
+        test!("adler32:s..e-4:e-4..e:be", HashAlgorithm::Adler32, "s..e-4", "e-4..e", false);
+        test!("crc32:s..s+l[0]:e-8..e-4:le", HashAlgorithm::Crc32, "s..s+l[0]", "e-8..e-4", true);
+
+        assert!(FixupParams::from_str("crc32:s..e").is_err());
+        assert!(FixupParams::from_str("crc16:s..e:e..e+2").is_err());
+        assert!(FixupParams::from_str("crc32:s..e:e-4..e:me").is_err());
+        assert!(FixupParams::from_str("crc32:s..e:e-4..e:le:x").is_err());
+    }
+
+    macro_rules! test_impl {
+        ( $inner: ident, $input: expr, $params: expr, $expected: expr ) => {
+            let src = Box::new(MockSource::new($input));
+            let src = Box::new(ConstSlicer::from_raw(src, (0, 0), (false, false), 8, 8));
+            let original = Box::new(MockSource::new($input));
+            let params = FixupParams::from_str($params).unwrap();
+
+            $inner(FixupDrain::new(src, original, &params).unwrap(), $expected);
+        };
+    }
+
+    macro_rules! test {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                // slices spanning several blocks; the range depends on the absolute position
+                let input: Vec<u8> = (0..1600u32).map(|x| if x % 8 < 4 { x as u8 } else { 0 }).collect();
+                let mut expected = input.clone();
+                for (i, chunk) in expected.chunks_mut(8).enumerate() {
+                    let len = 4 - i % 3;
+                    let mut digest = Vec::new();
+                    HashAlgorithm::Crc32.digest(&chunk[..len], &mut digest);
+                    chunk[4..].copy_from_slice(&digest);
+                }
+                test_impl!($inner, &input, "crc32:s..e-4-s/8%3:e-4..e", &expected);
+
+                // little endian, and truncated to the target
+                let mut expected = input.clone();
+                for chunk in expected.chunks_mut(8) {
+                    let mut digest = Vec::new();
+                    HashAlgorithm::Crc32.digest(&chunk[..4], &mut digest);
+                    chunk[4] = digest[3];
+                    chunk[5] = digest[2];
+                }
+                test_impl!($inner, &input, "crc32:s..e-4:e-4..e-2:le", &expected);
+            }
+        };
+    }
+
+    // drains the stream to the end; returns the first error if any
+    fn fixup_all(input: &[u8], params: &str) -> anyhow::Result<Vec<u8>> {
+        let src = Box::new(MockSource::new(input));
+        let src = Box::new(ConstSlicer::from_raw(src, (0, 0), (false, false), 8, 8));
+        let original = Box::new(MockSource::new(input));
+        let params = FixupParams::from_str(params).unwrap();
+
+        let mut drain = FixupDrain::new(src, original, &params)?;
+        let mut v = Vec::new();
+        loop {
+            let (is_eof, bytes) = drain.fill_buf(BLOCK_SIZE)?;
+            v.extend_from_slice(&drain.as_slice()[..bytes]);
+            drain.consume(bytes);
+            if is_eof {
+                return Ok(v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fixup_out_of_range() {
+        let input: Vec<u8> = (0..1600u32).map(|x| x as u8).collect();
+        assert!(fixup_all(&input, "crc32:s..e-4:e-4..e").is_ok());
+
+        // the range and the target out of the slice, from the first slice or a later one
+        assert!(fixup_all(&input[..8], "crc32:s..e+4:e-4..e").is_err());
+        assert!(fixup_all(&input[..8], "crc32:s..e-4:e-4..e+4").is_err());
+        assert!(fixup_all(&input, "crc32:s..e-4:e-4..e+(s==0x400)").is_err());
+        assert!(fixup_all(&input, "crc32:s-(s==0x400)..e-4:e-4..e").is_err());
+    }
+
+    test!(test_fixup_random_len, test_stream_random_len);
+    test!(test_fixup_random_consume, test_stream_random_consume);
+    test!(test_fixup_all_at_once, test_stream_all_at_once);
+}
+
+// end of fixup.rs
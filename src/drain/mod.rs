@@ -1,10 +1,15 @@
 // @file mod.rs
 // @author Hajime Suzuki
 
+mod fixup;
 mod patch;
+mod record;
+mod replace;
 mod scatter;
 
+pub use self::fixup::{FixupDrain, FixupParams};
 pub use self::patch::PatchDrain;
+pub use self::replace::{ReplaceDrain, ReplaceParams};
 pub use self::scatter::ScatterDrain;
 
 // end of mod.rs
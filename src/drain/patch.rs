@@ -59,7 +59,7 @@ pub struct PatchDrain {
 }
 
 impl PatchDrain {
-    pub fn new(patch: Box<dyn SegmentStream>, original: Box<dyn ByteStream>, command: &str, format: &InoutFormat) -> Self {
+    pub fn new(patch: Box<dyn SegmentStream>, original: Box<dyn ByteStream>, command: &str, format: &InoutFormat) -> Result<Self> {
         let mut pipe = BashPipe::new(command);
         let mut writer = pipe.spawn_writer();
         let formatter = TextFormatter::new(format, (0, 0));
@@ -90,14 +90,14 @@ impl PatchDrain {
         });
 
         let reader = pipe.spawn_reader();
-        let patch = PatchStream::new(original, Box::new(reader), format);
+        let patch = PatchStream::new(original, Box::new(reader), format)?;
 
-        PatchDrain {
+        Ok(PatchDrain {
             patch,
             prev_bytes: 0,
             pipe,
             thread: Some(thread),
-        }
+        })
     }
 }
 
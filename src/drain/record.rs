@@ -0,0 +1,102 @@
+// @file record.rs
+// @author Hajime Suzuki
+// @brief patch records generated from slices
+
+use crate::byte::PatchSource;
+use crate::segment::{Segment, SegmentStream};
+use anyhow::Result;
+use std::collections::VecDeque;
+
+// converts a slice into a patch record
+pub trait RecordGenerator: Send {
+    // appends the bytes to `buf` and returns the [pos, pos + span) range to replace, or None
+    // to leave the slice untouched. `stream` is the buffer `segment` points into, and `offset`
+    // is the absolute position of the head of `stream`.
+    fn generate(&mut self, stream: &[u8], offset: usize, segment: &Segment, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>>;
+}
+
+pub struct RecordSource<G: RecordGenerator> {
+    src: Box<dyn SegmentStream>,
+    generator: G,
+
+    // records waiting for the source stream to be consumed beyond their tails
+    records: VecDeque<(usize, usize, Vec<u8>)>,
+
+    // #bytes consumed in the source, and the tail of the last record
+    offset: usize,
+    last_tail: usize,
+
+    count: usize,
+    limit: usize,
+    is_eof: bool,
+}
+
+impl<G: RecordGenerator> RecordSource<G> {
+    pub fn new(src: Box<dyn SegmentStream>, generator: G, limit: Option<usize>) -> Self {
+        RecordSource {
+            src,
+            generator,
+            records: VecDeque::new(),
+            offset: 0,
+            last_tail: 0,
+            count: 0,
+            limit: limit.unwrap_or(usize::MAX),
+            is_eof: false,
+        }
+    }
+
+    fn fill_records(&mut self) -> Result<()> {
+        let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
+        if is_eof && bytes == 0 {
+            self.is_eof = true;
+            return Ok(());
+        }
+
+        // records overlapping the previous one are dropped
+        let (stream, segments) = self.src.as_slices();
+        for s in segments[..count].iter().take_while(|s| s.pos < max_consume) {
+            if self.count >= self.limit {
+                break;
+            }
+
+            let mut buf = Vec::new();
+            let (pos, span) = match self.generator.generate(stream, self.offset, s, &mut buf)? {
+                Some((pos, span)) if self.offset + pos >= self.last_tail => (pos, span),
+                _ => continue,
+            };
+            self.records.push_back((self.offset + pos, span, buf));
+
+            self.last_tail = self.offset + pos + span;
+            self.count += 1;
+        }
+
+        let (bytes, _) = self.src.consume(max_consume)?;
+        self.offset += bytes;
+        Ok(())
+    }
+}
+
+impl<G: RecordGenerator> PatchSource for RecordSource<G> {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        // the original stream is read from the cache of the consumed bytes; a record is released
+        // after the bytes it covers (and one more byte) are consumed and cached
+        loop {
+            if let Some((offset, span, _)) = self.records.front() {
+                if self.is_eof || self.offset > offset + span {
+                    let (offset, span, bytes) = self.records.pop_front().unwrap();
+                    buf.extend_from_slice(&bytes);
+                    return Ok(Some((offset, span)));
+                }
+            } else if self.is_eof {
+                return Ok(None);
+            }
+            self.fill_records()?;
+        }
+    }
+
+    fn format_cache(&self) -> String {
+        format!("records before {:#x}", self.last_tail)
+    }
+}
+
+// end of record.rs
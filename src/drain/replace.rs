@@ -0,0 +1,157 @@
+// @file replace.rs
+// @author Hajime Suzuki
+// @brief search-and-replace drain
+
+use super::record::{RecordGenerator, RecordSource};
+use crate::byte::{ByteStream, PatchStream};
+use crate::segment::{parse_replacement, Segment, SegmentStream};
+use anyhow::{anyhow, Result};
+use regex::bytes::Regex;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplaceParams {
+    // patterns for --find, or a regex for --regex
+    pub from: String,
+    pub is_regex: bool,
+
+    // replacement bytes, or a template with `$1`-style references for the regex
+    to: Vec<u8>,
+}
+
+impl ReplaceParams {
+    pub fn from_str(s: &str) -> Result<Self> {
+        if !s.starts_with('/') {
+            let (from, to) = parse_replacement(s)?;
+            return Ok(ReplaceParams { from, is_regex: false, to });
+        }
+
+        // "/PCRE/:TO"; split at the first "/:" where the regex is valid
+        for (i, _) in s.match_indices("/:").filter(|(i, _)| *i > 0) {
+            if Regex::new(&s[1..i]).is_ok() {
+                return Ok(ReplaceParams {
+                    from: s[1..i].to_string(),
+                    is_regex: true,
+                    to: s.as_bytes()[i + 2..].to_vec(),
+                });
+            }
+        }
+        Err(anyhow!("failed to parse {s:?} as /PCRE/:TO"))
+    }
+}
+
+enum Replacement {
+    Bytes(Vec<u8>),
+    Regex(Regex, Vec<u8>),
+}
+
+impl Replacement {
+    fn expand(&self, stream: &[u8], offset: usize, segment: &Segment, buf: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Replacement::Bytes(bytes) => buf.extend_from_slice(bytes),
+            Replacement::Regex(re, template) => {
+                // the regex is matched again at the head of the slice to get the capture groups.
+                // the whole buffer is passed so that the context around it (`\b`, `^`, etc.) is kept.
+                match re.captures_at(stream, segment.pos) {
+                    Some(caps) if caps.get(0).map(|m| m.range()) == Some(segment.as_range()) => caps.expand(template, buf),
+                    _ => {
+                        return Err(anyhow!(
+                            "slice {:#x}..{:#x} is not a match of the --replace regex (slices reshaped by the manipulators are not supported)",
+                            offset + segment.pos,
+                            offset + segment.tail()
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RecordGenerator for Replacement {
+    fn generate(&mut self, stream: &[u8], offset: usize, segment: &Segment, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.expand(stream, offset, segment, buf)?;
+        Ok(Some((segment.pos, segment.len)))
+    }
+}
+
+pub struct ReplaceDrain {
+    patch: PatchStream,
+}
+
+impl ReplaceDrain {
+    pub fn new(src: Box<dyn SegmentStream>, original: Box<dyn ByteStream>, params: &ReplaceParams, limit: Option<usize>) -> Result<Self> {
+        let replacement = if params.is_regex {
+            Replacement::Regex(Regex::new(&params.from)?, params.to.clone())
+        } else {
+            Replacement::Bytes(params.to.clone())
+        };
+
+        let source = RecordSource::new(src, replacement, limit);
+
+        Ok(ReplaceDrain {
+            patch: PatchStream::from_source(original, Box::new(source))?,
+        })
+    }
+}
+
+impl ByteStream for ReplaceDrain {
+    fn fill_buf(&mut self, request: usize) -> Result<(bool, usize)> {
+        self.patch.fill_buf(request)
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        self.patch.as_slice()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.patch.consume(amount)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplaceParams, Replacement};
+    use crate::segment::Segment;
+    use regex::bytes::Regex;
+
+    #[test]
+    fn test_replace_params() {
+        macro_rules! test {
+            ( $input: expr, $from: expr, $is_regex: expr, $to: expr ) => {{
+                let params = ReplaceParams::from_str($input).unwrap();
+                assert_eq!(params.from, $from, "{:?}", $input);
+                assert_eq!(params.is_regex, $is_regex, "{:?}", $input);
+                assert_eq!(params.to, $to.to_vec(), "{:?}", $input);
+            }};
+        }
+
+        test!("6f:4f 4f", "6f", false, b"OO");
+        test!("\"o\":", "\"o\"", false, b"");
+        test!("/o+/:0", "o+", true, b"0");
+        test!("/(a)/:/:$1", "(a)", true, b"/:$1");
+        test!("/[/:]/:x", "[/:]", true, b"x");
+
+        assert!(ReplaceParams::from_str("6f").is_err());
+        assert!(ReplaceParams::from_str("/(/:x").is_err());
+    }
+
+    #[test]
+    fn test_replace_regex_context() {
+        let replacement = Replacement::Regex(Regex::new(r"\Bo(x?)").unwrap(), b"0$1".to_vec());
+        let expand = |pos: usize, len: usize| -> anyhow::Result<Vec<u8>> {
+            let mut buf = Vec::new();
+            replacement.expand(b"fox o", 0, &Segment { pos, len, tag: 0 }, &mut buf)?;
+            Ok(buf)
+        };
+
+        // the match is evaluated with the bytes around it
+        assert_eq!(expand(1, 2).unwrap(), b"0x");
+
+        // slices not matching the regex at the same position
+        assert!(expand(1, 1).is_err());
+        assert!(expand(0, 3).is_err());
+        assert!(expand(4, 1).is_err());
+    }
+}
+
+// end of replace.rs
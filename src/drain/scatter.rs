@@ -3,11 +3,11 @@
 
 use crate::byte::ByteStream;
 use crate::eval::VarAttr;
-use crate::segment::SegmentStream;
+use crate::segment::{Segment, SegmentStream};
 use crate::streambuf::StreamBuf;
 use crate::template::Template;
-use crate::text::{InoutFormat, TextFormatter};
-use anyhow::Result;
+use crate::text::TextFormatter;
+use anyhow::{anyhow, Result};
 use std::collections::{HashMap, HashSet};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
@@ -31,11 +31,27 @@ impl Drain {
         }
 
         let file = template.render(|_, _| 0)?;
-        let file = OpenOptions::new().read(false).write(true).create(true).open(file)?;
+        let file = OpenOptions::new().read(false).write(true).create(true).truncate(true).open(file)?;
         Ok(Drain::File(file))
     }
 }
 
+fn format_segments(formatter: &TextFormatter, src: &dyn SegmentStream, offset: usize, segments: &[Segment], buf: &mut Vec<u8>) {
+    let (stream, _) = src.as_slices();
+    if segments.iter().all(|s| s.tag == 0) {
+        formatter.format_segments(offset, stream, segments, buf);
+        return;
+    }
+
+    // tagged segments are formatted one by one to append the labels
+    for s in segments.windows(1) {
+        formatter.format_segments(offset, stream, s, buf);
+        if let Some(label) = src.label(s[0].tag) {
+            formatter.format_label(label, buf);
+        }
+    }
+}
+
 struct ScatterContext {
     drain: Drain,
     files: HashSet<String>,
@@ -59,8 +75,8 @@ impl ScatterContext {
                     _ => 0,
                 })?;
 
-                let mut file = if self.files.get(&file).is_some() {
-                    OpenOptions::new().read(false).write(true).append(true).open(file)?
+                let mut file = if self.files.contains(&file) {
+                    OpenOptions::new().read(false).append(true).open(file)?
                 } else {
                     self.files.insert(file.to_string());
                     OpenOptions::new().read(false).write(true).create(true).truncate(true).open(file)?
@@ -70,6 +86,23 @@ impl ScatterContext {
         }
         Ok(())
     }
+
+    fn is_template(&self) -> bool {
+        matches!(self.drain, Drain::Template(_))
+    }
+
+    // appends the end-of-file record to every file written so far
+    fn dump_trailer(&mut self, buf: &[u8]) -> Result<()> {
+        match &mut self.drain {
+            Drain::File(file) => file.write_all(buf)?,
+            Drain::Template(_) => {
+                for file in &self.files {
+                    OpenOptions::new().read(false).append(true).open(file)?.write_all(buf)?;
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 pub struct ScatterDrain {
@@ -92,15 +125,16 @@ pub struct ScatterDrain {
 }
 
 impl ScatterDrain {
-    pub fn new(src: Box<dyn SegmentStream>, file: &str, format: &InoutFormat) -> Result<Self> {
-        let formatter = TextFormatter::new(format, (0, 0));
-
+    pub fn new(src: Box<dyn SegmentStream>, file: &str, formatter: TextFormatter) -> Result<Self> {
         // when "-" or nothing specified, we treat it as stdout
         let file = if file.is_empty() || file == "-" {
             None
         } else {
             Some(ScatterContext::new(file)?)
         };
+        if formatter.is_whole_array() && file.as_ref().is_some_and(|file| file.is_template()) {
+            return Err(anyhow!("--array-name must have a variable (e.g. {{n}}) when --output has one."));
+        }
 
         Ok(ScatterDrain {
             src,
@@ -121,11 +155,12 @@ impl ScatterDrain {
                 debug_assert!(bytes == max_consume);
             }
 
-            let (stream, segments) = self.src.as_slices();
-
-            self.formatter
-                .format_segments(self.offset, stream, &segments[self.src_consumed..count], buf);
+            let (_, segments) = self.src.as_slices();
+            format_segments(&self.formatter, &self.src, self.offset, &segments[self.src_consumed..count], buf);
             self.src_consumed += count;
+            if is_eof {
+                self.formatter.format_trailer(buf);
+            }
 
             // consumed bytes and count
             let (bytes, count) = self.src.consume(max_consume)?;
@@ -145,13 +180,23 @@ impl ScatterDrain {
         loop {
             let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
             if is_eof && bytes == 0 {
+                self.buf.clear();
+                self.formatter.format_trailer(&mut self.buf);
+                if !self.buf.is_empty() {
+                    self.file.as_mut().unwrap().dump_trailer(&self.buf)?;
+                }
                 return Ok((true, 0));
             }
 
-            let (stream, segments) = self.src.as_slices();
+            let is_template = self.file.as_ref().unwrap().is_template();
+            let (_, segments) = self.src.as_slices();
             for (i, s) in segments[self.src_consumed..count].windows(1).enumerate() {
                 self.buf.clear();
-                self.formatter.format_segments(self.offset, stream, s, &mut self.buf);
+                if is_template {
+                    // each file has to be valid on its own
+                    self.formatter.reset();
+                }
+                format_segments(&self.formatter, &self.src, self.offset, s, &mut self.buf);
                 self.file
                     .as_mut()
                     .unwrap()
@@ -192,13 +237,14 @@ mod tests {
     use super::ScatterDrain;
     use crate::byte::tester::*;
     use crate::segment::ConstSlicer;
-    use crate::text::InoutFormat;
+    use crate::text::{InoutFormat, TextFormatter};
 
     macro_rules! test_impl {
         ( $inner: ident, $pattern: expr, $drain: expr, $expected: expr ) => {
             let src = Box::new(MockSource::new($pattern));
             let src = Box::new(ConstSlicer::from_raw(src, (0, -3), (false, false), 4, 6));
-            let src = ScatterDrain::new(src, $drain, &InoutFormat::from_str("b").unwrap()).unwrap();
+            let formatter = TextFormatter::new(&InoutFormat::from_str("b").unwrap(), (0, 0));
+            let src = ScatterDrain::new(src, $drain, formatter).unwrap();
 
             $inner(src, $expected);
         };
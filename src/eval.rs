@@ -45,6 +45,10 @@ fn is_cmp(c: char) -> bool {
     c == 'g' || c == 'G' || c == 'l' || c == 'L' // >, >=, <, <=
 }
 
+fn is_eq(c: char) -> bool {
+    c == 'e' || c == 'n' // ==, !=
+}
+
 fn is_pow(c: char) -> bool {
     c == '@'
 }
@@ -61,8 +65,16 @@ fn latter_precedes(former: &Token, latter: &Token) -> bool {
                 return !is_shift(former);
             } else if is_pow(former) || is_pow(latter) {
                 return is_pow(latter);
+            } else if is_cmp(former) || is_cmp(latter) {
+                return !is_cmp(former);
+            } else if is_eq(former) || is_eq(latter) {
+                return !is_eq(former);
+            } else if former == '&' || latter == '&' {
+                return former != '&';
+            } else if former == '^' || latter == '^' {
+                return former != '^';
             }
-            debug_assert!(is_cmp(former) && is_cmp(latter));
+            debug_assert!(former == '|' && latter == '|');
             false
         }
         _ => true,
@@ -111,6 +123,22 @@ fn test_precedence() {
     assert!(latter_precedes(&Op('g'), &Op('@')));
     assert!(!latter_precedes(&Op('g'), &Op('g')));
 
+    assert!(!latter_precedes(&Op('g'), &Op('&')));
+    assert!(latter_precedes(&Op('&'), &Op('g')));
+
+    assert!(!latter_precedes(&Op('g'), &Op('e')));
+    assert!(latter_precedes(&Op('e'), &Op('g')));
+    assert!(latter_precedes(&Op('n'), &Op('+')));
+    assert!(!latter_precedes(&Op('e'), &Op('n')));
+
+    assert!(!latter_precedes(&Op('e'), &Op('&')));
+    assert!(latter_precedes(&Op('&'), &Op('e')));
+    assert!(!latter_precedes(&Op('&'), &Op('^')));
+    assert!(latter_precedes(&Op('^'), &Op('&')));
+    assert!(!latter_precedes(&Op('^'), &Op('|')));
+    assert!(latter_precedes(&Op('|'), &Op('^')));
+    assert!(!latter_precedes(&Op('|'), &Op('|')));
+
     assert!(!latter_precedes(&Prefix('+'), &Op('*')));
     assert!(!latter_precedes(&Prefix('+'), &Op('+')));
     assert!(!latter_precedes(&Prefix('+'), &Op('<')));
@@ -136,6 +164,14 @@ where
         return Some(Op(first));
     }
 
+    // "==" or "!="; a single '=' is not an operator
+    if first == '=' || (first == '!' && it.peek() == Some(&'=')) {
+        if it.next()? != '=' {
+            return None;
+        }
+        return Some(Op(if first == '=' { 'e' } else { 'n' }));
+    }
+
     // temporarily disabled
     // // "**"
     // if first == '*' && *it.peek()? == '*' {
@@ -233,12 +269,12 @@ where
     if (skip_mask & ((skip_mask << 1) | 1)) != 0 {
         return None;
     }
-    let mut val = 0;
+    let mut val: i64 = 0;
     for &digit in &digits[..i] {
         if digit as i64 >= num_base {
             return None;
         }
-        val = val * num_base + digit as i64;
+        val = val.checked_mul(num_base)?.checked_add(digit as i64)?;
     }
 
     let scaler = match it.peek() {
@@ -250,7 +286,7 @@ where
         _ => 1,
     };
 
-    Some(Val(val * scaler))
+    Some(Val(val.checked_mul(scaler)?))
 }
 
 fn parse_var<I>(first: char, vars: Option<&HashMap<&[u8], VarAttr>>, it: &mut Peekable<I>) -> Option<Token>
@@ -284,7 +320,7 @@ fn tokenize(input: &str, vars: Option<&HashMap<&[u8], VarAttr>>) -> Result<Vec<T
             '(' | ')' | '[' | ']' => {
                 tokens.push(Paren(x));
             }
-            '+' | '-' | '~' | '!' | '*' | '/' | '%' | '&' | '|' | '^' | '<' | '>' | '@' => {
+            '+' | '-' | '~' | '!' | '*' | '/' | '%' | '&' | '|' | '^' | '<' | '>' | '=' | '@' => {
                 tokens.push(parse_op(x, &mut it).with_context(|| format!("parsing failed at an operator in {input:?}"))?);
             }
             '0'..='9' => {
@@ -340,6 +376,8 @@ fn expand_and_push_op(op: &Token, lhs: usize, rpn: &mut Vec<(Token, usize)>) {
         Op('G') => rpn.extend_from_slice(&[(Op('-'), lhs), (Prefix('G'), 1)]),
         Op('l') => rpn.extend_from_slice(&[(Op('~'), lhs), (Val(-1), 0), (Op('+'), 2), (Prefix('G'), 1)]),
         Op('L') => rpn.extend_from_slice(&[(Op('~'), lhs), (Prefix('G'), 1)]),
+        Op('e') => rpn.extend_from_slice(&[(Op('-'), lhs), (Prefix('E'), 1)]),
+        Op('n') => rpn.extend_from_slice(&[(Op('-'), lhs), (Prefix('N'), 1)]),
         _ => rpn.push((*op, lhs)),
     }
 }
@@ -445,6 +483,8 @@ fn apply_prefix(c: char, x: i64) -> i64 {
         '-' => -x,
         '!' => !x,
         'G' => (x >= 0) as i64,
+        'E' => (x == 0) as i64,
+        'N' => (x != 0) as i64,
         _ => panic!("unknown op: {c:?}"),
     }
 }
@@ -855,7 +895,7 @@ fn remove_prefix_unary(tokens: &mut [(Token, usize)]) -> Option<usize> {
 
     match (tokens[lhs], tokens[root].0) {
         ((Prefix(s1), llhs), Prefix(s2)) => {
-            if s1 == s2 {
+            if s1 == s2 && is_unary(s1) {
                 // x ! ! => x
                 remove_prefix_unary(&mut tokens[..lhs - llhs + 1])
             } else if is_addsub(s1) && is_addsub(s2) {
@@ -911,14 +951,14 @@ fn remove_prefix_binary(tokens: &mut [(Token, usize)]) -> Option<usize> {
     match (tokens[lhs], tokens[root - 1].0, tokens[root].0) {
         (x, y, Op(op)) if is_addsub(op) => {
             let (s1, lhs, root) = match x {
-                (Prefix(s1), llhs) => {
+                (Prefix(s1 @ ('+' | '-')), llhs) => {
                     tokens[lhs - llhs + 1..].rotate_left(llhs);
                     (s1, lhs - llhs, root - llhs)
                 }
                 _ => ('+', lhs, root),
             };
             let (s2, root) = match y {
-                Prefix(s2) => (s2, root - 1),
+                Prefix(s2 @ ('+' | '-')) => (s2, root - 1),
                 _ => ('+', root),
             };
             save_and_flip(tokens, root, Op(gather_sign2(s1, op, s2)), root - lhs);
@@ -1136,6 +1176,15 @@ fn test_simplify_rpn() {
     test!("-(y - x) < -4", "G(((-1 * x + y) + -5))");
     test!("x <= -x + (x + x) - (-x)", "G(x)");
 
+    test!("4 == 4", "1");
+    test!("4 != 4", "0");
+    test!("x == 0", "E(x)");
+    test!("x != y", "N((x + -1 * y))");
+    test!("x + 1 == y - 1", "E(((x + -1 * y) + 2))");
+    test!("x >= 0 == y >= 0", "E((G(x) - G(y)))");
+    test!("!x - y == 0", "E((-1 * y + !(x)))");
+    test!("(x == 0) == 0", "E(E(x))");
+
     test!("a[0]", "a[0]");
     test!("a[1]", "a[1]");
     test!("2 * a[1]", "2 * a[1]");
@@ -1356,6 +1405,9 @@ fn test_parse_int() {
     assert!(parse_int("0d123a").is_err());
     assert_eq!(parse_int("0xabcdef").unwrap(), 0xabcdef);
     assert_eq!(parse_int("0xFEDCBA").unwrap(), 0xFEDCBA);
+    assert_eq!(parse_int("0x7fffffffffffffff").unwrap(), i64::MAX);
+    assert!(parse_int("0x8000000000000000").is_err());
+    assert!(parse_int("16Ei").is_err());
 
     assert_eq!(parse_int("1k").unwrap(), 1000);
     assert_eq!(parse_int("1K").unwrap(), 1000);
@@ -1421,6 +1473,24 @@ fn test_parse_int() {
     assert_eq!(parse_int("-12@2").unwrap(), -144);
     assert_eq!(parse_int("(-12)@2").unwrap(), 144);
 
+    assert_eq!(parse_int("0 & 1 < 2").unwrap(), 0);
+    assert_eq!(parse_int("3 | 4 & 1").unwrap(), 3);
+    assert_eq!(parse_int("(3 | 4) & 1").unwrap(), 1);
+
+    assert_eq!(parse_int("3 == 3").unwrap(), 1);
+    assert_eq!(parse_int("3 == 4").unwrap(), 0);
+    assert_eq!(parse_int("3 != 4").unwrap(), 1);
+    assert_eq!(parse_int("1 + 2 == 3").unwrap(), 1);
+    assert_eq!(parse_int("1 < 2 == 2 < 3").unwrap(), 1);
+    assert_eq!(parse_int("1 < 2 != 3 < 2").unwrap(), 1);
+    assert_eq!(parse_int("6 & 3 == 3").unwrap(), 0);
+    assert_eq!(parse_int("(6 & 3) == 2").unwrap(), 1);
+    assert_eq!(parse_int("1 | 6 & 3").unwrap(), 3);
+    assert_eq!(parse_int("1 ^ 3 & 6").unwrap(), 3);
+    assert_eq!(parse_int("!0 != 0").unwrap(), 1);
+    assert!(parse_int("3 = 3").is_err());
+    assert!(parse_int("3 === 3").is_err());
+
     assert!(parse_int("4 : 3").is_err());
     assert!(parse_int("4 + 3;").is_err());
     assert!(parse_int("4 - `3").is_err());
@@ -0,0 +1,129 @@
+// @file hash.rs
+// @author Hajime Suzuki
+// @brief checksum and hash functions for per-slice digests
+
+use anyhow::{anyhow, Result};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Crc32,
+    Crc32c,
+    Adler32,
+    Md5,
+    Sha1,
+    Sha256,
+    Xxh32,
+    Xxh64,
+}
+
+impl HashAlgorithm {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "crc32" => Ok(HashAlgorithm::Crc32),
+            "crc32c" => Ok(HashAlgorithm::Crc32c),
+            "adler32" => Ok(HashAlgorithm::Adler32),
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "xxh32" => Ok(HashAlgorithm::Xxh32),
+            "xxh64" => Ok(HashAlgorithm::Xxh64),
+            x => Err(anyhow!(
+                "unknown hash algorithm {x:?} (crc32, crc32c, adler32, md5, sha1, sha256, xxh32, or xxh64 expected)"
+            )),
+        }
+    }
+
+    pub fn from_str_list(s: &str) -> Result<Vec<Self>> {
+        s.split(',').map(Self::from_str).collect()
+    }
+
+    // appends the digest of `data` to `out`; integer checksums are stored in big endian, as they are printed
+    pub fn digest(&self, data: &[u8], out: &mut Vec<u8>) {
+        match self {
+            HashAlgorithm::Crc32 => out.extend_from_slice(&crc32fast::hash(data).to_be_bytes()),
+            HashAlgorithm::Crc32c => out.extend_from_slice(&crc32c::crc32c(data).to_be_bytes()),
+            HashAlgorithm::Adler32 => out.extend_from_slice(&adler::adler32_slice(data).to_be_bytes()),
+            HashAlgorithm::Md5 => out.extend_from_slice(&Md5::digest(data)),
+            HashAlgorithm::Sha1 => out.extend_from_slice(&Sha1::digest(data)),
+            HashAlgorithm::Sha256 => out.extend_from_slice(&Sha256::digest(data)),
+            HashAlgorithm::Xxh32 => out.extend_from_slice(&xxhash_rust::xxh32::xxh32(data, 0).to_be_bytes()),
+            HashAlgorithm::Xxh64 => out.extend_from_slice(&xxhash_rust::xxh64::xxh64(data, 0).to_be_bytes()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashAlgorithm;
+
+    #[test]
+    fn test_hash() {
+        macro_rules! test {
+            ( $algorithm: expr, $input: expr, $expected: expr ) => {{
+                let mut out = Vec::new();
+                $algorithm.digest($input, &mut out);
+                let out: String = out.iter().map(|x| format!("{:02x}", x)).collect();
+                assert_eq!(out, $expected, "{:?}, {:?}", $algorithm, $input);
+            }};
+        }
+
+        let long: Vec<u8> = (0..1000u32).map(|x| (x * 7 + 3) as u8).collect();
+
+        test!(HashAlgorithm::Crc32, b"", "00000000");
+        test!(HashAlgorithm::Crc32, b"123456789", "cbf43926");
+        test!(HashAlgorithm::Crc32c, b"123456789", "e3069283");
+        test!(HashAlgorithm::Adler32, b"", "00000001");
+        test!(HashAlgorithm::Adler32, b"Wikipedia", "11e60398");
+
+        test!(HashAlgorithm::Md5, b"", "d41d8cd98f00b204e9800998ecf8427e");
+        test!(HashAlgorithm::Md5, b"abc", "900150983cd24fb0d6963f7d28e17f72");
+        test!(HashAlgorithm::Sha1, b"", "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        test!(HashAlgorithm::Sha1, b"abc", "a9993e364706816aba3e25717850c26c9cd0d89d");
+        test!(
+            HashAlgorithm::Sha256,
+            b"",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        test!(
+            HashAlgorithm::Sha256,
+            b"abc",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        test!(HashAlgorithm::Xxh32, b"", "02cc5d05");
+        test!(HashAlgorithm::Xxh32, b"abc", "32d153ff");
+        test!(HashAlgorithm::Xxh64, b"", "ef46db3751d8e999");
+        test!(HashAlgorithm::Xxh64, b"abc", "44bc2cf5ad770999");
+
+        // multiple blocks and stripes
+        test!(HashAlgorithm::Crc32, &long, "17bc2a46");
+        test!(HashAlgorithm::Crc32c, &long, "dd2edff7");
+        test!(HashAlgorithm::Adler32, &long, "38adedfc");
+        test!(HashAlgorithm::Md5, &long, "10046f077f2082ac19676b8079f1cb1a");
+        test!(HashAlgorithm::Sha1, &long, "4231a8a50a10fa9758db8ec71fdef855b751048a");
+        test!(
+            HashAlgorithm::Sha256,
+            &long,
+            "1e9bc38cbf860b9ec31918b065f9b52476c549a782e0e7990bed8ce3868d2371"
+        );
+
+        let spam = b"Nobody inspects the spammish repetition";
+        test!(HashAlgorithm::Xxh32, spam, "e2293b2f");
+        test!(HashAlgorithm::Xxh64, spam, "fbcea83c8a378bf1");
+    }
+
+    #[test]
+    fn test_hash_parse() {
+        assert_eq!(
+            HashAlgorithm::from_str_list("crc32,sha256").unwrap(),
+            vec![HashAlgorithm::Crc32, HashAlgorithm::Sha256]
+        );
+        assert!(HashAlgorithm::from_str_list("crc32,sha3").is_err());
+        assert!(HashAlgorithm::from_str_list("").is_err());
+    }
+}
+
+// end of hash.rs
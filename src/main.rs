@@ -2,10 +2,12 @@ mod byte;
 mod drain;
 mod eval;
 mod filluninit;
+mod hash;
 mod mapper;
 mod params;
 mod pipeline;
 mod segment;
+mod stats;
 mod streambuf;
 mod template;
 mod text;
@@ -35,6 +37,9 @@ OPTIONS:
 
     -F, --in-format FMT     input format signature (applies to all inputs) [b]
     -f, --out-format FMT    output format signature (applies to --output) [x]
+        --array-name NAME   name of c, rust, or python arrays (one per slice if it has {n} or {l}) [slice_{n:x}]
+        --mosaic MODE       character set of the mosaic (ascii, latin1, cp437, ebcdic, utf8, or pictures) [ascii]
+        --color[=WHEN]      color bytes, offsets, and matches of --find/--regex (auto, always, or never) [never]
 
   Constructing input stream (exclusive)
 
@@ -47,35 +52,61 @@ OPTIONS:
     -n, --cut S..E[,...]    leave only bytes within the S..E range(s)
     -a, --pad N,M           add N and M bytes of zeros at the head and tail
     -p, --patch FILE        patch the input stream with the patchfile
+        --patch-format FMT  format signature of the patchfile [x]
 
   Slicing the stream (exclusive)
 
     -w, --width N[,S..E]    slice into N bytes and map them to S..E (default) [16,s..e]
-    -d, --find ARRAY        slice out every ARRAY location
+    -d, --find ARRAY[,...]  slice out every ARRAY location (NAME=ARRAY to label the matches)
+        --distance K[,M]    let --find match within distance K under metric M (byte, bit, or edit) [-,byte]
     -k, --walk EXPR[,...]   split the stream into eval(EXPR)-byte chunk(s), repeat it until the end
     -r, --slice S..E[,...]  slice out S..E range(s)
     -g, --guide FILE        slice out [offset, offset + length) ranges loaded from the file
+        --guide-format FMT  format signature of the guide file [x]
+        --guide-sort        sort all the guide records before slicing, even if the first 64 MiB of them is sorted
+        --regions W[,H[,F]] slice into fill runs (>= F bytes) and low / high entropy regions (< / >= H bits per
+                            byte in W-byte windows) [-,7.0,W]
+        --strings N         slice out runs of N or more printable characters, like strings(1)
+        --strings-encoding ENC
+                            encoding of the --strings runs (ascii, utf8, utf16le, or utf16be) [ascii]
+        --strings-nul       leave only NUL-terminated --strings runs
+        --cdc AVG|MIN,AVG,MAX
+                            split the stream into content-defined chunks of MIN..MAX bytes (FastCDC) [-,AVG/4,AVG*8]
+        --split ARRAY[,...] split the stream into records terminated by ARRAY(s)
+        --split-keep        leave the delimiters at the tails of the records
+        --split-escape BYTE don't split at delimiters escaped by BYTE
 
   Manipulating the slices (applied in this order)
 
     -e, --regex PCRE        match PCRE on every slice and leave the match locations
+        --regex-window B,A  let --regex see B bytes before and A bytes after every slice [0,0]
+        --regex-group G     leave capture group(s) G[,...] (index or name) instead of the matches
     -v, --invert S..E[,...] invert slices and map them to S..E range(s)
     -x, --extend S..E[,...] map every slice to S..E range(s)
     -m, --merge N           iteratively merge slices where distance <= N
+    -W, --where EXPR        leave only slices where eval(EXPR) is non-zero
     -l, --lines S..E[,...]  leave only slices (lines) in the S..E range(s)
 
   Post-processing the slices (exclusive)
 
     -o, --output TEMPLATE   render filename from TEMPLATE for each slice, and dump formatted slices to the files
                             (\"-\" for stdout; default) [-]
+        --stats             replace the array and the mosaic with entropy, min, max, #zeros, and printable ratio
+        --hash ALG[,...]    append digest column(s) of every slice after the mosaic (crc32, crc32c, adler32, md5,
+                            sha1, sha256, xxh32, or xxh64)
     -P, --patch-back CMD    pipe formatted slices to CMD, then feed its output onto the cached stream as patches
+    -R, --replace FROM:TO   replace every FROM (ARRAY, or /PCRE/ with $1 references in TO) with TO in the stream
+                            (/PCRE/ matches longer than 4096 bytes may be split)
+        --replace-limit N   replace only the first N occurrences of FROM [-]
+        --fixup ALG:S..E:P..Q[:le|be]
+                            recompute checksum ALG over S..E of every output slice and write it to P..Q [be]
 
   Miscellaneous
 
     -h, --help              print help (this) message
     -V, --version           print version information
         --filler N          use N (0 <= N < 256) for padding
-        --pager PAGER       feed the stream to PAGER (ignored in the --inplace mode) [less -S -F -X]
+        --pager PAGER       feed the stream to PAGER (ignored in the --inplace mode) [less -S -F -R -X]
 ";
 
 #[derive(Debug, Parser)]
@@ -208,7 +239,7 @@ fn build_drain(pager: &Option<String>) -> Result<(Option<Child>, Box<dyn Write>)
         return Ok((None, Box::new(std::io::stdout())));
     }
 
-    let pager = pager.unwrap_or_else(|| "less -S -F".to_string());
+    let pager = pager.unwrap_or_else(|| "less -S -F -R".to_string());
     let args: Vec<_> = pager.as_str().split_whitespace().collect();
     let mut child = std::process::Command::new(args[0]).args(&args[1..]).stdin(Stdio::piped()).spawn()?;
 
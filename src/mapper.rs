@@ -2,16 +2,66 @@
 // @brief slice mapper
 
 use self::RangeMapperAnchor::*;
+use self::SegmentMapperAnchor::*;
 use crate::eval::Token::*;
 use crate::eval::{Rpn, VarAttr};
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
 use std::ops::Range;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-struct SegmentMapperAnchor {
-    anchor: usize,
-    offset: isize,
+pub fn get_array_element(slice: &[u8], elem_size: usize, index: i64) -> Result<i64> {
+    debug_assert!((1..=8).contains(&elem_size) && elem_size.is_power_of_two());
+
+    if index < 0 {
+        return Err(anyhow!("slice index being negative (got: {index})."));
+    }
+
+    // elements not entirely contained in the slice are evaluated as zero
+    let offset = (index as usize).saturating_mul(elem_size);
+    if offset.saturating_add(elem_size) > slice.len() {
+        return Ok(0);
+    }
+
+    // always in the little endian, as in the --walk expressions
+    let mut buf = [0u8; 8];
+    buf[..elem_size].copy_from_slice(&slice[offset..offset + elem_size]);
+
+    let val = i64::from_le_bytes(buf);
+    let shift = 64 - 8 * elem_size;
+    Ok((val << shift) >> shift)
+}
+
+// evaluates `rpn` with the array variables over `slice`; `get` supplies the scalar variables
+pub fn evaluate_on_slice<F>(rpn: &Rpn, slice: &[u8], mut get: F) -> Result<i64>
+where
+    F: FnMut(usize) -> Option<i64>,
+{
+    // the first error of the array accesses, reported after the evaluation
+    let mut err = None;
+    let getter = |id: usize, index: i64| -> i64 {
+        if let Some(val) = get(id) {
+            return val;
+        }
+        get_array_element(slice, id, index).unwrap_or_else(|e| {
+            err.get_or_insert(e);
+            0
+        })
+    };
+
+    let val = rpn.evaluate(getter)?;
+    match err {
+        Some(e) => Err(e),
+        None => Ok(val),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SegmentMapperAnchor {
+    // "s + offset" (anchor == 0) or "e + offset" (anchor == 1)
+    Anchored { anchor: usize, offset: isize },
+
+    // arbitrary expression over "s", "e", and the slice content
+    Expr { rpn: Rpn, expr: String },
 }
 
 impl SegmentMapperAnchor {
@@ -24,35 +74,62 @@ impl SegmentMapperAnchor {
             _ => return Err(anyhow!("unrecognized default anchor {:?} (internal error)", const_default)),
         };
 
-        // "s" for the start of a slice, and "e" for the end
+        // "s" for the start of a slice, and "e" for the end. array views over the slice
+        // are "b", "h", "i", and "l", whose ids are the element sizes.
         let vars = [
-            (b"s", VarAttr { is_array: false, id: 0 }),
-            (b"e", VarAttr { is_array: false, id: 1 }),
+            (b"s".as_slice(), VarAttr { is_array: false, id: 16 }),
+            (b"e".as_slice(), VarAttr { is_array: false, id: 17 }),
+            (b"b".as_slice(), VarAttr { is_array: true, id: 1 }),
+            (b"h".as_slice(), VarAttr { is_array: true, id: 2 }),
+            (b"i".as_slice(), VarAttr { is_array: true, id: 4 }),
+            (b"l".as_slice(), VarAttr { is_array: true, id: 8 }),
         ];
-        let vars: HashMap<&[u8], VarAttr> = vars.iter().map(|(x, y)| (x.as_slice(), *y)).collect();
+        let vars: HashMap<&[u8], VarAttr> = vars.into_iter().collect();
 
-        // parse the expression into a RPN, and extract coefficient
+        // parse the expression into a RPN, and extract coefficient if it's in the simplest form
         let rpn = Rpn::new(expr, Some(&vars))?;
-        let (anchor, offset) = match rpn.tokens().as_slice() {
-            [Val(c)] => (const_default, *c as isize),
-            [Var(id, 1)] => (*id, 0),
-            [Var(id, 1), Val(c), Op('+')] => (*id, *c as isize),
-            _ => {
-                return Err(anyhow!(
-                    "slice-mapping expression (S..E) must be relative to input slice boundaries."
-                ))
-            }
+        let anchor = match rpn.tokens().as_slice() {
+            [Val(c)] => Anchored {
+                anchor: const_default,
+                offset: *c as isize,
+            },
+            [Var(id @ (16 | 17), 1)] => Anchored {
+                anchor: *id - 16,
+                offset: 0,
+            },
+            [Var(id @ (16 | 17), 1), Val(c), Op('+')] => Anchored {
+                anchor: *id - 16,
+                offset: *c as isize,
+            },
+            _ => Expr {
+                rpn,
+                expr: expr.to_string(),
+            },
         };
 
-        Ok(SegmentMapperAnchor { anchor, offset })
+        Ok(anchor)
     }
 
-    pub fn evaluate(&self, input: &[isize; 2]) -> isize {
-        input[self.anchor] + self.offset
+    fn evaluate(&self, input: &[isize; 2], slice: &[u8]) -> Result<isize> {
+        match self {
+            Anchored { anchor, offset } => Ok(input[*anchor] + offset),
+            Expr { rpn, expr } => {
+                let getter = |id: usize| -> Option<i64> {
+                    match id {
+                        16 => Some(input[0] as i64),
+                        17 => Some(input[1] as i64),
+                        _ => None,
+                    }
+                };
+                let val = evaluate_on_slice(rpn, slice, getter)
+                    .map_err(|e| anyhow!("failed on evaluating slice-mapping expression: {:?}: {}", expr, e))?;
+                Ok(val as isize)
+            }
+        }
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SegmentMapper {
     start: SegmentMapperAnchor,
     end: SegmentMapperAnchor,
@@ -73,11 +150,19 @@ impl SegmentMapper {
             return Err(anyhow!("slice-mapping expression must be in the S..E form: {:?}", expr));
         }
 
-        Ok(SegmentMapper { start: v[0], end: v[1] })
+        let end = v.pop().unwrap();
+        let start = v.pop().unwrap();
+        Ok(SegmentMapper { start, end })
+    }
+
+    // true if both ends are in the "s + k" or "e + k" form
+    pub fn is_anchored(&self) -> bool {
+        matches!((&self.start, &self.end), (Anchored { .. }, Anchored { .. }))
     }
 
-    pub fn evaluate(&self, start: &[isize; 2], end: &[isize; 2]) -> (isize, isize) {
-        (self.start.evaluate(start), self.end.evaluate(end))
+    // `slice` is the content of the input slice, used for evaluating the array variables
+    pub fn evaluate(&self, start: &[isize; 2], end: &[isize; 2], slice: &[u8]) -> Result<(isize, isize)> {
+        Ok((self.start.evaluate(start, slice)?, self.end.evaluate(end, slice)?))
     }
 }
 
@@ -87,11 +172,11 @@ fn test_segment_mapper_from_str() {
         ( $input: expr, $expected: expr ) => {
             let mapper = SegmentMapper::from_str($input).unwrap();
             let expected = SegmentMapper {
-                start: SegmentMapperAnchor {
+                start: Anchored {
                     anchor: $expected.0,
                     offset: $expected.1,
                 },
-                end: SegmentMapperAnchor {
+                end: Anchored {
                     anchor: $expected.2,
                     offset: $expected.3,
                 },
@@ -108,7 +193,14 @@ fn test_segment_mapper_from_str() {
     assert!(SegmentMapper::from_str("0...1").is_err());
 
     assert!(SegmentMapper::from_str("x..y").is_err());
-    assert!(SegmentMapper::from_str("s * 2..e").is_err());
+    assert!(SegmentMapper::from_str("b..e").is_err());
+    assert!(SegmentMapper::from_str("s..e[0]").is_err());
+
+    // arbitrary expressions
+    assert!(!SegmentMapper::from_str("s * 2..e").unwrap().is_anchored());
+    assert!(!SegmentMapper::from_str("s + (e - s) / 2..e").unwrap().is_anchored());
+    assert!(!SegmentMapper::from_str("s..s + 4 + i[0]").unwrap().is_anchored());
+    assert!(SegmentMapper::from_str("2 * s - s..e").unwrap().is_anchored());
 
     // implicit anchors
     test!("..", (0, 0, 1, 0));
@@ -138,7 +230,7 @@ fn test_segment_mapper_evaluate() {
     macro_rules! test {
         ( $input: expr, $slices: expr, $expected: expr ) => {
             let mapper = SegmentMapper::from_str($input).unwrap();
-            let (start, end) = mapper.evaluate(&$slices.0, &$slices.1);
+            let (start, end) = mapper.evaluate(&$slices.0, &$slices.1, &[]).unwrap();
 
             assert_eq!(start, $expected.0);
             assert_eq!(end, $expected.1);
@@ -159,6 +251,33 @@ fn test_segment_mapper_evaluate() {
     test!("3+e..e", ([10, 20], [30, 40]), (23, 40));
     test!("e..e+5", ([10, 20], [30, 40]), (20, 45));
     test!("3+e..e+5", ([10, 20], [30, 40]), (23, 45));
+
+    macro_rules! test_content {
+        ( $input: expr, $segment: expr, $slice: expr, $expected: expr ) => {
+            let mapper = SegmentMapper::from_str($input).unwrap();
+            let (start, end) = mapper.evaluate(&$segment, &$segment, $slice.as_slice()).unwrap();
+
+            assert_eq!(start, $expected.0);
+            assert_eq!(end, $expected.1);
+        };
+    }
+
+    // arithmetic over the anchors
+    test_content!("s + (e - s) / 2..e", [10, 40], [], (25, 40));
+    test_content!("s & ~0xf..e", [20, 40], [], (16, 40));
+    test_content!("s..(e + 0xf) & ~0xf", [20, 40], [], (20, 48));
+
+    // slice content (little endian, sign-extended, zero if out of the slice)
+    test_content!("s..s + 4 + i[0]", [10, 20], [3u8, 0, 0, 0, 0xff], (10, 17));
+    test_content!("s..s + b[4]", [10, 20], [3u8, 0, 0, 0, 0xff], (10, 9));
+    test_content!("s..s + (b[4] & 0xff)", [10, 20], [3u8, 0, 0, 0, 0xff], (10, 265));
+    test_content!("s + h[1]..e", [10, 20], [3u8, 0, 1, 1, 0xff], (267, 20));
+    test_content!("s..e + i[1]", [10, 20], [3u8, 0, 1, 1, 0xff], (10, 20));
+
+    // negative indices are reported as errors
+    let mapper = SegmentMapper::from_str("s..e + i[b[0] - 4]").unwrap();
+    assert!(mapper.evaluate(&[10, 20], &[10, 20], &[3u8, 0, 1, 1]).is_err());
+    assert!(mapper.evaluate(&[10, 20], &[10, 20], &[4u8, 0, 1, 1]).is_ok());
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
@@ -177,17 +296,20 @@ impl RangeMapper {
     pub fn from_str(expr: &str) -> Result<Self> {
         let mapper = SegmentMapper::from_str(expr)?;
 
-        let start = if mapper.start.anchor == 0 {
-            StartAnchored(std::cmp::max(mapper.start.offset, 0) as usize)
-        } else {
-            EndAnchored(std::cmp::max(-mapper.start.offset, 0) as usize)
+        // the stream end is unknown until EOF, so the ranges must be relative to the boundaries
+        let convert = |anchor: &SegmentMapperAnchor| -> Result<RangeMapperAnchor> {
+            match *anchor {
+                Anchored { anchor: 0, offset } => Ok(StartAnchored(std::cmp::max(offset, 0) as usize)),
+                Anchored { offset, .. } => Ok(EndAnchored(std::cmp::max(-offset, 0) as usize)),
+                Expr { .. } => Err(anyhow!(
+                    "range expression (S..E) must be relative to the boundaries, in the form of s + k or e + k: {:?}",
+                    expr
+                )),
+            }
         };
 
-        let end = if mapper.end.anchor == 0 {
-            StartAnchored(std::cmp::max(mapper.end.offset, 0) as usize)
-        } else {
-            EndAnchored(std::cmp::max(-mapper.end.offset, 0) as usize)
-        };
+        let start = convert(&mapper.start)?;
+        let end = convert(&mapper.end)?;
 
         Ok(RangeMapper { start, end })
     }
@@ -9,4 +9,18 @@ pub const BLOCK_SIZE: usize = 2 * 1024 * 1024;
 
 pub const MARGIN_SIZE: usize = 256;
 
+// memory budget for sorting guide records; larger guides are sorted in runs spilled to temporary files
+#[cfg(test)]
+pub const GUIDE_SORT_SIZE: usize = 4096;
+
+#[cfg(not(test))]
+pub const GUIDE_SORT_SIZE: usize = 64 * 1024 * 1024;
+
+// upper bound of the stream buffered for a slice mapped by --extend, e.g., with a content-derived end
+#[cfg(test)]
+pub const EXTEND_SIZE: usize = 4096;
+
+#[cfg(not(test))]
+pub const EXTEND_SIZE: usize = 256 * 1024 * 1024;
+
 // end of params.rs
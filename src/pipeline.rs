@@ -5,12 +5,13 @@
 use crate::byte::*;
 use crate::drain::*;
 use crate::eval::*;
+use crate::hash::HashAlgorithm;
 use crate::segment::*;
 use crate::text::*;
 use anyhow::{anyhow, Result};
 use clap::Parser;
 
-use std::io::Read;
+use std::io::{IsTerminal, Read};
 
 use self::Node::*;
 use self::NodeClass::*;
@@ -29,6 +30,24 @@ fn parse_const_slicer_params(s: &str) -> Result<ConstSlicerParams> {
     Ok(params)
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    Auto, // only when the output is a terminal
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            x => Err(anyhow!("unknown color mode {x:?} (auto, always, or never expected)")),
+        }
+    }
+}
+
 #[derive(Debug, Parser)]
 pub struct PipelineArgs {
     #[clap(short = 'F', long = "in-format", value_name = "FORMAT", value_parser = InoutFormat::from_str)]
@@ -37,6 +56,9 @@ pub struct PipelineArgs {
     #[clap(short = 'f', long = "out-format", value_name = "FORMAT", value_parser = InoutFormat::from_str)]
     out_format: Option<InoutFormat>,
 
+    #[clap(long = "array-name", value_name = "NAME")]
+    array_name: Option<String>,
+
     #[clap(long = "filler", value_name = "N", value_parser = parse_usize)]
     filler: Option<usize>,
 
@@ -58,12 +80,18 @@ pub struct PipelineArgs {
     #[clap(short = 'p', long = "patch", value_name = "FILE")]
     patch: Option<String>,
 
+    #[clap(long = "patch-format", value_name = "FORMAT", value_parser = InoutFormat::from_str)]
+    patch_format: Option<InoutFormat>,
+
     #[clap(short = 'w', long = "width", value_name = "N[,S..E]", value_parser = parse_const_slicer_params)]
     width: Option<ConstSlicerParams>,
 
     #[clap(short = 'd', long = "find", value_name = "PAT")]
     find: Option<String>,
 
+    #[clap(long = "distance", value_name = "K[,METRIC]", value_parser = Distance::from_str)]
+    distance: Option<Distance>,
+
     #[clap(short = 'k', long = "walk", value_name = "EXPR[,...]")]
     walk: Option<String>,
 
@@ -73,9 +101,45 @@ pub struct PipelineArgs {
     #[clap(short = 'g', long = "guide", value_name = "FILE")]
     guide: Option<String>,
 
+    #[clap(long = "guide-format", value_name = "FORMAT", value_parser = InoutFormat::from_str)]
+    guide_format: Option<InoutFormat>,
+
+    #[clap(long = "guide-sort")]
+    guide_sort: bool,
+
+    #[clap(long = "regions", value_name = "W[,H[,F]]")]
+    regions: Option<String>,
+
+    #[clap(long = "strings", value_name = "N", value_parser = parse_usize)]
+    strings: Option<usize>,
+
+    #[clap(long = "strings-encoding", value_name = "ENC")]
+    strings_encoding: Option<String>,
+
+    #[clap(long = "strings-nul")]
+    strings_nul: bool,
+
+    #[clap(long = "cdc", value_name = "AVG|MIN,AVG,MAX")]
+    cdc: Option<String>,
+
+    #[clap(long = "split", value_name = "ARRAY[,...]")]
+    split: Option<String>,
+
+    #[clap(long = "split-keep")]
+    split_keep: bool,
+
+    #[clap(long = "split-escape", value_name = "BYTE")]
+    split_escape: Option<String>,
+
     #[clap(short = 'e', long = "regex", value_name = "PCRE")]
     regex: Option<String>,
 
+    #[clap(long = "regex-window", value_name = "B,A", value_parser = parse_usize_pair)]
+    regex_window: Option<(usize, usize)>,
+
+    #[clap(long = "regex-group", value_name = "G[,...]")]
+    regex_group: Option<String>,
+
     #[clap(short = 'x', long = "extend", value_name = "S..E[,...]")]
     extend: Option<String>,
 
@@ -85,14 +149,39 @@ pub struct PipelineArgs {
     #[clap(short = 'm', long = "merge", value_name = "N", value_parser = parse_usize)]
     merge: Option<usize>,
 
+    #[clap(short = 'W', long = "where", value_name = "EXPR")]
+    where_: Option<String>,
+
     #[clap(short = 'l', long = "lines", value_name = "S..E[,...]")]
     lines: Option<String>,
 
+    #[clap(long = "stats")]
+    stats: bool,
+
+    #[clap(long = "hash", value_name = "ALG[,...]")]
+    hash: Option<String>,
+
+    #[clap(long = "mosaic", value_name = "MODE", value_parser = Mosaic::from_str)]
+    mosaic: Option<Mosaic>,
+
+    // "--color" alone is "--color=auto"; the value must follow '=' so that it isn't taken from the inputs
+    #[clap(long = "color", value_name = "WHEN", value_parser = ColorMode::from_str, num_args = 0..=1, require_equals = true, default_missing_value = "auto")]
+    color: Option<ColorMode>,
+
     #[clap(short = 'o', long = "output", value_name = "FILE")]
     output: Option<String>,
 
     #[clap(short = 'P', long = "patch-back", value_name = "CMD")]
     patch_back: Option<String>,
+
+    #[clap(short = 'R', long = "replace", value_name = "FROM:TO", value_parser = ReplaceParams::from_str)]
+    replace: Option<ReplaceParams>,
+
+    #[clap(long = "replace-limit", value_name = "N", value_parser = parse_usize)]
+    replace_limit: Option<usize>,
+
+    #[clap(long = "fixup", value_name = "ALG:S..E:P..Q[:le|be]", value_parser = FixupParams::from_str)]
+    fixup: Option<FixupParams>,
 }
 
 impl PipelineArgs {
@@ -124,18 +213,28 @@ pub enum Node {
     // Slicers: ByteStream -> SegmentStream
     Width(ConstSlicerParams),
     Find(String),
+    FindApprox(String, Distance),
     Slice(String),
-    Guide(String),
+    Guide(String, bool),
     Walk(String),
+    Regions(String),
+    Strings(usize, String, bool),
+    Cdc(String),
+    Split(String, bool, Option<String>),
+    RegexStream(String, (usize, usize), Option<String>),
     // SegmentFilters: SegmentStream -> SegmentStream
-    Regex(String),
+    Regex(String, (usize, usize), Option<String>),
+    Highlight,
     Bridge(String),
     Merge(usize),
     Extend(String),
+    Where(String),
     Lines(String),
     // Post-processing: SegmentStream -> ByteStream (Read)
     Scatter(String),
     PatchBack(String),
+    Replace(ReplaceParams, Option<usize>),
+    Fixup(FixupParams),
 }
 
 enum NodeClass {
@@ -158,16 +257,26 @@ impl Node {
             Tee => ByteFilter,
             Width(_) => Slicer,
             Find(_) => Slicer,
+            FindApprox(_, _) => Slicer,
             Slice(_) => Slicer,
-            Guide(_) => Slicer,
+            Guide(..) => Slicer,
             Walk(_) => Slicer,
-            Regex(_) => SegmentFilter,
+            Regions(_) => Slicer,
+            Strings(_, _, _) => Slicer,
+            Cdc(_) => Slicer,
+            Split(_, _, _) => Slicer,
+            RegexStream(_, _, _) => Slicer,
+            Regex(_, _, _) => SegmentFilter,
+            Highlight => SegmentFilter,
             Bridge(_) => SegmentFilter,
             Merge(_) => SegmentFilter,
             Extend(_) => SegmentFilter,
+            Where(_) => SegmentFilter,
             Lines(_) => SegmentFilter,
             Scatter(_) => Drain,
             PatchBack(_) => Drain,
+            Replace(_, _) => Drain,
+            Fixup(_) => Drain,
         }
     }
 
@@ -182,6 +291,7 @@ impl Node {
                 | (Slicer, Drain)
                 | (SegmentFilter, SegmentFilter)
                 | (SegmentFilter, Drain)
+                | (Drain, ByteFilter) // re-sliced for --fixup
         )
     }
 }
@@ -197,6 +307,13 @@ pub struct Pipeline {
     in_format: InoutFormat,
     out_format: InoutFormat,
     patch_format: InoutFormat,
+    patch_file_format: InoutFormat,
+    guide_format: InoutFormat,
+    stats: bool,
+    hashes: Vec<HashAlgorithm>,
+    array_name: Option<String>,
+    mosaic: Mosaic,
+    is_colored: bool,
     nodes: Vec<Node>,
 }
 
@@ -227,26 +344,98 @@ impl Pipeline {
         if let Some(file) = &m.patch {
             nodes.push(Patch(file.to_string()));
         }
-        if m.patch_back.is_some() {
+        if m.patch_back.is_some() || m.replace.is_some() || m.fixup.is_some() {
             nodes.push(Tee);
         }
 
-        // slicers are exclusive as well
-        let (cols, node) = match (m.width, &m.find, &m.walk, &m.slice, &m.guide) {
-            (Some(width), None, None, None, None) => (width.columns(), Width(width)),
-            (None, Some(pattern), None, None, None) => (0, Find(pattern.to_string())),
-            (None, None, Some(exprs), None, None) => (0, Walk(exprs.to_string())),
-            (None, None, None, Some(exprs), None) => (0, Slice(exprs.to_string())),
-            (None, None, None, None, Some(file)) => (0, Guide(file.to_string())),
-            (None, None, None, None, None) => (16, Width(ConstSlicerParams::from_raw(16, None)?)),
-            _ => return Err(anyhow!("--width, --find, --walk, --slice, and --guide are exclusive.")),
+        // slicers are exclusive as well; --replace brings its own slicer
+        let mut slicers = Vec::new();
+        if let Some(width) = m.width {
+            slicers.push((width.columns(), Width(width)));
+        }
+        if let Some(pattern) = &m.find {
+            match m.distance {
+                Some(distance) => slicers.push((0, FindApprox(pattern.to_string(), distance))),
+                None => slicers.push((0, Find(pattern.to_string()))),
+            }
+        }
+        if let Some(exprs) = &m.walk {
+            slicers.push((0, Walk(exprs.to_string())));
+        }
+        if let Some(exprs) = &m.slice {
+            slicers.push((0, Slice(exprs.to_string())));
+        }
+        if let Some(file) = &m.guide {
+            slicers.push((0, Guide(file.to_string(), m.guide_sort)));
+        }
+        if let Some(params) = &m.regions {
+            slicers.push((0, Regions(params.to_string())));
+        }
+        if let Some(min_len) = m.strings {
+            let encoding = m.strings_encoding.as_deref().unwrap_or("ascii");
+            slicers.push((0, Strings(min_len, encoding.to_string(), m.strings_nul)));
+        }
+        if let Some(params) = &m.cdc {
+            slicers.push((0, Cdc(params.to_string())));
+        }
+        if let Some(delimiters) = &m.split {
+            slicers.push((0, Split(delimiters.to_string(), m.split_keep, m.split_escape.clone())));
+        }
+
+        if m.replace.is_some() && !slicers.is_empty() {
+            return Err(anyhow!(
+                "--replace can't be used with --width, --find, --walk, --slice, --guide, --regions, --strings, --cdc, and --split."
+            ));
+        }
+        let (cols, node) = match slicers.len() {
+            0 => match (&m.replace, &m.regex, m.regex_window) {
+                (Some(replace), _, _) if replace.is_regex => (0, RegexStream(replace.from.clone(), (0, 4096), None)),
+                (Some(replace), _, _) => (0, Find(replace.from.clone())),
+                // --regex with --regex-window but without slicers matches the entire stream
+                (None, Some(pattern), Some(window)) => (0, RegexStream(pattern.to_string(), window, m.regex_group.clone())),
+                (None, _, _) => (16, Width(ConstSlicerParams::from_raw(16, None)?)),
+            },
+            1 => slicers.pop().unwrap(),
+            _ => {
+                return Err(anyhow!(
+                    "--width, --find, --walk, --slice, --guide, --regions, --strings, --cdc, and --split are exclusive."
+                ))
+            }
         };
+        if m.distance.is_some() && m.find.is_none() {
+            return Err(anyhow!("--distance must be used with --find."));
+        }
+        if m.guide_sort && m.guide.is_none() {
+            return Err(anyhow!("--guide-sort must be used with --guide."));
+        }
+        if (m.split_keep || m.split_escape.is_some()) && m.split.is_none() {
+            return Err(anyhow!("--split-keep and --split-escape must be used with --split."));
+        }
+        if (m.strings_encoding.is_some() || m.strings_nul) && m.strings.is_none() {
+            return Err(anyhow!("--strings-encoding and --strings-nul must be used with --strings."));
+        }
+        if m.regex_window.is_some() && m.regex.is_none() {
+            return Err(anyhow!("--regex-window must be used with --regex."));
+        }
+        if m.regex_group.is_some() && m.regex.is_none() {
+            return Err(anyhow!("--regex-group must be used with --regex."));
+        }
+        let is_regex_stream = matches!(node, RegexStream(_, _, _));
+        let slicer_index = nodes.len();
         nodes.push(node);
 
         // slice manipulators
-        if let Some(pattern) = &m.regex {
-            nodes.push(Regex(pattern.to_string()));
+        if let (Some(pattern), false) = (&m.regex, is_regex_stream) {
+            let window = m.regex_window.unwrap_or((0, 0));
+            nodes.push(Regex(pattern.to_string(), window, m.regex_group.clone()));
         }
+
+        // matches of --find and --regex are highlighted in the colored output; see below
+        let is_match = matches!(
+            nodes.last(),
+            Some(Find(_) | FindApprox(_, _) | RegexStream(_, _, _) | Regex(_, _, _))
+        );
+        let highlight_index = is_match.then_some(nodes.len());
         if let Some(invert) = &m.invert {
             nodes.push(Bridge(invert.to_string()));
         }
@@ -256,18 +445,50 @@ impl Pipeline {
         if let Some(thresh) = m.merge {
             nodes.push(Merge(thresh));
         }
+        if let Some(expr) = &m.where_ {
+            nodes.push(Where(expr.to_string()));
+        }
         if let Some(exprs) = &m.lines {
             nodes.push(Lines(exprs.to_string()));
         }
 
-        let (written_back, node) = match (&m.output, &m.patch_back) {
-            (Some(file), None) => (file.is_empty() || file == "-", Scatter(file.to_string())),
-            (None, Some(command)) => (false, PatchBack(command.to_string())),
-            (None, None) => (true, Scatter("-".to_string())),
-            _ => return Err(anyhow!("--output and --patch-back are exclusive.")),
+        let (written_back, node) = match (&m.output, &m.patch_back, &m.replace) {
+            (Some(file), None, None) => (file.is_empty() || file == "-", Scatter(file.to_string())),
+            (None, Some(command), None) => (false, PatchBack(command.to_string())),
+            (None, None, Some(replace)) => (false, Replace(replace.clone(), m.replace_limit)),
+            (None, None, None) => (true, Scatter("-".to_string())),
+            _ => return Err(anyhow!("--output, --patch-back, and --replace are exclusive.")),
         };
+        if m.replace_limit.is_some() && m.replace.is_none() {
+            return Err(anyhow!("--replace-limit must be used with --replace."));
+        }
+        // --lines stops reading the stream after the last line, leaving the rest unpatched
+        if m.replace.is_some() && m.lines.is_some() {
+            return Err(anyhow!("--lines can't be used with --replace (use --replace-limit instead)."));
+        }
         nodes.push(node);
 
+        // --fixup re-slices the output of --patch-back with the same slicers and filters
+        if let Some(fixup) = &m.fixup {
+            match nodes.pop() {
+                Some(Scatter(_)) if m.output.is_none() => {}
+                Some(node @ PatchBack(_)) => {
+                    if m.guide.as_deref() == Some("-") {
+                        return Err(anyhow!("--guide can't read stdin with --patch-back and --fixup."));
+                    }
+                    let slicers = nodes[slicer_index..].to_vec();
+                    nodes.push(node);
+                    nodes.push(Tee);
+                    nodes.extend(slicers);
+                }
+                _ => return Err(anyhow!("--fixup can't be used with --output and --replace.")),
+            }
+            if m.lines.is_some() {
+                return Err(anyhow!("--lines can't be used with --fixup."));
+            }
+            nodes.push(Fixup(fixup.clone()));
+        }
+
         // special handling for input / output formats
         let default_in_signature = "b";
         let default_out_signature = if m.inplace && written_back { "b" } else { "xxx" };
@@ -281,6 +502,60 @@ impl Pipeline {
             .clone()
             .unwrap_or_else(|| InoutFormat::from_str_with_columns(default_out_signature, cols).unwrap());
         let patch_format = InoutFormat::from_str_with_columns("xxx", cols).unwrap();
+        let patch_file_format = m.patch_format.clone().unwrap_or_else(|| patch_format.clone());
+        if out_format.is_input_only() {
+            return Err(anyhow!("tsv, bed, xxd, xxd-p, od, and hexdump can't be used for --out-format."));
+        }
+        if in_format.is_output_only() || patch_file_format.is_output_only() {
+            return Err(anyhow!("c, rust, and python can't be used for --in-format and --patch-format."));
+        }
+        if m.array_name.is_some() && !out_format.is_output_only() {
+            return Err(anyhow!("--array-name must be used with --out-format c, rust, or python."));
+        }
+        if m.mosaic.is_some() && (out_format.dialect != Dialect::Native || out_format.is_binary()) {
+            return Err(anyhow!("--mosaic must be used with --out-format x, d, or their variants."));
+        }
+        if m.hash.is_some() && (out_format.dialect != Dialect::Native || out_format.is_binary()) {
+            return Err(anyhow!("--hash must be used with --out-format x, d, or their variants."));
+        }
+        if m.stats && (out_format.dialect != Dialect::Native || out_format.is_binary()) {
+            return Err(anyhow!("--stats must be used with --out-format x, d, or their variants."));
+        }
+        // colors only for the formatted slices
+        let is_formatted = out_format.dialect == Dialect::Native && !out_format.is_binary();
+        let is_colored = match (m.color.unwrap_or(ColorMode::Never), nodes.last()) {
+            (ColorMode::Always, _) if !is_formatted => {
+                return Err(anyhow!("--color=always must be used with --out-format x, d, or their variants."));
+            }
+            (ColorMode::Always, Some(Scatter(_))) => true,
+            (ColorMode::Auto, Some(Scatter(_))) => is_formatted && written_back && std::io::stdout().is_terminal(),
+            _ => false,
+        };
+        if let (Some(index), true) = (highlight_index, is_colored) {
+            nodes.insert(index, Highlight);
+        }
+        if patch_file_format.is_binary() {
+            return Err(anyhow!("--patch-format doesn't accept raw binary (b)."));
+        }
+        if m.patch_format.is_some() && m.patch.is_none() {
+            return Err(anyhow!("--patch-format must be used with --patch."));
+        }
+
+        let guide_format = m.guide_format.clone().unwrap_or_else(|| patch_format.clone());
+        if guide_format.is_binary() {
+            return Err(anyhow!("--guide-format doesn't accept raw binary (b)."));
+        }
+        if guide_format.is_output_only() {
+            return Err(anyhow!("c, rust, and python can't be used for --guide-format."));
+        }
+        if m.guide_format.is_some() && m.guide.is_none() {
+            return Err(anyhow!("--guide-format must be used with --guide."));
+        }
+
+        let hashes = match &m.hash {
+            Some(hash) => HashAlgorithm::from_str_list(hash)?,
+            None => Vec::new(),
+        };
 
         // background byte
         let filler = match m.filler {
@@ -295,6 +570,13 @@ impl Pipeline {
             in_format,
             out_format,
             patch_format,
+            patch_file_format,
+            guide_format,
+            stats: m.stats,
+            hashes,
+            array_name: m.array_name.clone(),
+            mosaic: m.mosaic.unwrap_or(Mosaic::Ascii),
+            is_colored,
             nodes,
         };
         pipeline.validate()?;
@@ -348,6 +630,7 @@ impl Pipeline {
         let mut sources: Vec<_> = sources.into_iter().map(|x| self.build_parser(x)).collect();
 
         let mut cache = None;
+        let mut highlights = None;
         let mut node = match &self.nodes[0] {
             Cat => NodeInstance::Byte(Box::new(CatStream::new(sources))),
             Zip => NodeInstance::Byte(Box::new(ZipStream::new(sources, self.word_size))),
@@ -367,7 +650,7 @@ impl Pipeline {
                     (cache, NodeInstance::Byte(next))
                 }
                 (Patch(file), NodeInstance::Byte(prev)) => {
-                    let next = Box::new(PatchStream::new(prev, self.open_file(file)?, &self.patch_format));
+                    let next = Box::new(PatchStream::new(prev, self.open_file(file)?, &self.patch_file_format)?);
                     (cache, NodeInstance::Byte(next))
                 }
                 (Tee, NodeInstance::Byte(prev)) => {
@@ -383,22 +666,53 @@ impl Pipeline {
                     let next = Box::new(ExactMatchSlicer::new(prev, pattern)?);
                     (cache, NodeInstance::Segment(next))
                 }
+                (FindApprox(pattern, distance), NodeInstance::Byte(prev)) => {
+                    let next = Box::new(ApproxMatchSlicer::new(prev, pattern, distance)?);
+                    (cache, NodeInstance::Segment(next))
+                }
                 (Slice(exprs), NodeInstance::Byte(prev)) => {
                     let next = Box::new(RangeSlicer::new(prev, exprs)?);
                     (cache, NodeInstance::Segment(next))
                 }
-                (Guide(file), NodeInstance::Byte(prev)) => {
-                    let next = Box::new(GuidedSlicer::new(prev, self.open_file(file)?));
+                (Guide(file, sort), NodeInstance::Byte(prev)) => {
+                    let next = GuidedSlicer::new(prev, self.open_file(file)?, &self.guide_format);
+                    let next = Box::new(if *sort { next.with_sort() } else { next });
                     (cache, NodeInstance::Segment(next))
                 }
                 (Walk(exprs), NodeInstance::Byte(prev)) => {
                     let next = Box::new(WalkSlicer::new(prev, exprs)?);
                     (cache, NodeInstance::Segment(next))
                 }
-                (Regex(pattern), NodeInstance::Segment(prev)) => {
-                    let next = Box::new(RegexSlicer::new(prev, pattern)?);
+                (Regions(params), NodeInstance::Byte(prev)) => {
+                    let next = Box::new(RegionSlicer::new(prev, params)?);
                     (cache, NodeInstance::Segment(next))
                 }
+                (Strings(min_len, encoding, requires_nul), NodeInstance::Byte(prev)) => {
+                    let next = Box::new(StringsSlicer::new(prev, *min_len, encoding, *requires_nul)?);
+                    (cache, NodeInstance::Segment(next))
+                }
+                (Cdc(params), NodeInstance::Byte(prev)) => {
+                    let next = Box::new(CdcSlicer::new(prev, params)?);
+                    (cache, NodeInstance::Segment(next))
+                }
+                (Split(delimiters, keeps_delimiter, escape), NodeInstance::Byte(prev)) => {
+                    let delimiters = Box::new(ExactMatchSlicer::new(prev, delimiters)?);
+                    let next = Box::new(SplitSlicer::new(delimiters, *keeps_delimiter, escape.as_deref())?);
+                    (cache, NodeInstance::Segment(next))
+                }
+                (RegexStream(pattern, window, groups), NodeInstance::Byte(prev)) => {
+                    let next = Box::new(StreamRegexSlicer::new(prev, pattern, *window, groups.as_deref())?);
+                    (cache, NodeInstance::Segment(next))
+                }
+                (Regex(pattern, window, groups), NodeInstance::Segment(prev)) => {
+                    let next = Box::new(RegexSlicer::new(prev, pattern, *window, groups.as_deref())?);
+                    (cache, NodeInstance::Segment(next))
+                }
+                (Highlight, NodeInstance::Segment(prev)) => {
+                    let ranges = MatchRanges::default();
+                    highlights = Some(ranges.clone());
+                    (cache, NodeInstance::Segment(Box::new(MatchTap::new(prev, ranges))))
+                }
                 (Bridge(invert), NodeInstance::Segment(prev)) => {
                     let next = Box::new(BridgeStream::new(prev, invert)?);
                     (cache, NodeInstance::Segment(next))
@@ -411,16 +725,38 @@ impl Pipeline {
                     let next = Box::new(ExtendStream::new(prev, extend)?);
                     (cache, NodeInstance::Segment(next))
                 }
+                (Where(expr), NodeInstance::Segment(prev)) => {
+                    let next = Box::new(PredicateStream::new(prev, expr)?);
+                    (cache, NodeInstance::Segment(next))
+                }
                 (Lines(exprs), NodeInstance::Segment(prev)) => {
                     let next = Box::new(FilterStream::new(prev, exprs)?);
                     (cache, NodeInstance::Segment(next))
                 }
                 (Scatter(file), NodeInstance::Segment(prev)) => {
-                    let next = Box::new(ScatterDrain::new(prev, file, &self.out_format)?);
+                    let mut formatter = TextFormatter::new(&self.out_format, (0, 0))
+                        .with_stats(self.stats)
+                        .with_hashes(&self.hashes)
+                        .with_mosaic(self.mosaic);
+                    if self.is_colored {
+                        formatter = formatter.with_colors(highlights.take());
+                    }
+                    if let Some(name) = &self.array_name {
+                        formatter = formatter.with_array_name(name)?;
+                    }
+                    let next = Box::new(ScatterDrain::new(prev, file, formatter)?);
                     (cache, NodeInstance::Byte(next))
                 }
                 (PatchBack(command), NodeInstance::Segment(prev)) => {
-                    let next = Box::new(PatchDrain::new(prev, cache.unwrap(), command, &self.patch_format));
+                    let next = Box::new(PatchDrain::new(prev, cache.unwrap(), command, &self.patch_format)?);
+                    (None, NodeInstance::Byte(next))
+                }
+                (Replace(params, limit), NodeInstance::Segment(prev)) => {
+                    let next = Box::new(ReplaceDrain::new(prev, cache.unwrap(), params, *limit)?);
+                    (None, NodeInstance::Byte(next))
+                }
+                (Fixup(params), NodeInstance::Segment(prev)) => {
+                    let next = Box::new(FixupDrain::new(prev, cache.unwrap(), params)?);
                     (None, NodeInstance::Byte(next))
                 }
                 (next, _) => return Err(anyhow!("unallowed node {:?} found after (internal error)", next)),
@@ -442,6 +778,20 @@ mod tests {
     use clap::Parser;
     use std::io::Read;
 
+    #[test]
+    fn test_pipeline_args_err() {
+        let from_args = |args: &str| Pipeline::from_args(&PipelineArgs::parse_from(args.split_whitespace()));
+        assert!(from_args("nd --width=4 --hash=md5").is_ok());
+
+        // columns only in the formatted slices
+        assert!(from_args("nd --width=4 --hash=md5 --out-format=b").is_err());
+        assert!(from_args("nd --width=4 --hash=md5 --out-format=json").is_err());
+        assert!(from_args("nd --width=4 --hash=md5 --out-format=c").is_err());
+        assert!(from_args("nd --width=4 --stats --out-format=b").is_err());
+        assert!(from_args("nd --width=4 --stats --out-format=csv").is_err());
+        assert!(from_args("nd --width=4 --stats --out-format=ihex").is_err());
+    }
+
     #[test]
     fn test_pipeline() {
         macro_rules! test {
@@ -484,7 +834,7 @@ mod tests {
         test!(
             "nd --out-format=b --pad=3,5",
             [b"0123456789".as_slice()],
-            b"\0\0\00123456789\0\0\0\0\0"
+            b"\0\0\x000123456789\0\0\0\0\0"
         );
         test!(
             "nd --out-format=b --pad=3,5 --filler=0x0a",
@@ -495,7 +845,7 @@ mod tests {
         test!(
             "nd --out-format=b --cat=4",
             [b"0123456789".as_slice(), b"0123456789".as_slice()],
-            b"0123456789\0\00123456789\0\0"
+            b"0123456789\0\x000123456789\0\0"
         );
         test!(
             "nd --out-format=b --cat=4 --filler=0x0a",
@@ -506,7 +856,7 @@ mod tests {
         test!(
             "nd --out-format=b --zip=4",
             [b"0123456789".as_slice(), b"0123456789".as_slice()],
-            b"012301234567456789\0\089\0\0"
+            b"012301234567456789\0\x0089\0\0"
         );
         test!(
             "nd --out-format=b --zip=4 --filler=0x0a",
@@ -517,13 +867,263 @@ mod tests {
         test!(
             "nd --out-format=b --in-format=x",
             [b"0004 0004 | 31 32 33 34\n000a 0000 | 61 62 63".as_slice()],
-            b"\0\0\0\01234\0\0abc"
+            b"\0\0\0\x001234\0\0abc"
         );
         test!(
             "nd --out-format=b --in-format=x --filler=0x0a",
             [b"0004 0004 | 31 32 33 34\n000a 0000 | 61 62 63".as_slice()],
             b"\n\n\n\n1234\n\nabc"
         );
+
+        test!(
+            "nd --out-format=x+g2le --width=4,s+1..e",
+            [b"0123456789".as_slice()],
+            b"000000000001 0003 | 3231 33                                 | 123             \n\
+              000000000005 0003 | 3635 37                                 | 567             \n\
+              000000000009 0001 | 39                                      | 9               \n"
+        );
+        test!(
+            "nd --out-format=b --in-format=x+g2le",
+            [b"000000000001 0003 | 3231 33 | 123\n000000000005 0003 | 3635 37 | 567\n".as_slice()],
+            b"\x00123\x00567"
+        );
+        test!(
+            "nd --mosaic=utf8 --width=4",
+            [b"0\xc3\xa9\xe2\x86\x92\x00".as_slice()],
+            "000000000000 0004 | 30 c3 a9 e2 | 0\u{e9} .\n\
+             000000000004 0003 | 86 92 00    | ... \n"
+                .as_bytes()
+        );
+        test!(
+            "nd --color=always --find=6c6c --extend=s-3..e+2",
+            [b"He\x00llo\xff\n".as_slice()],
+            b"\x1b[34m000000000000 0007\x1b[0m | \
+              \x1b[0;36m48 65\x1b[0;90m 00\x1b[0;36m \x1b[0;36;7m6c 6c\x1b[0;36m 6f\x1b[0;31m ff\x1b[0m | \
+              \x1b[0;36mHe\x1b[0;90m.\x1b[0;36;7mll\x1b[0;36mo\x1b[0;31m.\x1b[0m\n"
+        );
+
+        test!(
+            "nd --out-format=b --in-format=ihex --filler=0x0a",
+            [b":020000040000FA\n:04000400313233342E\n:03000A00616263CD\n:00000001FF\n".as_slice()],
+            b"\n\n\n\n1234\n\nabc"
+        );
+        test!(
+            "nd --out-format=b --in-format=srec --filler=0x0a",
+            [b"S00600004844521B\nS1070004313233342A\nS106000A616263C9\nS9030000FC\n".as_slice()],
+            b"\n\n\n\n1234\n\nabc"
+        );
+        test!(
+            "nd --out-format=b --in-format=xxd",
+            [b"00000000: 3031 3233  0123\n*\n0000000c: 6162 63    abc\n".as_slice()],
+            b"012301230123abc"
+        );
+        test!(
+            "nd --out-format=b --in-format=xxd-p",
+            [b"30313233\n616263\n".as_slice()],
+            b"0123abc"
+        );
+        test!(
+            "nd --out-format=b --in-format=od",
+            [b"000000 30 31 32 33\n*\n00000a\n".as_slice()],
+            b"0123012301"
+        );
+        test!(
+            "nd --out-format=b --in-format=hexdump",
+            [b"00000002  30 31 32 33 34 35 36 37  38                       |012345678|\n0000000b\n".as_slice()],
+            b"\x00\x00012345678"
+        );
+
+        test!(
+            "nd --out-format=json --width=4,s+1..e",
+            [b"0123456\"89".as_slice()],
+            b"{\"offset\":1,\"length\":3,\"hex\":\"313233\"}\n{\"offset\":5,\"length\":3,\"hex\":\"353622\"}\n{\"offset\":9,\"length\":1,\"hex\":\"39\"}\n"
+        );
+        test!(
+            "nd --out-format=json+ascii --width=4,s+1..e",
+            [b"0123456\"89".as_slice()],
+            b"{\"offset\":1,\"length\":3,\"hex\":\"313233\",\"ascii\":\"123\"}\n{\"offset\":5,\"length\":3,\"hex\":\"353622\",\"ascii\":\"56\\\"\"}\n{\"offset\":9,\"length\":1,\"hex\":\"39\",\"ascii\":\"9\"}\n"
+        );
+        test!(
+            "nd --out-format=csv+ascii --width=4,s+1..e",
+            [b"0123456\"89".as_slice()],
+            b"1,3,313233,\"123\"\n5,3,353622,\"56\"\"\"\n9,1,39,\"9\"\n"
+        );
+        test!(
+            "nd --out-format=b --in-format=json",
+            [b"{\"offset\":1,\"length\":3,\"hex\":\"313233\"}\n{\"offset\":5,\"length\":1,\"hex\":\"35\"}\n".as_slice()],
+            b"\x00123\x005"
+        );
+        test!(
+            "nd --out-format=b --in-format=csv",
+            [b"offset,length,hex\n1,3,313233\n5,1,35\n".as_slice()],
+            b"\x00123\x005"
+        );
+
+        test!(
+            "nd --out-format=ihex --width=4,s+1..e",
+            [b"0123456789".as_slice()],
+            b":0300010031323366\n:0300050035363756\n:0100090039BD\n:00000001FF\n"
+        );
+        test!(
+            "nd --out-format=srec --width=4,s+1..e",
+            [b"0123456789".as_slice()],
+            b"S106000131323362\nS106000535363752\nS104000939B9\nS9030000FC\n"
+        );
+
+        test!(
+            "nd --out-format=c --width=4,s+1..e",
+            [b"0123456789".as_slice()],
+            b"unsigned char slice_1[] = {\n  0x31, 0x32, 0x33\n};\nunsigned int slice_1_len = 3;\n\
+              unsigned char slice_5[] = {\n  0x35, 0x36, 0x37\n};\nunsigned int slice_5_len = 3;\n\
+              unsigned char slice_9[] = {\n  0x39\n};\nunsigned int slice_9_len = 1;\n"
+        );
+        test!(
+            "nd --out-format=rust --array-name=DIGITS --width=4,s+1..e",
+            [b"0123456789".as_slice()],
+            b"pub static DIGITS: &[u8] = &[\n    0x31, 0x32, 0x33, 0x35, 0x36, 0x37, 0x39,\n];\n"
+        );
+        test!(
+            "nd --out-format=python --array-name=d{l} --width=4,s+1..e",
+            [b"0123456789".as_slice()],
+            b"d0 = (\n    b\"\\x31\\x32\\x33\"\n)\nd1 = (\n    b\"\\x35\\x36\\x37\"\n)\nd2 = (\n    b\"\\x39\"\n)\n"
+        );
+
+        test!("nd --out-format=b --find=33,35", [b"0123456789".as_slice()], b"35");
+        test!(
+            "nd --find=x=33,y=?5",
+            [b"0123456789".as_slice()],
+            b"000000000003 0001 | 33 | 3 | x\n000000000005 0001 | 35 | 5 | y\n"
+        );
+
+        test!("nd --out-format=b --find=33 --distance=1,bit", [b"0123456789".as_slice()], b"1237");
+        test!(
+            "nd --find=x=3133 --distance=1",
+            [b"0123456789".as_slice()],
+            b"000000000001 0002 | 31 32 | 12 | x 1\n000000000002 0002 | 32 33 | 23 | x 1\n"
+        );
+
+        test!("nd --out-format=b --regex=a.c", [b"xxabcxxabc".as_slice()], b"abcabc");
+        test!("nd --out-format=b --regex=a.c", [b"xxxxxxxxxxxxxxabcxxabc".as_slice()], b"abc");
+        test!(
+            "nd --out-format=b --regex=a.c --regex-window=0,4",
+            [b"xxxxxxxxxxxxxxabcxxabc".as_slice()],
+            b"abcabc"
+        );
+        test!("nd --out-format=b --width=4 --regex=a.c", [b"xxabcxxabc".as_slice()], b"");
+        test!(
+            "nd --out-format=b --width=4 --regex=a.c --regex-window=0,2",
+            [b"xxabcxxabc".as_slice()],
+            b"abcabc"
+        );
+
+        test!(
+            "nd --regex=(?P<n>[0-9]+)-([a-z]+) --regex-window=0,16 --regex-group=n,2",
+            [b"12-ab 3-c".as_slice()],
+            b"000000000000 0002 | 31 32 | 12 | n\n000000000003 0002 | 61 62 | ab\n000000000006 0001 | 33 | 3 | n\n000000000008 0001 | 63 | c\n"
+        );
+
+        test!("nd --replace=35:", [b"0123456789".as_slice()], b"012346789");
+        test!("nd --replace=33,35:\"ab\"", [b"0123456789".as_slice()], b"012ab4ab6789");
+        test!(
+            "nd --replace=33,35:\"ab\" --replace-limit=1",
+            [b"0123456789".as_slice()],
+            b"012ab456789"
+        );
+        test!(
+            "nd --replace=33,35:\"ab\" --where=index==1",
+            [b"0123456789".as_slice()],
+            b"01234ab6789"
+        );
+        test!("nd --replace=/([0-9])([0-9])/:$2$1", [b"0123456789".as_slice()], b"1032547698");
+        test!("nd --replace=/[13579]/:", [b"0123456789".as_slice()], b"02468");
+        test!("nd --replace=/\\Bo/:0", [b"fox o".as_slice()], b"f0x o");
+
+        test!(
+            "nd --find=x=3435 --hash=crc32,adler32",
+            [b"0123456789".as_slice()],
+            b"000000000004 0002 | 34 35 | 45 | ac40252b | 009f006a | x\n"
+        );
+
+        test!(
+            "nd --regions=4,1.5",
+            [b"\0\0\0\0\0\0abcdaabb".as_slice()],
+            b"000000000000 0006 | 00 00 00 00 00 00 | ...... | fill 00\n000000000006 0004 | 61 62 63 64 | abcd | high\n00000000000a 0004 | 61 61 62 62 | aabb | low\n"
+        );
+        test!(
+            "nd --regions=4,1.5 --where=len>4 --out-format=b",
+            [b"\0\0\0\0\0\0abcdaabb".as_slice()],
+            b"\0\0\0\0\0\0"
+        );
+        test!(
+            "nd --strings=4 --out-format=b",
+            [b"\x01abc\x02abcd\0efghi\xffjklm".as_slice()],
+            b"abcdefghijklm"
+        );
+        test!(
+            "nd --strings=4 --strings-nul --out-format=b",
+            [b"\x01abc\x02abcd\0efghi\xffjklm".as_slice()],
+            b"abcd"
+        );
+        test!(
+            "nd --strings=3 --strings-encoding=utf16le --extend=s..e+2 --out-format=b",
+            [b"a\0b\0\0\0x\0y\0z\0\0\0".as_slice()],
+            b"x\0y\0z\0\0\0"
+        );
+        test!(
+            "nd --cdc=4,8,16 --out-format=b",
+            [b"The quick brown fox jumps over the lazy dog.\n".as_slice()],
+            b"The quick brown fox jumps over the lazy dog.\n"
+        );
+        test!(
+            "nd --cdc=4,8,16 --where=len==16 --out-format=b",
+            [b"The quick brown fox jumps over the lazy dog.\n".as_slice()],
+            b"ver the lazy dog"
+        );
+        test!("nd --split=0a --out-format=b --where=len>1", [b"ab\nc\ndef".as_slice()], b"abdef");
+        test!(
+            "nd --split=0a --split-keep --extend=s..e-1 --out-format=b",
+            [b"ab\nc\ndef".as_slice()],
+            b"abcde"
+        );
+        test!(
+            "nd --split=0d0a --split-escape=5c --out-format=b --where=len>2",
+            [b"ab\\\r\ncd\r\nef\r\n".as_slice()],
+            b"ab\\\r\ncd"
+        );
+        test!(
+            "nd --stats --width=8",
+            [b"abcd\0\0\xff\n0".as_slice()],
+            b"000000000000 0008 | | 2.7500 00 ff 0002 0.5000\n000000000008 0001 | | 0.0000 30 30 0000 1.0000\n"
+        );
+        test!(
+            "nd --stats --width=8 --out-format=d --hash=crc32",
+            [b"abcd\0\0\xff\n".as_slice()],
+            b"0 8 | | 2.7500 00 ff 2 0.5000 | f4699a95\n"
+        );
+
+        test!(
+            "nd --width=8 --fixup=crc32:s..e-4:e-4..e",
+            [b"abcd\0\0\0\0efgh\0\0\0\0".as_slice()],
+            b"abcd\xed\x82\xcd\x11efgh\x08\x33\x7b\xb5"
+        );
+        test!(
+            "nd --width=8 --fixup=adler32:s+1..e-5:e-2..e:le",
+            [b"abcd\0\0\0\0efgh\0\0\0\0".as_slice()],
+            b"abcd\0\0\xc6\x00efgh\0\0\xce\x00"
+        );
+        test!(
+            "nd --width=8 --where=index==1 --patch-back=cat --fixup=crc32:s..e-4:e-4..e",
+            [b"abcd\0\0\0\0efgh\0\0\0\0".as_slice()],
+            b"abcd\0\0\0\0efgh\x08\x33\x7b\xb5"
+        );
+
+        test!("nd --out-format=b --width=2 --where=index&1", [b"0123456789".as_slice()], b"2367");
+        test!("nd --out-format=b --width=2 --where=b[1]==0x35", [b"0123456789".as_slice()], b"45");
+        test!(
+            "nd --out-format=b --width=2 --where=b[1]==0x35 --lines=1..",
+            [b"0123456789".as_slice()],
+            b""
+        );
     }
 }
 
@@ -0,0 +1,352 @@
+// @file approx.rs
+// @author Hajime Suzuki
+// @brief approximate pattern matching slicer
+
+use super::pattern::{parse_patterns, Pattern};
+use super::{Segment, SegmentStream};
+use crate::byte::ByteStream;
+use crate::eval::parse_usize;
+use crate::params::BLOCK_SIZE;
+use anyhow::{anyhow, Result};
+use std::cmp::Reverse;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Distance {
+    Byte(usize), // Hamming distance in bytes
+    Bit(usize),  // Hamming distance in bits
+    Edit(usize), // Levenshtein distance in bytes
+}
+
+impl Distance {
+    pub fn from_str(s: &str) -> Result<Self> {
+        let (k, metric) = s.split_once(',').unwrap_or((s, "byte"));
+        let k = parse_usize(k)?;
+
+        match metric.trim() {
+            "byte" => Ok(Distance::Byte(k)),
+            "bit" => Ok(Distance::Bit(k)),
+            "edit" => Ok(Distance::Edit(k)),
+            _ => Err(anyhow!("unknown metric {metric:?} (byte, bit, or edit expected)")),
+        }
+    }
+
+    fn max_dist(&self) -> usize {
+        match self {
+            Distance::Byte(k) | Distance::Bit(k) | Distance::Edit(k) => *k,
+        }
+    }
+
+    fn max_len(&self, pattern: &Pattern) -> usize {
+        match self {
+            Distance::Edit(k) => pattern.bytes.len() + k,
+            _ => pattern.bytes.len(),
+        }
+    }
+}
+
+fn hamming_dist(pattern: &Pattern, stream: &[u8], max_dist: usize, count_bits: bool) -> Option<(usize, usize)> {
+    if stream.len() < pattern.bytes.len() {
+        return None;
+    }
+
+    let mut dist = 0;
+    for ((x, m), y) in stream.iter().zip(&pattern.mask).zip(&pattern.bytes) {
+        let diff = (x & m) ^ y;
+        dist += if count_bits {
+            diff.count_ones() as usize
+        } else {
+            (diff != 0) as usize
+        };
+        if dist > max_dist {
+            return None;
+        }
+    }
+    Some((pattern.bytes.len(), dist))
+}
+
+fn edit_dist(pattern: &Pattern, stream: &[u8], max_dist: usize, dp: &mut Vec<usize>) -> Option<(usize, usize)> {
+    // the alignment is anchored at the head of the stream; dp[j] holds the distance
+    // between the pattern prefix and stream[..j]
+    let n = std::cmp::min(stream.len(), pattern.bytes.len() + max_dist);
+    dp.clear();
+    dp.extend(0..=n);
+
+    for (i, (m, y)) in pattern.mask.iter().zip(&pattern.bytes).enumerate() {
+        let mut diag = dp[0];
+        dp[0] = i + 1;
+
+        let mut min = dp[0];
+        for j in 1..=n {
+            let sub = diag + ((stream[j - 1] & m) != *y) as usize;
+            diag = dp[j];
+            dp[j] = std::cmp::min(sub, std::cmp::min(dp[j - 1], dp[j]) + 1);
+            min = std::cmp::min(min, dp[j]);
+        }
+
+        if min > max_dist {
+            return None;
+        }
+    }
+
+    // the longest one among the best alignments, e.g., "fox" rather than "f" for "fax"
+    let (len, dist) = dp.iter().enumerate().max_by_key(|&(len, &d)| (Reverse(d), len))?;
+    if *dist > max_dist {
+        return None;
+    }
+    Some((len, *dist))
+}
+
+pub struct ApproxMatchSlicer {
+    src: Box<dyn ByteStream>,
+    segments: Vec<Segment>,
+    scanned: usize,
+
+    patterns: Vec<Pattern>,
+    distance: Distance,
+    max_len: usize,
+
+    // labels for (pattern, distance) pairs
+    labels: Vec<String>,
+
+    // working buffer for the edit distance
+    dp: Vec<usize>,
+}
+
+impl ApproxMatchSlicer {
+    pub fn new(src: Box<dyn ByteStream>, patterns: &str, distance: &Distance) -> Result<Self> {
+        let (patterns, names) = parse_patterns(patterns)?;
+
+        // a distance as large as the pattern lets it match anywhere
+        let max_dist = distance.max_dist();
+        for p in &patterns {
+            let len = if matches!(distance, Distance::Bit(_)) {
+                8 * p.bytes.len()
+            } else {
+                p.bytes.len()
+            };
+            if max_dist >= len {
+                return Err(anyhow!("distance must be smaller than the pattern length ({max_dist} >= {len})"));
+            }
+        }
+
+        let mut labels = Vec::new();
+        for p in &patterns {
+            for d in 0..=max_dist {
+                let label = match p.tag {
+                    0 => d.to_string(),
+                    tag => format!("{} {}", names[tag - 1], d),
+                };
+                labels.push(label);
+            }
+        }
+
+        Ok(ApproxMatchSlicer {
+            src,
+            segments: Vec::new(),
+            scanned: 0,
+            max_len: patterns.iter().map(|x| distance.max_len(x)).max().unwrap_or(0),
+            patterns,
+            distance: *distance,
+            labels,
+            dp: Vec::new(),
+        })
+    }
+}
+
+impl SegmentStream for ApproxMatchSlicer {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        let (is_eof, bytes) = self.src.fill_buf(BLOCK_SIZE)?;
+
+        if self.patterns.is_empty() {
+            self.scanned = bytes;
+            return Ok((is_eof, bytes, 0, self.scanned));
+        }
+
+        // matches starting in [self.scanned, end) are located in this call
+        let stream = self.src.as_slice();
+        let end = if is_eof {
+            bytes
+        } else {
+            std::cmp::max(self.scanned, (bytes + 1).saturating_sub(self.max_len))
+        };
+
+        let max_dist = self.distance.max_dist();
+        for pos in self.scanned..end {
+            for (i, pattern) in self.patterns.iter().enumerate() {
+                let stream = &stream[pos..bytes];
+                let found = match self.distance {
+                    Distance::Byte(_) => hamming_dist(pattern, stream, max_dist, false),
+                    Distance::Bit(_) => hamming_dist(pattern, stream, max_dist, true),
+                    Distance::Edit(_) => edit_dist(pattern, stream, max_dist, &mut self.dp),
+                };
+
+                if let Some((len, dist)) = found {
+                    self.segments.push(Segment {
+                        pos,
+                        len,
+                        tag: i * (max_dist + 1) + dist + 1,
+                    });
+                }
+            }
+        }
+
+        self.scanned = end;
+        Ok((is_eof, bytes, self.segments.len(), self.scanned))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        let stream = self.src.as_slice();
+        (stream, &self.segments)
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let bytes = std::cmp::min(bytes, self.scanned);
+        self.src.consume(bytes);
+
+        let from = self.segments.partition_point(|x| x.pos < bytes);
+        let to = self.segments.len();
+
+        self.segments.copy_within(from..to, 0);
+        self.segments.truncate(to - from);
+
+        for s in &mut self.segments {
+            s.pos -= bytes;
+        }
+        self.scanned -= bytes;
+
+        Ok((bytes, from))
+    }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        let label = self.labels.get(tag.checked_sub(1)?)?;
+        Some(label.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ApproxMatchSlicer, Distance};
+    use crate::segment::tester::*;
+
+    macro_rules! bind {
+        ( $pattern: expr, $distance: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                let distance = Distance::from_str($distance).unwrap();
+                Box::new(ApproxMatchSlicer::new(src, $pattern, &distance).unwrap())
+            }
+        };
+    }
+
+    macro_rules! test {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                // empty pattern
+                $inner(b"", &bind!("", "1"), &[]);
+                $inner(b"abcdefghijklmnopqrstu", &bind!("", "1"), &[]);
+
+                // exact
+                let expected = [Segment { pos: 0, len: 3, tag: 1 }];
+                $inner(b"abcdefghijklmnopqrstu", &bind!("61 62 63", "0"), &expected);
+                $inner(b"abcdefghijklmnopqrstu", &bind!("61 62 63", "0,edit"), &expected);
+
+                // byte-level Hamming distance
+                $inner(b"abcdefghijklmnopqrstu", &bind!("61 62 63", "1"), &expected);
+                $inner(
+                    b"xabdxabcxacxx",
+                    &bind!("61 62 63", "1"),
+                    &[Segment { pos: 1, len: 3, tag: 2 }, Segment { pos: 5, len: 3, tag: 1 }],
+                );
+                $inner(
+                    b"mississippi, mississippi, and mississippi",
+                    &bind!("\"sip\"", "1,byte"),
+                    &[
+                        Segment { pos: 3, len: 3, tag: 2 },
+                        Segment { pos: 6, len: 3, tag: 1 },
+                        Segment { pos: 16, len: 3, tag: 2 },
+                        Segment { pos: 19, len: 3, tag: 1 },
+                        Segment { pos: 33, len: 3, tag: 2 },
+                        Segment { pos: 36, len: 3, tag: 1 },
+                    ],
+                );
+
+                // bit-level Hamming distance; 'a' ^ 'c' == 0x02, 'b' ^ 'c' == 0x01
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("\"cc\"", "1,bit"),
+                    &[Segment { pos: 1, len: 2, tag: 2 }],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("\"cc\"", "2,bit"),
+                    &[Segment { pos: 0, len: 2, tag: 3 }, Segment { pos: 1, len: 2, tag: 2 }],
+                );
+
+                // edit distance; the longest one among the best alignments is reported
+                $inner(
+                    b"xabdxabcxacxx",
+                    &bind!("\"abc\"", "1,edit"),
+                    &[
+                        Segment { pos: 1, len: 3, tag: 2 },
+                        Segment { pos: 4, len: 4, tag: 2 },
+                        Segment { pos: 5, len: 3, tag: 1 },
+                        Segment { pos: 6, len: 2, tag: 2 },
+                        Segment { pos: 9, len: 2, tag: 2 },
+                    ],
+                );
+
+                $inner(b"abxc", &bind!("\"abc\"", "1,edit"), &[Segment { pos: 0, len: 4, tag: 2 }]);
+                $inner(b"the fox", &bind!("\"fax\"", "1,edit"), &[Segment { pos: 4, len: 3, tag: 2 }]);
+                $inner(
+                    b"the fox",
+                    &bind!("\"fx\"", "1,edit"),
+                    &[
+                        Segment { pos: 4, len: 3, tag: 2 },
+                        Segment { pos: 5, len: 2, tag: 2 },
+                        Segment { pos: 6, len: 1, tag: 2 },
+                    ],
+                );
+
+                // multiple patterns
+                $inner(
+                    b"xabdxabcxacxx",
+                    &bind!("\"abc\", \"xa\"", "1"),
+                    &[
+                        Segment { pos: 0, len: 2, tag: 3 },
+                        Segment { pos: 1, len: 3, tag: 2 },
+                        Segment { pos: 4, len: 2, tag: 3 },
+                        Segment { pos: 5, len: 3, tag: 1 },
+                        Segment { pos: 8, len: 2, tag: 3 },
+                        Segment { pos: 11, len: 2, tag: 4 },
+                    ],
+                );
+            }
+        };
+    }
+
+    test!(test_approx_all_at_once, test_segment_all_at_once);
+    test!(test_approx_random_len, test_segment_random_len);
+    test!(test_approx_occasional_consume, test_segment_occasional_consume);
+
+    #[test]
+    fn test_approx_parse() {
+        assert_eq!(Distance::from_str("1").unwrap(), Distance::Byte(1));
+        assert_eq!(Distance::from_str("2,bit").unwrap(), Distance::Bit(2));
+        assert_eq!(Distance::from_str("3,edit").unwrap(), Distance::Edit(3));
+        assert!(Distance::from_str("-1").is_err());
+        assert!(Distance::from_str("1,hamming").is_err());
+
+        let bind = |pattern: &str, distance: &str| {
+            let src = Box::new(MockSource::new(b""));
+            ApproxMatchSlicer::new(src, pattern, &Distance::from_str(distance).unwrap())
+        };
+        assert!(bind("61 62", "1").is_ok());
+        assert!(bind("61 62", "2").is_err());
+        assert!(bind("61 62", "15,bit").is_ok());
+        assert!(bind("61 62", "16,bit").is_err());
+        assert!(bind("61 62", "2,edit").is_err());
+    }
+}
+
+// end of approx.rs
@@ -1,6 +1,7 @@
 // @file bridge.rs
 // @author Hajime Suzuki
 
+use super::extend::MappedSorter;
 use super::{Segment, SegmentStream};
 use crate::mapper::SegmentMapper;
 use anyhow::{anyhow, Result};
@@ -9,6 +10,10 @@ pub struct BridgeStream {
     src: Box<dyn SegmentStream>,
     segments: Vec<Segment>,
 
+    // sorter; needed to reorder the segments mapped by multiple expressions or by the ones
+    // depending on the contents of the gaps
+    sorter: MappedSorter,
+
     // #segments that are not consumed in the source but already accumulated to `acc`
     // note: not the count from the beginning of the source stream
     src_scanned: usize,
@@ -16,6 +21,9 @@ pub struct BridgeStream {
     // end position of the previous source segment
     last_end: isize,
 
+    // absolute offset of the head of the current stream
+    offset: usize,
+
     // the number of bytes that can be consumed at most in this slicer. is computed
     // from the `last_end` so that the next any segment overlaps with the cosumable range
     max_consume: usize,
@@ -37,53 +45,56 @@ impl BridgeStream {
         Ok(BridgeStream {
             src,
             segments: Vec::new(),
+            sorter: MappedSorter::new("--invert"),
             src_scanned: 0,
             last_end: 0,
+            offset: 0,
             max_consume: 0,
             mappers,
         })
     }
 
-    fn update_max_consume(&mut self, is_eof: bool, bytes: usize) {
+    fn update_max_consume(&mut self, is_eof: bool, bytes: usize) -> Result<()> {
         if is_eof {
             self.max_consume = bytes;
-            return;
+            return Ok(());
         }
 
-        let phantom = [self.last_end, self.last_end + 1];
+        let offset = self.offset as isize;
+        let phantom = [offset + self.last_end, offset + self.last_end + 1];
 
-        let eval_phantom = |m: &SegmentMapper| {
-            let (start, end) = m.evaluate(&phantom, &phantom);
-            std::cmp::min(start, end)
+        let eval_phantom = |m: &SegmentMapper| -> Result<isize> {
+            let (start, end) = m.evaluate(&phantom, &phantom, &[])?;
+            Ok(std::cmp::min(start, end) - offset)
         };
-        let max_consume = self.mappers.iter().map(eval_phantom).min().unwrap_or(0);
+        let max_consume = self.mappers.iter().map(eval_phantom).collect::<Result<Vec<_>>>()?;
+        let max_consume = max_consume.into_iter().min().unwrap_or(0);
         let max_consume = std::cmp::max(0, max_consume) as usize;
 
         self.max_consume = max_consume;
+        Ok(())
     }
 
-    fn extend_segment_buf(&mut self, is_eof: bool, count: usize, bytes: usize) {
+    fn extend_segment_buf(&mut self, is_eof: bool, count: usize, bytes: usize) -> Result<()> {
         let tail = if is_eof { bytes } else { usize::MAX };
 
-        let map_segment = |m: &SegmentMapper, gap: &[isize; 2]| -> Option<Segment> {
-            let (start, end) = m.evaluate(gap, gap);
+        let offset = self.offset;
+        let (stream, segments) = self.src.as_slices();
 
-            let start = (start.max(0) as usize).min(tail);
-            let end = (end.max(0) as usize).min(tail);
+        let sorter = &mut self.sorter;
+        let mut map_segment = |m: &SegmentMapper, gap: &[isize; 2]| -> Result<()> {
+            // expressions are evaluated on the absolute positions
+            let slice = &stream[gap[0].max(0) as usize..gap[1].max(0) as usize];
+            let abs_gap = [gap[0] + offset as isize, gap[1] + offset as isize];
+            let (start, end) = m.evaluate(&abs_gap, &abs_gap, slice)?;
 
             // record the segment
-            if start < end {
-                Some(Segment {
-                    pos: start,
-                    len: end - start,
-                })
-            } else {
-                None
+            if let Some((start, end)) = sorter.clip(start, end, offset, tail)? {
+                sorter.push(offset, start, end - start, 0);
             }
+            Ok(())
         };
 
-        let (_, segments) = self.src.as_slices();
-
         // first map all the source segment pairs with `mappers`
         let mut prev_end = self.last_end;
         if count > self.src_scanned {
@@ -98,9 +109,7 @@ impl BridgeStream {
 
                 // map the gap then clip
                 for mapper in &self.mappers {
-                    if let Some(s) = map_segment(mapper, &[prev_end, curr_start]) {
-                        self.segments.push(s);
-                    }
+                    map_segment(mapper, &[prev_end, curr_start])?;
                 }
                 prev_end = std::cmp::max(prev_end, curr_end);
             }
@@ -111,9 +120,7 @@ impl BridgeStream {
             let bytes = bytes as isize;
 
             for mapper in &self.mappers {
-                if let Some(s) = map_segment(mapper, &[prev_end, bytes]) {
-                    self.segments.push(s);
-                }
+                map_segment(mapper, &[prev_end, bytes])?;
             }
             prev_end = bytes;
         }
@@ -121,16 +128,19 @@ impl BridgeStream {
         // all source segments are mapped; save the source-scanning states
         self.last_end = prev_end;
         self.src_scanned = count;
+        Ok(())
     }
 }
 
 impl SegmentStream for BridgeStream {
     fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
         let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
-        self.extend_segment_buf(is_eof, count, bytes);
+        self.extend_segment_buf(is_eof, count, bytes)?;
 
-        // update max_consume
-        self.update_max_consume(is_eof, bytes);
+        // update max_consume, then sort the segments before it
+        self.update_max_consume(is_eof, bytes)?;
+        self.sorter
+            .flush(&mut self.segments, self.offset, &mut self.max_consume, is_eof, bytes)?;
         let max_consume = std::cmp::min(max_consume, self.max_consume);
 
         Ok((is_eof, bytes, self.segments.len(), max_consume))
@@ -156,10 +166,15 @@ impl SegmentStream for BridgeStream {
             s.pos -= bytes;
         }
         self.last_end -= bytes as isize;
+        self.offset += bytes;
         self.max_consume -= bytes;
 
         Ok((bytes, from))
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.src.label(tag)
+    }
 }
 
 #[cfg(test)]
@@ -371,6 +386,104 @@ mod tests {
         };
     }
 
+    macro_rules! test_expr {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                // arithmetic over the anchors
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s & ~3..e"),
+                    &[(0..3).into(), (4..7).into(), (8..11).into(), (12..15).into(), (16..21).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s + (e - s) / 2..e"),
+                    &[
+                        (1..3).into(),
+                        (6..7).into(),
+                        (10..11).into(),
+                        (14..15).into(),
+                        (19..21).into(),
+                    ],
+                );
+
+                // contents of the gaps
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..s + (b[0] & 3)"),
+                    &[(0..1).into(), (5..7).into(), (9..11).into(), (13..15).into(), (17..19).into()],
+                );
+
+                // mapped segments are sorted even if they come from later gaps
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..e, s - 4..e"),
+                    &[
+                        (0..3).into(),
+                        (0..3).into(),
+                        (1..7).into(),
+                        (5..7).into(),
+                        (5..11).into(),
+                        (9..11).into(),
+                        (9..15).into(),
+                        (13..15).into(),
+                        (13..21).into(),
+                        (17..21).into(),
+                    ],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..s + 1, s - 2 + (b[1] & 1)..s"),
+                    &[
+                        (0..1).into(),
+                        (4..5).into(),
+                        (5..6).into(),
+                        (8..9).into(),
+                        (9..10).into(),
+                        (12..13).into(),
+                        (13..14).into(),
+                        (16..17).into(),
+                        (17..18).into(),
+                    ],
+                );
+            }
+        };
+    }
+
+    // fills and consumes the stream to the end; returns the first error if any
+    fn consume_all(pattern: &[u8], exprs: &str) -> anyhow::Result<()> {
+        let src = Box::new(MockSource::new(pattern));
+        let src = Box::new(ConstSlicer::from_raw(src, (0, 0), (false, false), 4, 2));
+        let mut src = BridgeStream::new(src, exprs)?;
+        loop {
+            let (is_eof, bytes, _, max_consume) = src.fill_segment_buf()?;
+            src.consume(max_consume)?;
+            if is_eof && bytes == max_consume {
+                return Ok(());
+            }
+        }
+    }
+
+    #[test]
+    fn test_bridge_expr_err() {
+        let pattern = vec![0x10u8; 1000];
+        assert!(consume_all(&pattern, "s..e + b[0]").is_ok());
+
+        // the start decreasing along the gaps, beyond the consumed head
+        assert!(consume_all(&pattern, "s - b[0]..e").is_err());
+        assert!(consume_all(&pattern, "s + b[0] - 0x10..e").is_ok());
+
+        // the end too far ahead to be buffered (clipped at the tail if the stream is short)
+        let pattern = vec![0x10u8; 10000];
+        assert!(consume_all(&pattern, "s..s + h[0]").is_err());
+        assert!(consume_all(&pattern[..2000], "s..s + h[0]").is_ok());
+    }
+
+    test_expr!(test_bridge_expr_all_at_once, test_segment_all_at_once);
+    test_expr!(test_bridge_expr_random_len, test_segment_random_len);
+    test_expr!(test_bridge_expr_occasional_consume, test_segment_occasional_consume);
+
     test!(test_bridge_all_at_once, test_segment_all_at_once);
     test!(test_bridge_random_len, test_segment_random_len);
     test!(test_bridge_occasional_consume, test_segment_occasional_consume);
@@ -394,6 +507,7 @@ mod tests {
                 v.push(Segment {
                     pos: p.pos + offset,
                     len: p.len,
+                    tag: 0,
                 });
             }
         }
@@ -426,7 +540,7 @@ mod tests {
             let bind = |x: &[u8]| -> Box<dyn SegmentStream> {
                 let src = Box::new(MockSource::new(x));
                 let guide = Box::new(MockSource::new(&guide));
-                let src = Box::new(GuidedSlicer::new(src, guide));
+                let src = Box::new(GuidedSlicer::new(src, guide, &InoutFormat::from_str("xxx").unwrap()));
 
                 Box::new(BridgeStream::new(src, $offsets).unwrap())
             };
@@ -0,0 +1,249 @@
+// @file cdc.rs
+// @author Hajime Suzuki
+// @brief content-defined chunking slicer (FastCDC)
+
+use super::{Segment, SegmentStream};
+use crate::byte::ByteStream;
+use crate::eval::parse_usize;
+use crate::params::BLOCK_SIZE;
+use anyhow::{anyhow, Result};
+
+// random table for the gear hash; generated by splitmix64 so that the chunks are reproducible
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x6e64_6364_6373_6c63;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct CdcParams {
+    min: usize,
+    avg: usize,
+    max: usize,
+
+    // normalized chunking; the stricter mask before `avg` and the looser one after it. the gear hash
+    // accumulates the history in the upper bits, so the masks take the upper bits.
+    mask_s: u64,
+    mask_l: u64,
+}
+
+impl CdcParams {
+    // "AVG" or "MIN,AVG,MAX"
+    fn from_str(s: &str) -> Result<Self> {
+        let v = s.split(',').map(parse_usize).collect::<Result<Vec<_>>>()?;
+        let (min, avg, max) = match v[..] {
+            [avg] => (avg / 4, avg, avg * 8),
+            [min, avg, max] => (min, avg, max),
+            _ => return Err(anyhow!("failed to parse {s:?} as AVG or MIN,AVG,MAX")),
+        };
+        if avg < 4 || min > avg || avg > max {
+            return Err(anyhow!("MIN <= AVG <= MAX and AVG >= 4 required for chunk sizes (got: {s:?})"));
+        }
+
+        // log2(avg), rounded
+        let bits = 63 - (avg + (avg >> 1)).leading_zeros() as usize;
+        let mask = |bits: usize| !0u64 << (64 - bits);
+
+        Ok(CdcParams {
+            min,
+            avg,
+            max,
+            mask_s: mask(bits + 1),
+            mask_l: mask(bits - 1),
+        })
+    }
+
+    // length of the chunk at the head of `stream`, or None if the boundary may be found beyond the stream
+    fn cut(&self, stream: &[u8], is_eof: bool) -> Option<usize> {
+        let len = std::cmp::min(stream.len(), self.max);
+        if len <= self.min {
+            return if is_eof || len == self.max { Some(len) } else { None };
+        }
+
+        let normal = std::cmp::min(len, self.avg);
+        let mut hash = 0u64;
+        for (i, &x) in stream.iter().enumerate().take(len).skip(self.min) {
+            hash = (hash << 1).wrapping_add(GEAR[x as usize]);
+            let mask = if i < normal { self.mask_s } else { self.mask_l };
+            if hash & mask == 0 {
+                return Some(i + 1);
+            }
+        }
+
+        if is_eof || len == self.max {
+            Some(len)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct CdcSlicer {
+    src: Box<dyn ByteStream>,
+    segments: Vec<Segment>,
+
+    scanned: usize,
+    request: usize,
+
+    params: CdcParams,
+}
+
+impl CdcSlicer {
+    pub fn new(src: Box<dyn ByteStream>, params: &str) -> Result<Self> {
+        Ok(CdcSlicer {
+            src,
+            segments: Vec::new(),
+            scanned: 0,
+            request: BLOCK_SIZE,
+            params: CdcParams::from_str(params)?,
+        })
+    }
+}
+
+impl SegmentStream for CdcSlicer {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        let (is_eof, bytes) = self.src.fill_buf(self.request)?;
+        let stream = &self.src.as_slice()[..bytes];
+
+        self.request = BLOCK_SIZE;
+        while self.scanned < bytes {
+            let pos = self.scanned;
+            match self.params.cut(&stream[pos..], is_eof) {
+                Some(len) => {
+                    self.segments.push(Segment { pos, len, tag: 0 });
+                    self.scanned += len;
+                }
+                None => {
+                    self.request = std::cmp::max(BLOCK_SIZE, pos + self.params.max);
+                    break;
+                }
+            }
+        }
+
+        Ok((is_eof, bytes, self.segments.len(), self.scanned))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        let stream = self.src.as_slice();
+        (stream, &self.segments)
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let bytes = std::cmp::min(bytes, self.scanned);
+        self.src.consume(bytes);
+
+        let from = self.segments.partition_point(|x| x.pos < bytes);
+        let to = self.segments.len();
+
+        self.segments.copy_within(from..to, 0);
+        self.segments.truncate(to - from);
+
+        for s in &mut self.segments {
+            s.pos -= bytes;
+        }
+        self.scanned -= bytes;
+
+        Ok((bytes, from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CdcParams, CdcSlicer};
+    use crate::segment::tester::*;
+    use rand::Rng;
+
+    macro_rules! bind {
+        ( $params: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                Box::new(CdcSlicer::new(src, $params).unwrap())
+            }
+        };
+    }
+
+    fn chunk_all(input: &[u8], params: &str) -> Vec<Segment> {
+        let params = CdcParams::from_str(params).unwrap();
+
+        let mut v = Vec::new();
+        let mut pos = 0;
+        while pos < input.len() {
+            let len = params.cut(&input[pos..], true).unwrap();
+            v.push((pos..pos + len).into());
+            pos += len;
+        }
+        v
+    }
+
+    fn gen_input(len: usize) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        (0..len).map(|_| rng.gen::<u8>()).collect()
+    }
+
+    macro_rules! test {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                $inner(b"", &bind!("16"), &[]);
+                $inner(b"abc", &bind!("16"), &[(0..3).into()]);
+                $inner(&[0u8; 100], &bind!("4,16,32"), &chunk_all(&[0u8; 100], "4,16,32"));
+
+                for params in ["16", "8,32,64", "64,256,1024", "32,32,32"] {
+                    let input = gen_input(10000);
+                    let expected = chunk_all(&input, params);
+                    $inner(&input, &bind!(params), &expected);
+                }
+            }
+        };
+    }
+
+    test!(test_cdc_all_at_once, test_segment_all_at_once);
+    test!(test_cdc_random_len, test_segment_random_len);
+    test!(test_cdc_occasional_consume, test_segment_occasional_consume);
+
+    #[test]
+    fn test_cdc_boundaries() {
+        let input = gen_input(1 << 20);
+        let chunks = chunk_all(&input, "256,1024,8192");
+
+        // chunk sizes are within [min, max], around the average
+        assert!(chunks[..chunks.len() - 1].iter().all(|x| x.len >= 256 && x.len <= 8192));
+        let avg = input.len() / chunks.len();
+        assert!(avg > 768 && avg < 1536, "{avg}");
+
+        // boundaries are content-defined; an insertion at the head shifts only the first few chunks
+        let mut shifted = b"inserted".to_vec();
+        shifted.extend_from_slice(&input);
+
+        let tails: std::collections::HashSet<_> = chunks.iter().map(|x| x.tail() + 8).collect();
+        let shifted = chunk_all(&shifted, "256,1024,8192");
+        let common = shifted.iter().filter(|x| tails.contains(&x.tail())).count();
+        assert!(common + 4 >= chunks.len(), "{common} / {}", chunks.len());
+    }
+
+    #[test]
+    fn test_cdc_params() {
+        let params = CdcParams::from_str("1024").unwrap();
+        assert_eq!((params.min, params.avg, params.max), (256, 1024, 8192));
+        assert_eq!(params.mask_s.count_ones(), 11);
+        assert_eq!(params.mask_l.count_ones(), 9);
+
+        assert!(CdcParams::from_str("2").is_err());
+        assert!(CdcParams::from_str("64,32,128").is_err());
+        assert!(CdcParams::from_str("16,32,16").is_err());
+        assert!(CdcParams::from_str("16,32").is_err());
+    }
+}
+
+// end of cdc.rs
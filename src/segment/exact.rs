@@ -1,39 +1,52 @@
 // @file exact.rs
 // @author Hajime Suzuki
 
+use super::pattern::{parse_patterns, Pattern};
 use super::{Segment, SegmentStream};
 use crate::byte::ByteStream;
 use crate::params::BLOCK_SIZE;
-use crate::text::parser::parse_hex_body;
-use anyhow::{anyhow, Context, Result};
+use aho_corasick::AhoCorasick;
+use anyhow::Result;
 
 pub struct ExactMatchSlicer {
     src: Box<dyn ByteStream>,
     segments: Vec<Segment>,
     scanned: usize,
-    pattern: Vec<u8>,
+
+    patterns: Vec<Pattern>,
+    max_len: usize,
+    labels: Vec<String>,
+
+    // the automaton for the anchors, and the pattern ids for each of the anchors
+    automaton: Option<AhoCorasick>,
+    anchored: Vec<usize>,
+
+    // patterns consisting only of wildcards and partially-masked bytes; compared at every position
+    unanchored: Vec<usize>,
 }
 
 impl ExactMatchSlicer {
-    // TODO: support escaped representation for non-printable characters
-    // TODO: we may support some value representation?? (then strings must be escaped)
-    pub fn new(src: Box<dyn ByteStream>, pattern: &str) -> Result<Self> {
-        let mut pattern = pattern.as_bytes().to_vec();
-        pattern.resize(256, b'\n');
-
-        let mut buf = vec![0u8; 64];
-        let ((_, parsed), filled) =
-            parse_hex_body(false, &pattern, &mut buf).with_context(|| format!("failed to parse {pattern:?} into bytes"))?;
-        if parsed >= 4 * 48 {
-            return Err(anyhow!("ARRAY must not be longer than 64 bytes"));
-        }
-        buf.truncate(filled);
+    pub fn new(src: Box<dyn ByteStream>, patterns: &str) -> Result<Self> {
+        let (v, labels) = parse_patterns(patterns)?;
+
+        let (anchored, unanchored): (Vec<_>, Vec<_>) = (0..v.len()).partition(|&i| !v[i].anchor.is_empty());
+        let automaton = if anchored.is_empty() {
+            None
+        } else {
+            let anchors = anchored.iter().map(|&i| &v[i].bytes[v[i].anchor.clone()]);
+            Some(AhoCorasick::new(anchors)?)
+        };
 
         Ok(ExactMatchSlicer {
             src,
             segments: Vec::new(),
             scanned: 0,
-            pattern: buf,
+            max_len: v.iter().map(|x| x.bytes.len()).max().unwrap_or(0),
+            patterns: v,
+            labels,
+            automaton,
+            anchored,
+            unanchored,
         })
     }
 }
@@ -43,22 +56,60 @@ impl SegmentStream for ExactMatchSlicer {
         let (is_eof, bytes) = self.src.fill_buf(BLOCK_SIZE)?;
 
         // no need to scan the bytes when the pattern is empty
-        if self.pattern.is_empty() {
+        if self.patterns.is_empty() {
             self.scanned = bytes;
             return Ok((is_eof, bytes, 0, self.scanned));
         }
 
+        // matches starting in [self.scanned, end) are located in this call
         let stream = self.src.as_slice();
-        let len = self.pattern.len();
+        let end = if is_eof {
+            bytes
+        } else {
+            std::cmp::max(self.scanned, (bytes + 1).saturating_sub(self.max_len))
+        };
+        let base = self.segments.len();
+
+        if let Some(automaton) = &self.automaton {
+            for m in automaton.find_overlapping_iter(&stream[self.scanned..bytes]) {
+                let pattern = &self.patterns[self.anchored[m.pattern()]];
+                let pos = self.scanned + m.start();
+
+                // the pattern head is before the scanning window, or the rest is not in the stream yet
+                if pos < self.scanned + pattern.anchor.start || pos - pattern.anchor.start >= end {
+                    continue;
+                }
+
+                let pos = pos - pattern.anchor.start;
+                if pattern.is_match(&stream[pos..bytes]) {
+                    self.segments.push(Segment {
+                        pos,
+                        len: pattern.bytes.len(),
+                        tag: pattern.tag,
+                    });
+                }
+            }
+        }
 
-        for pos in memchr::memmem::find_iter(&stream[self.scanned..bytes], &self.pattern) {
-            self.segments.push(Segment {
-                pos: self.scanned + pos,
-                len,
-            });
+        for &i in &self.unanchored {
+            let pattern = &self.patterns[i];
+            for pos in self.scanned..end {
+                if pattern.is_match(&stream[pos..bytes]) {
+                    self.segments.push(Segment {
+                        pos,
+                        len: pattern.bytes.len(),
+                        tag: pattern.tag,
+                    });
+                }
+            }
+        }
+
+        // the automaton reports matches in the order of their tails
+        if self.patterns.len() > 1 {
+            self.segments[base..].sort_by_key(|x| x.pos);
         }
 
-        self.scanned = if is_eof { bytes } else { bytes - self.pattern.len() + 1 };
+        self.scanned = end;
         Ok((is_eof, bytes, self.segments.len(), self.scanned))
     }
 
@@ -84,6 +135,11 @@ impl SegmentStream for ExactMatchSlicer {
 
         Ok((bytes, from))
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        let label = self.labels.get(tag.checked_sub(1)?)?;
+        Some(label.as_bytes())
+    }
 }
 
 #[cfg(test)]
@@ -138,6 +194,59 @@ mod tests {
                         (35..38).into(),
                     ],
                 );
+
+                // multiple patterns
+                $inner(
+                    b"mississippi, mississippi, and mississippi",
+                    &bind!("70 70 69, 73 73 69"),
+                    &[
+                        (2..5).into(),
+                        (5..8).into(),
+                        (8..11).into(),
+                        (15..18).into(),
+                        (18..21).into(),
+                        (21..24).into(),
+                        (32..35).into(),
+                        (35..38).into(),
+                        (38..41).into(),
+                    ],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("61 62, 61 62 63"),
+                    &[(0..2).into(), (0..3).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("x=74 75,y=6b"),
+                    &[Segment { pos: 10, len: 1, tag: 2 }, Segment { pos: 19, len: 2, tag: 1 }],
+                );
+
+                // strings and typed numbers
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("\"def\", u16be:0x7071"),
+                    &[(3..6).into(), (15..17).into()],
+                );
+
+                // wildcards and masks
+                $inner(b"abcdefghijklmnopqrstu", &bind!("62 ?? 64"), &[(1..4).into()]);
+                $inner(b"abcdefghijklmnopqrstu", &bind!("7? ?3"), &[(17..19).into()]);
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("????"),
+                    &(0..20).map(|i| (i..i + 2).into()).collect::<Vec<_>>(),
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("60/f0 ??"),
+                    &(0..15).map(|i| (i..i + 2).into()).collect::<Vec<_>>(),
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind!("?? ?? 66, 64 ??"),
+                    &[(3..5).into(), (3..6).into()],
+                );
             }
         };
     }
@@ -146,6 +255,19 @@ mod tests {
     test!(test_exact_random_len, test_segment_random_len);
     test!(test_exact_occasional_consume, test_segment_occasional_consume);
 
+    #[test]
+    fn test_exact_parse_error() {
+        let bind = |pattern: &str| ExactMatchSlicer::new(Box::new(MockSource::new(b"")), pattern);
+
+        assert!(bind("61 62").is_ok());
+        assert!(bind("61 6").is_err());
+        assert!(bind("61 6g").is_err());
+        assert!(bind("61 80/c0").is_ok());
+        assert!(bind("61 80/c?").is_err());
+        assert!(bind("6162/ff").is_err());
+        assert!(bind("a=61,=62").is_err());
+    }
+
     fn gen_pattern(pattern: &[u8], offset: usize, len: usize, rep: usize) -> (Vec<u8>, Vec<Segment>) {
         debug_assert!(offset + pattern.len() <= len);
 
@@ -164,6 +286,7 @@ mod tests {
             s.push(Segment {
                 pos: base_len + offset,
                 len: pattern.len(),
+                tag: 0,
             });
         }
 
@@ -220,6 +343,11 @@ mod tests {
 
                 test_impl!($inner, "abcdefg", 0, BLOCK_SIZE + 1, BLOCK_SIZE + 2);
                 test_impl!($inner, "abcdefg", BLOCK_SIZE - 8, BLOCK_SIZE + 1, BLOCK_SIZE + 2);
+
+                // longer than 64 bytes
+                let long = "abcdefghijklmnopqrstuvwxyz".repeat(3);
+                test_impl!($inner, long, 0, BLOCK_SIZE, BLOCK_SIZE + 2);
+                test_impl!($inner, long, BLOCK_SIZE - long.len(), BLOCK_SIZE + 1, BLOCK_SIZE + 2);
             }
         };
     }
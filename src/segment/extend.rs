@@ -3,23 +3,115 @@
 
 use super::{Segment, SegmentStream};
 use crate::mapper::SegmentMapper;
+use crate::params::EXTEND_SIZE;
 use anyhow::{anyhow, Result};
 use std::cmp::Reverse;
 use std::collections::BinaryHeap;
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 struct SorterElement {
-    pos: usize,
+    pos: usize, // absolute offset from the head of the stream
     len: usize,
-    original_end: usize,
+    tag: usize,
 }
 
-impl From<SorterElement> for Segment {
-    fn from(elem: SorterElement) -> Segment {
-        Segment {
-            pos: elem.pos,
-            len: elem.len,
+// sorter for the mapped segments; shared by --extend and --invert. the segments are held here
+// until no further segment can precede them.
+pub(super) struct MappedSorter {
+    heap: BinaryHeap<Reverse<SorterElement>>,
+    option: &'static str, // option name in the error messages
+}
+
+impl MappedSorter {
+    pub(super) fn new(option: &'static str) -> Self {
+        MappedSorter {
+            heap: BinaryHeap::new(),
+            option,
+        }
+    }
+
+    // maps the evaluated absolute range to the one relative to `offset`, and clips it at `tail`
+    pub(super) fn clip(&self, start: isize, end: isize, offset: usize, tail: usize) -> Result<Option<(usize, usize)>> {
+        // the bytes before `offset` are already consumed; it happens when the start
+        // decreases along the source segments, e.g., with "s - b[0]..e"
+        if start < end && start.max(0) < offset as isize {
+            return Err(anyhow!(
+                "slice {:#x}..{:#x} mapped by {} starts before the consumed head of the stream at {:#x} (the start must not decrease along the slices)",
+                start,
+                end,
+                self.option,
+                offset
+            ));
+        }
+
+        let start = ((start - offset as isize).max(0) as usize).min(tail);
+        let end = ((end - offset as isize).max(0) as usize).min(tail);
+        Ok(if start < end { Some((start, end)) } else { None })
+    }
+
+    // `pos` is relative to `offset`
+    pub(super) fn push(&mut self, offset: usize, pos: usize, len: usize, tag: usize) {
+        self.heap.push(Reverse(SorterElement {
+            pos: pos + offset,
+            len,
+            tag,
+        }));
+    }
+
+    pub(super) fn flush(
+        &mut self,
+        segments: &mut Vec<Segment>,
+        offset: usize,
+        max_consume: &mut usize,
+        is_eof: bool,
+        bytes: usize,
+    ) -> Result<()> {
+        // segments mapped before reaching EOF may extend beyond the tail; clip them
+        if is_eof {
+            for s in segments.iter_mut() {
+                s.len = std::cmp::min(s.tail(), bytes).saturating_sub(s.pos);
+            }
+            segments.retain(|s| s.len > 0);
+        }
+
+        // move the mapped segments that no later segment precedes into `segments` array.
+        // the later segments start at or after `max_consume`. segments not entirely in the
+        // stream are also left in the sorter until the stream gets long enough.
+        let tail = if is_eof { usize::MAX } else { offset + *max_consume };
+        while let Some(&Reverse(s)) = self.heap.peek() {
+            if s.pos > tail || (!is_eof && s.pos + s.len > offset + bytes) {
+                // the stream is buffered until the pending segment gets entirely in it
+                if s.pos <= tail && offset + bytes - s.pos > EXTEND_SIZE {
+                    return Err(anyhow!(
+                        "slice {:#x}..{:#x} mapped by {} is too long to be buffered (the limit is {} bytes)",
+                        s.pos,
+                        s.pos + s.len,
+                        self.option,
+                        EXTEND_SIZE
+                    ));
+                }
+
+                // the stream must not be consumed beyond the pending segment
+                *max_consume = std::cmp::min(*max_consume, s.pos - offset);
+                break;
+            }
+
+            let s = self.heap.pop().unwrap().0;
+            let s = Segment {
+                pos: s.pos - offset,
+                len: s.len,
+                tag: s.tag,
+            };
+            let len = if is_eof {
+                std::cmp::min(s.tail(), bytes).saturating_sub(s.pos)
+            } else {
+                s.len
+            };
+            if len > 0 {
+                segments.push(Segment { len, ..s });
+            }
         }
+        Ok(())
     }
 }
 
@@ -28,13 +120,16 @@ pub struct ExtendStream {
     segments: Vec<Segment>,
 
     // sorter; needed to reorder the segments when the start positions of the mapped
-    // segments are derived from the end positions of the source segments
-    sorter: BinaryHeap<Reverse<SorterElement>>,
+    // segments are derived from the end positions or the contents of the source segments.
+    sorter: MappedSorter,
 
     // #segments that are not consumed in the source but already accumulated to `acc`
     // note: not the count from the beginning of the source stream
     src_scanned: usize,
 
+    // absolute offset of the head of the current stream
+    offset: usize,
+
     // the number of bytes that can be consumed at most in this slicer. is computed
     // from the `last_end` so that the next any segment overlaps with the cosumable range
     max_consume: usize,
@@ -56,87 +151,84 @@ impl ExtendStream {
         Ok(ExtendStream {
             src,
             segments: Vec::new(),
-            sorter: BinaryHeap::new(),
+            sorter: MappedSorter::new("--extend"),
             src_scanned: 0,
+            offset: 0,
             max_consume: 0,
             mappers,
         })
     }
 
-    fn update_max_consume(&mut self, is_eof: bool, bytes: usize, max_consume: usize) {
+    fn update_max_consume(&mut self, is_eof: bool, bytes: usize, max_consume: usize) -> Result<()> {
         if is_eof {
             self.max_consume = bytes;
-            return;
+            return Ok(());
         }
 
-        let max_consume = max_consume as isize;
+        // the mapped positions are assumed to be non-decreasing in `s` and `e`. the contents
+        // of the phantom segment are not available, and evaluated as zeros.
+        let offset = self.offset as isize;
+        let max_consume = offset + max_consume as isize;
         let phantom = [max_consume, max_consume + 1];
 
-        let eval_phantom = |m: &SegmentMapper| {
-            let (start, end) = m.evaluate(&phantom, &phantom);
-            std::cmp::min(start, end)
+        let eval_phantom = |m: &SegmentMapper| -> Result<isize> {
+            let (start, end) = m.evaluate(&phantom, &phantom, &[])?;
+            Ok(std::cmp::min(start, end) - offset)
         };
-        let max_consume = self.mappers.iter().map(eval_phantom).min().unwrap_or(0);
+        let max_consume = self.mappers.iter().map(eval_phantom).collect::<Result<Vec<_>>>()?;
+        let max_consume = max_consume.into_iter().min().unwrap_or(0);
         let max_consume = std::cmp::max(0, max_consume) as usize;
 
         self.max_consume = max_consume;
+        Ok(())
     }
 
-    fn extend_segment_buf(&mut self, is_eof: bool, count: usize, bytes: usize) {
+    fn extend_segment_buf(&mut self, is_eof: bool, count: usize, bytes: usize) -> Result<()> {
         let tail = if is_eof { bytes } else { usize::MAX };
+        let offset = self.offset;
 
-        let map_segment = |m: &SegmentMapper, segment: &Segment| -> Option<SorterElement> {
-            let s = [segment.pos as isize, segment.tail() as isize];
-            let (start, end) = m.evaluate(&s, &s);
+        let (stream, segments) = self.src.as_slices();
 
-            let start = (start.max(0) as usize).min(tail);
-            let end = (end.max(0) as usize).min(tail);
+        let sorter = &mut self.sorter;
+        let mut map_segment = |m: &SegmentMapper, segment: &Segment| -> Result<()> {
+            // expressions are evaluated on the absolute positions
+            let s = [(segment.pos + offset) as isize, (segment.tail() + offset) as isize];
+            let (start, end) = m.evaluate(&s, &s, &stream[segment.as_range()])?;
 
             // record the segment
-            if start < end {
-                Some(SorterElement {
-                    pos: start,
-                    len: end - start,
-                    original_end: segment.tail(),
-                })
-            } else {
-                None
+            if let Some((start, end)) = sorter.clip(start, end, offset, tail)? {
+                sorter.push(offset, start, end - start, segment.tag);
             }
+            Ok(())
         };
 
-        let (_, segments) = self.src.as_slices();
-
-        // first map all the source segment pairs with `mappers`
+        // map all the source segment pairs with `mappers`
         debug_assert!(count >= self.src_scanned);
         for next in &segments[self.src_scanned..count] {
             for mapper in &self.mappers {
-                if let Some(s) = map_segment(mapper, next) {
-                    self.sorter.push(Reverse(s));
-                }
+                map_segment(mapper, next)?;
             }
         }
 
         // all source segments are mapped; save the source-scanning states
         self.src_scanned = count;
+        Ok(())
+    }
 
-        // then sort the mapped segments into `self.segments` array
-        let tail = if is_eof { usize::MAX } else { bytes };
-        while let Some(&Reverse(s)) = self.sorter.peek() {
-            debug_assert!(s.original_end <= tail);
-
-            let s = self.sorter.pop().unwrap().0;
-            self.segments.push(s.into());
-        }
+    fn flush_sorter(&mut self, is_eof: bool, bytes: usize) -> Result<()> {
+        self.sorter
+            .flush(&mut self.segments, self.offset, &mut self.max_consume, is_eof, bytes)
     }
 }
 
 impl SegmentStream for ExtendStream {
     fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
         let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
-        self.extend_segment_buf(is_eof, count, bytes);
+        self.extend_segment_buf(is_eof, count, bytes)?;
 
-        // update max_consume
-        self.update_max_consume(is_eof, bytes, max_consume);
+        // update max_consume, then sort the segments before it
+        self.update_max_consume(is_eof, bytes, max_consume)?;
+        self.flush_sorter(is_eof, bytes)?;
         let max_consume = std::cmp::min(max_consume, self.max_consume);
 
         Ok((is_eof, bytes, self.segments.len(), max_consume))
@@ -161,10 +253,15 @@ impl SegmentStream for ExtendStream {
         for s in &mut self.segments {
             s.pos -= bytes;
         }
+        self.offset += bytes;
         self.max_consume -= bytes;
 
         Ok((bytes, from))
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.src.label(tag)
+    }
 }
 
 #[cfg(test)]
@@ -379,6 +476,99 @@ mod tests {
         };
     }
 
+    macro_rules! test_expr {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                // arithmetic over the anchors
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s & ~3..e"),
+                    &[(0..5).into(), (4..9).into(), (8..13).into(), (12..17).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s + (e - s) / 2..e"),
+                    &[(4..5).into(), (8..9).into(), (12..13).into(), (16..17).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..(e + 7) & ~7"),
+                    &[(3..8).into(), (7..16).into(), (11..16).into(), (15..21).into()],
+                );
+
+                // slice contents
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..s + b[0] - 0x60"),
+                    &[(3..7).into(), (7..15).into(), (11..21).into(), (15..21).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..s + (h[0] >> 8) - 0x64"),
+                    &[(3..4).into(), (7..12).into(), (11..20).into(), (15..21).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..e + b[2]"),
+                    &[(3..5).into(), (7..9).into(), (11..13).into(), (15..17).into()],
+                );
+
+                // mapped segments are sorted even if they come from later source segments
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_closed!(4, 2, "s..s + 1, s - 3..s - 2"),
+                    &[
+                        (0..1).into(),
+                        (3..4).into(),
+                        (4..5).into(),
+                        (7..8).into(),
+                        (8..9).into(),
+                        (11..12).into(),
+                        (12..13).into(),
+                        (15..16).into(),
+                    ],
+                );
+            }
+        };
+    }
+
+    // fills and consumes the stream to the end; returns the first error if any
+    fn consume_all(pattern: &[u8], exprs: &str) -> anyhow::Result<()> {
+        let src = Box::new(MockSource::new(pattern));
+        let src = Box::new(ConstSlicer::from_raw(src, (0, 0), (false, false), 4, 4));
+        let mut src = ExtendStream::new(src, exprs)?;
+        loop {
+            let (is_eof, bytes, _, max_consume) = src.fill_segment_buf()?;
+            src.consume(max_consume)?;
+            if is_eof && bytes == max_consume {
+                return Ok(());
+            }
+        }
+    }
+
+    #[test]
+    fn test_extend_expr_err() {
+        let pattern = vec![0x10u8; 1000];
+        assert!(consume_all(&pattern, "s..e + b[0]").is_ok());
+
+        // the start decreasing along the slices, beyond the consumed head
+        assert!(consume_all(&pattern, "s - b[0]..e").is_err());
+        assert!(consume_all(&pattern, "s - b[0] + 0x10..e").is_ok());
+
+        // negative index
+        assert!(consume_all(&pattern, "s..e + b[b[0] - 0x20]").is_err());
+
+        // the end too far ahead to be buffered (clipped at the tail if the stream is short)
+        let pattern = vec![0x01u8; 10000];
+        assert!(consume_all(&pattern, "s..s + i[0]").is_err());
+        assert!(consume_all(&pattern[..2000], "s..s + i[0]").is_ok());
+    }
+
+    test_expr!(test_extend_expr_all_at_once, test_segment_all_at_once);
+    test_expr!(test_extend_expr_random_len, test_segment_random_len);
+    test_expr!(test_extend_expr_occasional_consume, test_segment_occasional_consume);
+
     test!(test_extend_all_at_once, test_segment_all_at_once);
     test!(test_extend_random_len, test_segment_random_len);
     test!(test_extend_occasional_consume, test_segment_occasional_consume);
@@ -402,6 +592,7 @@ mod tests {
                 v.push(Segment {
                     pos: p.pos + offset,
                     len: p.len,
+                    tag: 0,
                 });
             }
         }
@@ -434,7 +625,7 @@ mod tests {
             let bind = |x: &[u8]| -> Box<dyn SegmentStream> {
                 let src = Box::new(MockSource::new(x));
                 let guide = Box::new(MockSource::new(&guide));
-                let src = Box::new(GuidedSlicer::new(src, guide));
+                let src = Box::new(GuidedSlicer::new(src, guide, &InoutFormat::from_str("xxx").unwrap()));
 
                 Box::new(ExtendStream::new(src, $offsets).unwrap())
             };
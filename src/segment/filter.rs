@@ -2,9 +2,11 @@
 // @author Hajime Suzuki
 
 use super::{Segment, SegmentStream};
-use crate::mapper::RangeMapper;
-use anyhow::Result;
+use crate::eval::{Rpn, VarAttr};
+use crate::mapper::{evaluate_on_slice, RangeMapper};
+use anyhow::{anyhow, Result};
 use std::cmp::Reverse;
+use std::collections::HashMap;
 
 struct Cutter {
     filters: Vec<RangeMapper>,      // filters that both ends are start-anchored
@@ -186,11 +188,125 @@ impl SegmentStream for FilterStream {
 
         Ok((bytes, from))
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.src.label(tag)
+    }
+}
+
+struct Predicate {
+    expr: String,
+    rpn: Rpn,
+}
+
+impl Predicate {
+    fn new(expr: &str) -> Result<Self> {
+        let vars: HashMap<&[u8], VarAttr> = [
+            (b"b".as_slice(), VarAttr { is_array: true, id: 1 }),
+            (b"h".as_slice(), VarAttr { is_array: true, id: 2 }),
+            (b"i".as_slice(), VarAttr { is_array: true, id: 4 }),
+            (b"l".as_slice(), VarAttr { is_array: true, id: 8 }),
+            (b"pos".as_slice(), VarAttr { is_array: false, id: 16 }),
+            (b"len".as_slice(), VarAttr { is_array: false, id: 17 }),
+            (b"index".as_slice(), VarAttr { is_array: false, id: 18 }),
+        ]
+        .into_iter()
+        .collect();
+
+        let rpn = Rpn::new(expr, Some(&vars))?;
+        let expr = expr.to_string();
+
+        Ok(Predicate { expr, rpn })
+    }
+
+    fn evaluate(&self, stream: &[u8], offset: usize, segment: &Segment, index: usize) -> Result<bool> {
+        let slice = &stream[segment.as_range()];
+        let getter = |id: usize| -> Option<i64> {
+            match id {
+                16 => Some((offset + segment.pos) as i64),
+                17 => Some(segment.len as i64),
+                18 => Some(index as i64),
+                _ => None,
+            }
+        };
+
+        let val =
+            evaluate_on_slice(&self.rpn, slice, getter).map_err(|e| anyhow!("failed on evaluating expression: {:?}: {}", &self.expr, e))?;
+        Ok(val != 0)
+    }
+}
+
+pub struct PredicateStream {
+    src: Box<dyn SegmentStream>,
+    src_scanned: usize,  // relative count in the next segment array
+    src_consumed: usize, // absolute count from the head
+    offset: usize,       // absolute offset of the stream head
+    segments: Vec<Segment>,
+    predicate: Predicate,
+}
+
+impl PredicateStream {
+    pub fn new(src: Box<dyn SegmentStream>, expr: &str) -> Result<Self> {
+        Ok(PredicateStream {
+            src,
+            src_scanned: 0,
+            src_consumed: 0,
+            offset: 0,
+            segments: Vec::new(),
+            predicate: Predicate::new(expr)?,
+        })
+    }
+}
+
+impl SegmentStream for PredicateStream {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
+        let (stream, segments) = self.src.as_slices();
+
+        // evaluate the predicate on the newly found slices; the remaining ones keep the original order
+        for (i, s) in segments[self.src_scanned..count].iter().enumerate() {
+            let index = self.src_consumed + self.src_scanned + i;
+            if self.predicate.evaluate(stream, self.offset, s, index)? {
+                self.segments.push(*s);
+            }
+        }
+        self.src_scanned = count;
+
+        Ok((is_eof, bytes, self.segments.len(), max_consume))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        let (stream, _) = self.src.as_slices();
+        (stream, &self.segments)
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let (bytes, src_count) = self.src.consume(bytes)?;
+        self.src_scanned -= src_count;
+        self.src_consumed += src_count;
+        self.offset += bytes;
+
+        let from = self.segments.partition_point(|x| x.pos < bytes);
+        let to = self.segments.len();
+
+        self.segments.copy_within(from..to, 0);
+        self.segments.truncate(to - from);
+
+        for s in &mut self.segments {
+            s.pos -= bytes;
+        }
+
+        Ok((bytes, from))
+    }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.src.label(tag)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FilterStream;
+    use super::{FilterStream, PredicateStream};
     use crate::byte::tester::*;
     use crate::segment::tester::*;
     use crate::segment::ConstSlicer;
@@ -298,6 +414,67 @@ mod tests {
     test!(test_filter_random_len, test_segment_random_len);
     test!(test_filter_occasional_consume, test_segment_occasional_consume);
 
+    macro_rules! bind_predicate {
+        ( $expr: expr ) => {
+            |pattern: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(pattern));
+                let src = Box::new(ConstSlicer::from_raw(src, (3, 3), (false, false), 4, 2));
+
+                Box::new(PredicateStream::new(src, $expr).unwrap())
+            }
+        };
+    }
+
+    macro_rules! test_predicate {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                // pass all
+                $inner(b"", &bind_predicate!("1"), &[]);
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_predicate!("1"),
+                    &[(3..5).into(), (7..9).into(), (11..13).into(), (15..17).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_predicate!("len == 2"),
+                    &[(3..5).into(), (7..9).into(), (11..13).into(), (15..17).into()],
+                );
+
+                // pass none
+                $inner(b"abcdefghijklmnopqrstu", &bind_predicate!("0"), &[]);
+                $inner(b"abcdefghijklmnopqrstu", &bind_predicate!("b[2]"), &[]);
+
+                // position and index
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_predicate!("pos >= 11"),
+                    &[(11..13).into(), (15..17).into()],
+                );
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_predicate!("index & 1"),
+                    &[(7..9).into(), (15..17).into()],
+                );
+
+                // slice contents
+                $inner(b"abcdefghijklmnopqrstu", &bind_predicate!("b[0] == 0x68"), &[(7..9).into()]);
+                $inner(
+                    b"abcdefghijklmnopqrstu",
+                    &bind_predicate!("b[1] != 0x6d"),
+                    &[(3..5).into(), (7..9).into(), (15..17).into()],
+                );
+                $inner(b"abcdefghijklmnopqrstu", &bind_predicate!("h[0] == 0x6968"), &[(7..9).into()]);
+                $inner(b"abcdefghijklmnopqrstu", &bind_predicate!("i[0]"), &[]);
+            }
+        };
+    }
+
+    test_predicate!(test_predicate_all_at_once, test_segment_all_at_once);
+    test_predicate!(test_predicate_random_len, test_segment_random_len);
+    test_predicate!(test_predicate_occasional_consume, test_segment_occasional_consume);
+
     fn format_spans(spans: &[(usize, usize)], tail: usize, anchors: impl FnMut(usize) -> (usize, usize)) -> String {
         let mut anchors = anchors;
 
@@ -327,6 +504,7 @@ mod tests {
                 segments.push(Segment {
                     pos: i * pitch,
                     len: pitch,
+                    tag: 0,
                 });
             }
         }
@@ -2,70 +2,294 @@
 // @author Hajime Suzuki
 
 use super::{Segment, SegmentStream};
-use crate::byte::ByteStream;
-use crate::params::BLOCK_SIZE;
-use crate::text::parser::TextParser;
+use crate::byte::{ByteStream, PatchSource};
+use crate::params::{BLOCK_SIZE, GUIDE_SORT_SIZE};
+use crate::text::parser::new_record_parser;
 use crate::text::InoutFormat;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, Write};
+
+// offset, span, and label
+type GuideRecord = (usize, usize, Option<Box<[u8]>>);
+
+fn record_size(record: &GuideRecord) -> usize {
+    std::mem::size_of::<GuideRecord>() + record.2.as_ref().map_or(0, |x| x.len())
+}
+
+// (offset, span, label length + 1 or 0 for none, label) in little endian
+fn write_record(dst: &mut impl Write, record: &GuideRecord) -> Result<()> {
+    let label = record.2.as_deref().unwrap_or(&[]);
+    let label_len = record.2.as_ref().map_or(0, |x| x.len() as u64 + 1);
+
+    dst.write_all(&(record.0 as u64).to_le_bytes())?;
+    dst.write_all(&(record.1 as u64).to_le_bytes())?;
+    dst.write_all(&label_len.to_le_bytes())?;
+    dst.write_all(label)?;
+    Ok(())
+}
+
+fn read_record(src: &mut impl Read) -> Result<Option<GuideRecord>> {
+    let mut header = [0u8; 24];
+    match src.read_exact(&mut header) {
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        x => x?,
+    }
+
+    let field = |i: usize| u64::from_le_bytes(header[8 * i..8 * (i + 1)].try_into().unwrap()) as usize;
+    let label = match field(2) {
+        0 => None,
+        len => {
+            let mut label = vec![0u8; len - 1];
+            src.read_exact(&mut label)?;
+            Some(label.into_boxed_slice())
+        }
+    };
+    Ok(Some((field(0), field(1), label)))
+}
+
+// loads the records of the guide and returns them in the order of their offsets; records at the
+// same offset keep their order. records exceeding GUIDE_SORT_SIZE are sorted in runs, spilled to
+// temporary files, and merged.
+struct SortedGuide {
+    src: Box<dyn PatchSource>,
+    is_loaded: bool,
+
+    // if the first GUIDE_SORT_SIZE of the records are sorted, the rest are streamed without
+    // sorting; the slicer checks their order instead
+    is_lazy: bool,
+    is_streaming: bool,
+    buf: Vec<u8>,
+
+    // all the records if they fit in the budget
+    records: std::vec::IntoIter<GuideRecord>,
+
+    // spilled runs, their heads, and the heap of (offset, run index) of the heads
+    runs: Vec<BufReader<File>>,
+    heads: Vec<Option<GuideRecord>>,
+    heap: BinaryHeap<Reverse<(usize, usize)>>,
+
+    // the last record returned
+    curr: Option<GuideRecord>,
+}
+
+impl SortedGuide {
+    fn new(src: Box<dyn PatchSource>, is_lazy: bool) -> Self {
+        SortedGuide {
+            src,
+            is_loaded: false,
+            is_lazy,
+            is_streaming: false,
+            buf: Vec::new(),
+            records: Vec::new().into_iter(),
+            runs: Vec::new(),
+            heads: Vec::new(),
+            heap: BinaryHeap::new(),
+            curr: None,
+        }
+    }
+
+    fn spill(&mut self, records: &mut Vec<GuideRecord>) -> Result<()> {
+        records.sort_by_key(|x| x.0);
+
+        let mut file = BufWriter::new(tempfile::tempfile()?);
+        for record in records.drain(..) {
+            write_record(&mut file, &record)?;
+        }
+        let file = file.into_inner().map_err(|e| e.into_error())?;
+        self.runs.push(BufReader::new(file));
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<()> {
+        let mut records = Vec::new();
+        let mut size = 0;
+        let mut is_sorted = true;
+
+        let mut buf = Vec::new();
+        while let Some((offset, span)) = self.src.read_patch(&mut buf)? {
+            is_sorted &= records.last().map_or(true, |x: &GuideRecord| x.0 <= offset);
+
+            let record = (offset, span, self.src.label().map(|x| x.into()));
+            size += record_size(&record);
+            records.push(record);
+            buf.clear();
+
+            if size >= GUIDE_SORT_SIZE {
+                if self.is_lazy && is_sorted && self.runs.is_empty() {
+                    self.records = records.into_iter();
+                    self.is_streaming = true;
+                    return Ok(());
+                }
+                self.spill(&mut records)?;
+                size = 0;
+            }
+        }
+
+        if self.runs.is_empty() {
+            records.sort_by_key(|x| x.0);
+            self.records = records.into_iter();
+            return Ok(());
+        }
+
+        if !records.is_empty() {
+            self.spill(&mut records)?;
+        }
+        for (i, run) in self.runs.iter_mut().enumerate() {
+            run.rewind()?;
+
+            let head = read_record(run)?;
+            if let Some(head) = &head {
+                self.heap.push(Reverse((head.0, i)));
+            }
+            self.heads.push(head);
+        }
+        Ok(())
+    }
+
+    fn next_record(&mut self) -> Result<Option<GuideRecord>> {
+        if self.runs.is_empty() {
+            let record = self.records.next();
+            if record.is_some() || !self.is_streaming {
+                return Ok(record);
+            }
+
+            self.buf.clear();
+            let record = self.src.read_patch(&mut self.buf)?;
+            return Ok(record.map(|(offset, span)| (offset, span, self.src.label().map(|x| x.into()))));
+        }
+
+        // the smallest offset, then the earliest run
+        let i = match self.heap.pop() {
+            Some(Reverse((_, i))) => i,
+            None => return Ok(None),
+        };
+        let next = read_record(&mut self.runs[i])?;
+        if let Some(next) = &next {
+            self.heap.push(Reverse((next.0, i)));
+        }
+        Ok(std::mem::replace(&mut self.heads[i], next))
+    }
+}
+
+impl PatchSource for SortedGuide {
+    // bytes of the records are not retained as the slicer only needs the ranges
+    fn read_patch(&mut self, _buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        if !std::mem::replace(&mut self.is_loaded, true) {
+            self.load()?;
+        }
+
+        self.curr = self.next_record()?;
+        Ok(self.curr.as_ref().map(|x| (x.0, x.1)))
+    }
+
+    fn label(&self) -> Option<&[u8]> {
+        self.curr.as_ref()?.2.as_deref()
+    }
+
+    fn format_cache(&self) -> String {
+        self.src.format_cache()
+    }
+}
 
 pub struct GuidedSlicer {
     src: Box<dyn ByteStream>,
-    guide: TextParser,
+    guide: SortedGuide,
     buf: Vec<u8>,
     segments: Vec<Segment>,
-    guide_consumed: usize,
     src_consumed: usize,
     max_consume: usize,
+
+    // (offset, span, tag) of the record waiting for the stream to cover it
+    pending: Option<(usize, usize, usize)>,
+    last_offset: usize,
+
+    // names of the records, interned and referred to by the tags of the segments (index + 1)
+    labels: Vec<Vec<u8>>,
+    label_indices: HashMap<Vec<u8>, usize>,
 }
 
 impl GuidedSlicer {
-    pub fn new(src: Box<dyn ByteStream>, guide: Box<dyn ByteStream>) -> Self {
+    pub fn new(src: Box<dyn ByteStream>, guide: Box<dyn ByteStream>, format: &InoutFormat) -> Self {
         GuidedSlicer {
             src,
-            guide: TextParser::new(guide, &InoutFormat::from_str("xxx").unwrap()),
+            guide: SortedGuide::new(new_record_parser(guide, format), true),
             buf: Vec::new(),
             segments: Vec::new(),
-            guide_consumed: 0,
             src_consumed: 0,
             max_consume: 0,
+            pending: None,
+            last_offset: 0,
+            labels: Vec::new(),
+            label_indices: HashMap::new(),
         }
     }
 
-    fn extend_segment_buf(&mut self, is_eof: bool, bytes: usize) -> Result<()> {
-        // all existing segments must be covered as we requested longer
-        debug_assert!(self.segments.last().map_or(0, |x| x.tail()) <= bytes);
+    // sorts all the guide records before slicing; otherwise the records beyond the first
+    // GUIDE_SORT_SIZE must be sorted
+    pub fn with_sort(mut self) -> Self {
+        self.guide.is_lazy = false;
+        self
+    }
+
+    fn intern_label(&mut self) -> usize {
+        let label = match self.guide.label() {
+            Some(label) => label,
+            None => return 0,
+        };
+        if let Some(&index) = self.label_indices.get(label) {
+            return index + 1;
+        }
+
+        self.labels.push(label.to_vec());
+        self.label_indices.insert(label.to_vec(), self.labels.len() - 1);
+        self.labels.len()
+    }
 
+    fn extend_segment_buf(&mut self, is_eof: bool, bytes: usize) -> Result<()> {
         // first update max_consume to the end of the current chunk of the stream
         // (may be shortened in the loop)
         self.max_consume = bytes;
 
         let tail = self.src_consumed + bytes; // in absolute offset
         loop {
-            // read the next guide to the buffer
-            self.buf.clear();
+            let (offset, span, tag) = match self.pending.take() {
+                Some(record) => record,
+                None => {
+                    self.buf.clear();
+                    let (offset, span) = match self.guide.read_patch(&mut self.buf)? {
+                        Some(record) => record,
+                        None => break, // the guide stream reached EOF
+                    };
+                    if offset < self.last_offset {
+                        return Err(anyhow!(
+                            "guide records must be sorted by their offsets if they take more than {} bytes in memory (use --guide-sort) at {}",
+                            GUIDE_SORT_SIZE,
+                            self.guide.format_cache()
+                        ));
+                    }
+                    self.last_offset = offset;
+                    (offset, span, self.intern_label())
+                }
+            };
 
-            let ret = self.guide.read_line(&mut self.buf)?;
-            if ret.is_none() {
-                // the guide stream reached EOF
-                self.guide_consumed = self.segments.len();
+            // the records are sorted by their offsets, so the ones after this are not exposed either
+            if !is_eof && (offset >= tail || offset + span > tail) {
+                self.max_consume = std::cmp::min(bytes, offset - self.src_consumed);
+                self.pending = Some((offset, span, tag));
                 break;
             }
-            let (offset, span) = ret.unwrap();
 
-            // slice the stream out by the guide
-            let pos = offset - self.src_consumed;
-            let len = if is_eof {
-                std::cmp::min(offset + span, tail) - offset
-            } else {
-                span
-            };
-            self.segments.push(Segment { pos, len });
-
-            if offset + span > tail {
-                self.max_consume = std::cmp::min(self.max_consume, pos);
-                self.guide_consumed = self.segments.len() - 1; // do not expose the last segment
-                break;
+            // clip the record at the end of the stream; the ones beyond the end are dropped
+            if offset >= tail {
+                debug_assert!(is_eof);
+                continue;
             }
+            let pos = offset - self.src_consumed;
+            let len = std::cmp::min(offset + span, tail) - offset;
+            self.segments.push(Segment { pos, len, tag });
         }
         Ok(())
     }
@@ -73,17 +297,18 @@ impl GuidedSlicer {
 
 impl SegmentStream for GuidedSlicer {
     fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
-        let request = std::cmp::max(BLOCK_SIZE, self.segments.last().map_or(0, |x| x.tail()));
+        let pending = self.pending.map_or(0, |(offset, span, _)| offset + span - self.src_consumed);
+        let request = std::cmp::max(BLOCK_SIZE, pending);
         let (is_eof, bytes) = self.src.fill_buf(request)?;
 
         self.extend_segment_buf(is_eof, bytes)?;
 
-        Ok((is_eof, bytes, self.guide_consumed, self.max_consume))
+        Ok((is_eof, bytes, self.segments.len(), self.max_consume))
     }
 
     fn as_slices(&self) -> (&[u8], &[Segment]) {
         let stream = self.src.as_slice();
-        (stream, &self.segments[..self.guide_consumed])
+        (stream, &self.segments)
     }
 
     fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
@@ -99,12 +324,15 @@ impl SegmentStream for GuidedSlicer {
         for s in &mut self.segments {
             s.pos -= bytes;
         }
-        self.guide_consumed -= from;
         self.src_consumed += bytes;
         self.max_consume -= bytes;
 
         Ok((bytes, from))
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.labels.get(tag.checked_sub(1)?).map(|x| x.as_slice())
+    }
 }
 
 #[cfg(test)]
@@ -112,9 +340,10 @@ mod tests {
     use super::GuidedSlicer;
     use crate::byte::tester::*;
     use crate::segment::tester::*;
+    use rand::seq::SliceRandom;
     use rand::Rng;
 
-    fn gen_guide(max_len: usize, max_count: usize) -> (Vec<u8>, Vec<Segment>) {
+    fn gen_guide(max_len: usize, max_count: usize, is_shuffled: bool) -> (Vec<u8>, Vec<Segment>) {
         let mut rng = rand::thread_rng();
 
         let mut offset = 0;
@@ -132,29 +361,36 @@ mod tests {
             v.push(Segment {
                 pos: offset,
                 len: std::cmp::min(len, max_len - offset),
+                tag: 0,
             });
         }
 
         v.sort_by_key(|x| (x.pos, x.len));
+        if is_shuffled {
+            v.shuffle(&mut rng);
+        }
 
         let mut s = Vec::new();
         for x in &v {
             s.extend_from_slice(format!("{:x} {:x} | \n", x.pos, x.len).as_bytes());
         }
 
+        // records at the same offset keep their order
+        v.sort_by_key(|x| x.pos);
         (s, v)
     }
 
     macro_rules! test_impl {
-        ( $inner: ident, $len: expr, $count: expr ) => {
+        ( $inner: ident, $len: expr, $count: expr, $is_shuffled: expr, $sort: expr ) => {
             let mut rng = rand::thread_rng();
             let v = (0..$len).map(|_| rng.gen::<u8>()).collect::<Vec<u8>>();
-            let (guide, segments) = gen_guide($len, $count);
+            let (guide, segments) = gen_guide($len, $count, $is_shuffled);
 
             let bind = |x: &[u8]| -> Box<dyn SegmentStream> {
                 let stream = Box::new(MockSource::new(x));
                 let guide = Box::new(MockSource::new(&guide));
-                Box::new(GuidedSlicer::new(stream, guide))
+                let slicer = GuidedSlicer::new(stream, guide, &InoutFormat::from_str("xxx").unwrap());
+                Box::new(if $sort { slicer.with_sort() } else { slicer })
             };
             $inner(&v, &bind, &segments);
         };
@@ -164,18 +400,21 @@ mod tests {
         ( $name: ident, $inner: ident ) => {
             #[test]
             fn $name() {
-                test_impl!($inner, 0, 0);
-                test_impl!($inner, 10, 0);
-                test_impl!($inner, 10, 1);
-
-                test_impl!($inner, 1000, 0);
-                test_impl!($inner, 1000, 1000);
-
-                // try longer, multiple times
-                test_impl!($inner, 100000, 10);
-                test_impl!($inner, 100000, 100);
-                test_impl!($inner, 100000, 1000);
-                test_impl!($inner, 100000, 10000);
+                // shuffled guides are sorted without `with_sort` if the head of them is unsorted
+                for (is_shuffled, sort) in [(false, false), (true, true), (true, false)] {
+                    test_impl!($inner, 0, 0, is_shuffled, sort);
+                    test_impl!($inner, 10, 0, is_shuffled, sort);
+                    test_impl!($inner, 10, 1, is_shuffled, sort);
+
+                    test_impl!($inner, 1000, 0, is_shuffled, sort);
+                    test_impl!($inner, 1000, 1000, is_shuffled, sort);
+
+                    // try longer, multiple times; spilled to temporary files
+                    test_impl!($inner, 100000, 10, is_shuffled, sort);
+                    test_impl!($inner, 100000, 100, is_shuffled, sort);
+                    test_impl!($inner, 100000, 1000, is_shuffled, sort);
+                    test_impl!($inner, 100000, 10000, is_shuffled, sort);
+                }
             }
         };
     }
@@ -183,6 +422,73 @@ mod tests {
     test!(test_guided_all_at_once, test_segment_all_at_once);
     test!(test_guided_random_len, test_segment_random_len);
     test!(test_guided_occasional_consume, test_segment_occasional_consume);
+
+    fn slice_all(guide: &[u8], format: &str, len: usize, sort: bool) -> anyhow::Result<Vec<(usize, usize, Option<String>)>> {
+        let stream = Box::new(MockSource::new(&vec![0u8; len]));
+        let guide = Box::new(MockSource::new(guide));
+        let slicer = GuidedSlicer::new(stream, guide, &InoutFormat::from_str(format).unwrap());
+        let mut slicer = if sort { slicer.with_sort() } else { slicer };
+
+        let mut v = Vec::new();
+        let mut offset = 0;
+        loop {
+            let (is_eof, bytes, count, max_consume) = slicer.fill_segment_buf()?;
+            let (_, segments) = slicer.as_slices();
+            for s in segments[..count].iter().take_while(|s| is_eof || s.pos < max_consume) {
+                let label = slicer.label(s.tag).map(|x| String::from_utf8_lossy(x).to_string());
+                v.push((offset + s.pos, s.len, label));
+            }
+            offset += slicer.consume(bytes)?.0;
+            if is_eof {
+                break;
+            }
+        }
+        Ok(v)
+    }
+
+    #[test]
+    fn test_guided_labels() {
+        let guide = b"chr1\t16\t19\tfox\nchr1\t40\t43\tdog\nchr1\t48\t50\tfox\n";
+        let v = slice_all(guide, "bed", 64, false).unwrap();
+        let fox = Some("fox".to_string());
+        assert_eq!(v, [(16, 3, fox.clone()), (40, 3, Some("dog".to_string())), (48, 2, fox)]);
+
+        let v = slice_all(b"16,3\n", "csv", 64, false).unwrap();
+        assert_eq!(v, [(16, 3, None)]);
+    }
+
+    #[test]
+    fn test_guided_unsorted() {
+        // unsorted and overlapping; records beyond the end are clipped or dropped
+        let guide = b"start,length,name\n40,3,dog\n16,3,fox\n60,8,tail\n16,1,f\n17,30,long\n64,1,out\n";
+        let v = slice_all(guide, "csv", 64, true).unwrap();
+
+        let expected = [
+            (16, 3, Some("fox")),
+            (16, 1, Some("f")),
+            (17, 30, Some("long")),
+            (40, 3, Some("dog")),
+            (60, 4, Some("tail")),
+        ];
+        assert_eq!(v, expected.map(|(x, y, z)| (x, y, z.map(|z| z.to_string()))));
+
+        // short guides are sorted without --guide-sort
+        assert_eq!(slice_all(guide, "csv", 64, false).unwrap(), v);
+
+        // records out of order after the sorted head of a long guide
+        let mut guide: Vec<u8> = (0..1000).flat_map(|x| format!("{x:x} 1\n").into_bytes()).collect();
+        guide.extend_from_slice(b"0 1\n");
+        assert!(slice_all(&guide, "xxx", 1000, false).is_err());
+        assert_eq!(slice_all(&guide, "xxx", 1000, true).unwrap().len(), 1001);
+    }
+
+    #[test]
+    fn test_guided_empty_records() {
+        // empty records at the end of the chunks of the stream are kept
+        let guide: Vec<u8> = (0..=1000).flat_map(|x| format!("{x:x} 0\n").into_bytes()).collect();
+        let v = slice_all(&guide, "xxx", 1000, false).unwrap();
+        assert_eq!(v, (0..1000).map(|x| (x, 0, None)).collect::<Vec<_>>());
+    }
 }
 
 // enf of file.rs
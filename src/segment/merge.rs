@@ -10,6 +10,7 @@ struct Accumulator {
     count: usize,
     start: usize,
     end: usize,
+    tag: usize, // inherited from the first segment of the pile
 
     // params
     merge_threshold: usize,
@@ -21,14 +22,16 @@ impl Accumulator {
             count: 0,
             start: usize::MAX,
             end: usize::MAX,
+            tag: 0,
             merge_threshold,
         }
     }
 
-    fn init(&mut self, start: usize, end: usize) {
+    fn init(&mut self, segment: &Segment) {
         self.count = 1;
-        self.start = start;
-        self.end = end;
+        self.start = segment.pos;
+        self.end = segment.tail();
+        self.tag = segment.tag;
     }
 
     fn pop(&mut self) -> Segment {
@@ -41,7 +44,11 @@ impl Accumulator {
         self.start = usize::MAX;
         self.end = usize::MAX;
 
-        Segment { pos, len: tail - pos }
+        Segment {
+            pos,
+            len: tail - pos,
+            tag: self.tag,
+        }
     }
 
     fn resume(&mut self, segment: &Segment) -> bool {
@@ -52,7 +59,7 @@ impl Accumulator {
         }
 
         // the accumulator is empty, and it's initialized with the first segment
-        self.init(segment.pos, segment.tail());
+        self.init(segment);
         true
     }
 
@@ -75,7 +82,7 @@ impl Accumulator {
         }
 
         let popped = self.pop();
-        self.init(start, end);
+        self.init(segment);
 
         Some(popped)
     }
@@ -203,6 +210,10 @@ impl SegmentStream for MergeStream {
 
         Ok((bytes, from))
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.src.label(tag)
+    }
 }
 
 #[cfg(test)]
@@ -287,6 +298,7 @@ mod tests {
                 v.push(Segment {
                     pos: p.pos + offset,
                     len: p.len,
+                    tag: 0,
                 });
             }
         }
@@ -319,7 +331,7 @@ mod tests {
             let bind = |x: &[u8]| -> Box<dyn SegmentStream> {
                 let src = Box::new(MockSource::new(x));
                 let guide = Box::new(MockSource::new(&guide));
-                let src = Box::new(GuidedSlicer::new(src, guide));
+                let src = Box::new(GuidedSlicer::new(src, guide, &InoutFormat::from_str("xxx").unwrap()));
                 Box::new(MergeStream::new(src, $merge))
             };
 
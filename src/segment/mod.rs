@@ -2,26 +2,40 @@
 // @author Hajime Suzuki
 // @date 2022/2/4
 
+mod approx;
 mod bridge;
+mod cdc;
 mod exact;
 mod extend;
 mod filter;
 mod guided;
 mod merge;
+mod pattern;
 mod range;
 mod regex;
+mod region;
+mod split;
 mod stride;
+mod strings;
+mod tap;
 mod walk;
 
+pub use self::approx::{ApproxMatchSlicer, Distance};
 pub use self::bridge::BridgeStream;
+pub use self::cdc::CdcSlicer;
 pub use self::exact::ExactMatchSlicer;
 pub use self::extend::ExtendStream;
-pub use self::filter::FilterStream;
+pub use self::filter::{FilterStream, PredicateStream};
 pub use self::guided::GuidedSlicer;
 pub use self::merge::MergeStream;
+pub use self::pattern::parse_replacement;
 pub use self::range::RangeSlicer;
-pub use self::regex::RegexSlicer;
+pub use self::regex::{RegexSlicer, StreamRegexSlicer};
+pub use self::region::RegionSlicer;
+pub use self::split::SplitSlicer;
 pub use self::stride::{ConstSlicer, ConstSlicerParams};
+pub use self::strings::StringsSlicer;
+pub use self::tap::{MatchRanges, MatchTap};
 pub use self::walk::WalkSlicer;
 
 use anyhow::Result;
@@ -37,6 +51,7 @@ use rand::Rng;
 pub struct Segment {
     pub pos: usize,
     pub len: usize,
+    pub tag: usize, // 0 for untagged; otherwise resolved into a label by SegmentStream::label
 }
 
 impl Segment {
@@ -54,6 +69,7 @@ impl From<Range<usize>> for Segment {
         Segment {
             pos: other.start,
             len: other.len(),
+            tag: 0,
         }
     }
 }
@@ -69,6 +85,11 @@ pub trait SegmentStream: Send {
 
     // (#bytes, #segments)
     fn consume(&mut self, bytes: usize) -> Result<(usize, usize)>;
+
+    // label text for a non-zero tag; segment filters forward the query to their source
+    fn label(&self, _tag: usize) -> Option<&[u8]> {
+        None
+    }
 }
 
 impl<T: SegmentStream + ?Sized> SegmentStream for Box<T> {
@@ -83,6 +104,10 @@ impl<T: SegmentStream + ?Sized> SegmentStream for Box<T> {
     fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
         (**self).consume(bytes)
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        (**self).label(tag)
+    }
 }
 
 #[cfg(test)]
@@ -255,6 +280,7 @@ pub mod tester {
     pub use super::stride::ConstSlicer;
     pub use super::{test_segment_all_at_once, test_segment_occasional_consume, test_segment_random_len, Segment, SegmentStream};
     pub use crate::byte::tester::*;
+    pub use crate::text::InoutFormat;
 }
 
 // end of mod.rs
@@ -0,0 +1,450 @@
+// @file pattern.rs
+// @author Hajime Suzuki
+// @brief byte pattern parser for the search slicers
+
+use crate::eval::parse_int;
+use anyhow::{anyhow, Context, Result};
+use std::ops::Range;
+
+fn parse_nibble(x: u8) -> Option<(u8, u8)> {
+    // (value, mask); '?' matches any nibble
+    match x {
+        b'0'..=b'9' => Some((x - b'0', 0x0f)),
+        b'a'..=b'f' => Some((x - b'a' + 10, 0x0f)),
+        b'A'..=b'F' => Some((x - b'A' + 10, 0x0f)),
+        b'?' => Some((0, 0)),
+        _ => None,
+    }
+}
+
+fn parse_byte(x: &[u8]) -> Option<(u8, u8)> {
+    let (hi, hi_mask) = parse_nibble(x[0])?;
+    let (lo, lo_mask) = parse_nibble(x[1])?;
+    Some(((hi << 4) | lo, (hi_mask << 4) | lo_mask))
+}
+
+// splits `s` at `delim`s that are not in a quoted string
+fn split_unquoted(s: &str, delim: u8) -> Vec<&str> {
+    let mut v = Vec::new();
+    let mut start = 0;
+    let mut is_quoted = false;
+    let mut is_escaped = false;
+
+    for (i, &x) in s.as_bytes().iter().enumerate() {
+        match x {
+            _ if is_escaped => is_escaped = false,
+            b'\\' if is_quoted => is_escaped = true,
+            b'"' => is_quoted = !is_quoted,
+            _ if x == delim && !is_quoted => {
+                v.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    v.push(&s[start..]);
+    v
+}
+
+enum Unit {
+    Char(char),
+    Byte(u8),
+}
+
+// parses a quoted string at the head of `s`; returns the units and the remainder after the closing quote
+fn parse_quoted(s: &str) -> Result<(Vec<Unit>, &str)> {
+    debug_assert!(s.starts_with('"'));
+
+    let parse_hex = |digits: &str| -> Option<u32> {
+        if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|x| x.is_ascii_hexdigit()) {
+            return None;
+        }
+        u32::from_str_radix(digits, 16).ok()
+    };
+
+    let mut v = Vec::new();
+    let mut it = s.char_indices().skip(1);
+
+    while let Some((i, x)) = it.next() {
+        let unit = match x {
+            '"' => return Ok((v, &s[i + 1..])),
+            '\\' => match it.next().map(|x| x.1) {
+                Some('n') => Unit::Char('\n'),
+                Some('r') => Unit::Char('\r'),
+                Some('t') => Unit::Char('\t'),
+                Some('0') => Unit::Char('\0'),
+                Some(x @ ('\\' | '"' | '\'')) => Unit::Char(x),
+                Some('x') => {
+                    let digits: String = it.by_ref().take(2).map(|x| x.1).collect();
+                    let n = parse_hex(&digits).filter(|_| digits.len() == 2);
+                    Unit::Byte(n.ok_or_else(|| anyhow!("\\x must be followed by two hex digits in {s:?}"))? as u8)
+                }
+                Some('u') => {
+                    if it.next().map(|x| x.1) != Some('{') {
+                        return Err(anyhow!("\\u must be followed by {{HEX}} in {s:?}"));
+                    }
+                    let digits: String = it.by_ref().map(|x| x.1).take_while(|&x| x != '}').collect();
+                    let c = parse_hex(&digits).and_then(char::from_u32);
+                    Unit::Char(c.ok_or_else(|| anyhow!("invalid unicode escape \\u{{{digits}}} found in {s:?}"))?)
+                }
+                Some(x) => return Err(anyhow!("unknown escape sequence \\{x} found in {s:?}")),
+                None => break,
+            },
+            x => Unit::Char(x),
+        };
+        v.push(unit);
+    }
+    Err(anyhow!("unterminated string found in {s:?}"))
+}
+
+fn encode_string(encoding: &str, units: &[Unit]) -> Result<Vec<u8>> {
+    let mut v = Vec::new();
+    let mut buf = [0u16; 2];
+
+    for unit in units {
+        match (encoding, unit) {
+            // \xHH is a raw byte in UTF-8 strings
+            ("utf8", Unit::Byte(x)) => v.push(*x),
+            ("utf8", Unit::Char(c)) => v.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            ("utf16le" | "utf16be", unit) => {
+                let units = match unit {
+                    Unit::Byte(x) => {
+                        buf[0] = *x as u16;
+                        &buf[..1]
+                    }
+                    Unit::Char(c) => &*c.encode_utf16(&mut buf),
+                };
+                for x in units {
+                    let x = if encoding == "utf16le" { x.to_le_bytes() } else { x.to_be_bytes() };
+                    v.extend_from_slice(&x);
+                }
+            }
+            _ => return Err(anyhow!("unknown string encoding {encoding:?} (utf8, utf16le, or utf16be expected)")),
+        }
+    }
+    Ok(v)
+}
+
+// an unsigned integer literal, e.g. "0xffff_ffff_ffff_ffff"; no arithmetic
+fn parse_u64_literal(val: &str) -> Option<u64> {
+    let (radix, digits) = match val.get(..2) {
+        Some("0x" | "0X") => (16, &val[2..]),
+        Some("0b" | "0B") => (2, &val[2..]),
+        Some("0d" | "0D") => (10, &val[2..]),
+        _ => (10, val),
+    };
+    if digits.starts_with(['_', '+']) || digits.ends_with('_') || digits.contains("__") {
+        return None;
+    }
+    u64::from_str_radix(&digits.replace('_', ""), radix).ok()
+}
+
+fn encode_number(ty: &str, val: &str) -> Result<Vec<u8>> {
+    if ty.is_empty() || !ty.is_ascii() {
+        return Err(anyhow!("unknown type {ty:?} (e.g., u8, i16le, u32be, or f64le expected)"));
+    }
+
+    let (kind, rest) = ty.split_at(1);
+    let (bits, is_be) = match (rest.strip_suffix("le"), rest.strip_suffix("be")) {
+        (Some(bits), _) => (bits, Some(false)),
+        (_, Some(bits)) => (bits, Some(true)),
+        _ => (rest, None),
+    };
+
+    let bits: usize = match (kind, bits, is_be) {
+        ("u" | "i", "8", None) => 8,
+        ("u" | "i", "16" | "32" | "64", Some(_)) | ("f", "32" | "64", Some(_)) => bits.parse().unwrap(),
+        _ => return Err(anyhow!("unknown type {ty:?} (e.g., u8, i16le, u32be, or f64le expected)")),
+    };
+
+    let bytes = if kind == "f" {
+        let x: f64 = val
+            .parse()
+            .with_context(|| format!("failed to parse {val:?} as a floating-point number"))?;
+        if bits == 32 {
+            (x as f32).to_le_bytes().to_vec()
+        } else {
+            x.to_le_bytes().to_vec()
+        }
+    } else {
+        // the expression engine works on i64; unsigned literals beyond i64::MAX are parsed separately
+        let x = match parse_int(val) {
+            Ok(x) => x as i128,
+            Err(e) => match parse_u64_literal(val) {
+                Some(x) if kind == "u" => x as i128,
+                _ => return Err(e).with_context(|| format!("failed to evaluate {val:?} as an integer")),
+            },
+        };
+        let (min, max) = match kind {
+            "u" => (0, (1 << bits) - 1),
+            _ => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+        };
+        if x < min || x > max {
+            return Err(anyhow!("{val:?} is out of the range of {ty}"));
+        }
+        x.to_le_bytes()[..bits / 8].to_vec()
+    };
+
+    if is_be == Some(true) {
+        return Ok(bytes.into_iter().rev().collect());
+    }
+    Ok(bytes)
+}
+
+pub struct Pattern {
+    // bytes are stored masked
+    pub bytes: Vec<u8>,
+    pub mask: Vec<u8>,
+
+    // the longest run of unmasked bytes; located by the automaton, then the whole pattern is compared
+    pub anchor: Range<usize>,
+    pub tag: usize,
+}
+
+impl Pattern {
+    fn push_hex(&mut self, token: &str) -> Result<()> {
+        // space-separated hex bytes, e.g. "1f 8b 08 ??", "1f8b08", "4? ?0", or "80/c0" for explicit masks
+        let (hex, explicit_mask) = match token.split_once('/') {
+            Some((hex, explicit_mask)) => (hex.as_bytes(), Some(explicit_mask.as_bytes())),
+            None => (token.as_bytes(), None),
+        };
+        if hex.len() % 2 != 0 {
+            return Err(anyhow!("odd number of digits found in {token:?}"));
+        }
+
+        for x in hex.chunks(2) {
+            let (x, m) = parse_byte(x).ok_or_else(|| anyhow!("invalid hex digit found in {token:?}"))?;
+            self.bytes.push(x);
+            self.mask.push(m);
+        }
+
+        if let Some(explicit_mask) = explicit_mask {
+            if hex.len() != 2 || explicit_mask.len() != 2 {
+                return Err(anyhow!("mask must be a single byte applied to a single byte (got: {token:?})"));
+            }
+            let (m, full) = parse_byte(explicit_mask).ok_or_else(|| anyhow!("invalid hex digit found in {token:?}"))?;
+            if full != 0xff {
+                return Err(anyhow!("mask must not contain wildcards (got: {token:?})"));
+            }
+            *self.mask.last_mut().unwrap() &= m;
+        }
+        Ok(())
+    }
+
+    fn push_exact(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+        self.mask.resize(self.bytes.len(), 0xff);
+    }
+
+    pub fn from_str(pattern: &str, tag: usize) -> Result<Self> {
+        let mut p = Pattern {
+            bytes: Vec::new(),
+            mask: Vec::new(),
+            anchor: 0..0,
+            tag,
+        };
+
+        // a sequence of hex bytes, strings ("...", utf16le:"..."), and typed numbers (u32le:0xdeadbeef)
+        let mut rem = pattern.trim_start();
+        while !rem.is_empty() {
+            let len = rem.find(char::is_whitespace).unwrap_or(rem.len());
+            let ty = rem[..len].split_once(':').map(|x| x.0);
+
+            match (rem.starts_with('"'), ty) {
+                (true, _) => {
+                    let (units, next) = parse_quoted(rem)?;
+                    p.push_exact(&encode_string("utf8", &units)?);
+                    rem = next;
+                }
+                (false, Some(ty)) if rem[ty.len() + 1..].starts_with('"') => {
+                    let (units, next) = parse_quoted(&rem[ty.len() + 1..])?;
+                    p.push_exact(&encode_string(ty, &units)?);
+                    rem = next;
+                }
+                (false, Some(ty)) => {
+                    p.push_exact(&encode_number(ty, &rem[ty.len() + 1..len])?);
+                    rem = &rem[len..];
+                }
+                (false, None) => {
+                    p.push_hex(&rem[..len])?;
+                    rem = &rem[len..];
+                }
+            }
+            rem = rem.trim_start();
+        }
+
+        for (x, m) in p.bytes.iter_mut().zip(&p.mask) {
+            *x &= *m;
+        }
+
+        // find the longest unmasked run
+        let mut start = 0;
+        for (i, &m) in p.mask.iter().chain(&[0]).enumerate() {
+            if m == 0xff {
+                continue;
+            }
+            if i - start > p.anchor.len() {
+                p.anchor = start..i;
+            }
+            start = i + 1;
+        }
+
+        Ok(p)
+    }
+
+    pub fn is_match(&self, stream: &[u8]) -> bool {
+        if stream.len() < self.bytes.len() {
+            return false;
+        }
+        let stream = &stream[..self.bytes.len()];
+        stream.iter().zip(&self.mask).zip(&self.bytes).all(|((x, m), y)| (x & m) == *y)
+    }
+}
+
+// parses "PAT[,NAME=PAT,...]" into patterns and labels. named patterns are tagged with the
+// index of the name in the labels plus one. empty patterns, which never match, are removed.
+pub fn parse_patterns(patterns: &str) -> Result<(Vec<Pattern>, Vec<String>)> {
+    let mut v = Vec::new();
+    let mut labels = Vec::new();
+
+    for pattern in split_unquoted(patterns, b',') {
+        let name = pattern.split_once('=').map(|x| x.0).filter(|x| !x.contains(['"', ':']));
+        let pattern = match name {
+            Some(name) => &pattern[name.len() + 1..],
+            None => pattern,
+        };
+
+        let tag = match name.map(|x| x.trim()) {
+            Some("") => return Err(anyhow!("empty name found in {patterns:?}")),
+            Some(name) => {
+                labels.push(name.to_string());
+                labels.len()
+            }
+            None => 0,
+        };
+
+        let pattern = Pattern::from_str(pattern, tag).with_context(|| format!("failed to parse {pattern:?} into bytes"))?;
+        if !pattern.bytes.is_empty() {
+            v.push(pattern);
+        }
+    }
+    Ok((v, labels))
+}
+
+// splits "FROM:TO" into the patterns and the replacement bytes. ':' may also appear in the typed
+// numbers, so it's split at the first unquoted ':' where both sides are valid.
+pub fn parse_replacement(s: &str) -> Result<(String, Vec<u8>)> {
+    let pieces = split_unquoted(s, b':');
+
+    for i in 1..pieces.len() {
+        let (from, to) = (pieces[..i].join(":"), pieces[i..].join(":"));
+        if !matches!(parse_patterns(&from), Ok((patterns, _)) if !patterns.is_empty()) {
+            continue;
+        }
+        match Pattern::from_str(&to, 0) {
+            Ok(to) if to.mask.iter().all(|&x| x == 0xff) => return Ok((from, to.bytes)),
+            _ => continue,
+        }
+    }
+    Err(anyhow!("failed to parse {s:?} as FROM:TO (TO must not contain wildcards)"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_patterns, parse_replacement};
+
+    #[test]
+    fn test_parse_patterns() {
+        macro_rules! test {
+            ( $input: expr, $expected: expr ) => {{
+                let (patterns, _) = parse_patterns($input).unwrap();
+                let patterns: Vec<_> = patterns.iter().map(|x| (x.bytes.as_slice(), x.mask.as_slice())).collect();
+                let expected: &[(&[u8], &[u8])] = $expected;
+                assert_eq!(patterns, expected, "{:?}", $input);
+            }};
+        }
+
+        // hex
+        test!("", &[]);
+        test!("61 62", &[(b"ab", b"\xff\xff")]);
+        test!("6162, 63", &[(b"ab", b"\xff\xff"), (b"c", b"\xff")]);
+        test!("6? ?2 ?? 81/c3", &[(b"\x60\x02\x00\x81", b"\xf0\x0f\x00\xc3")]);
+
+        // strings
+        test!("\"ab\"", &[(b"ab", b"\xff\xff")]);
+        test!("\"PK\\x03\\x04\"", &[(b"PK\x03\x04", b"\xff\xff\xff\xff")]);
+        test!("\"a,b\" 63", &[(b"a,b\x63", b"\xff\xff\xff\xff")]);
+        test!("\"\\\"\\\\\\n\\0\"", &[(b"\"\\\n\0", b"\xff\xff\xff\xff")]);
+        test!("\"\\xff\\u{ff}\"", &[(b"\xff\xc3\xbf", b"\xff\xff\xff")]);
+        test!("utf8:\"\u{e9}\"", &[(b"\xc3\xa9", b"\xff\xff")]);
+        test!("utf16le:\"a\u{e9}\"", &[(b"a\0\xe9\0", b"\xff\xff\xff\xff")]);
+        test!("utf16be:\"\\u{1f600}\"", &[(b"\xd8\x3d\xde\x00", b"\xff\xff\xff\xff")]);
+
+        // numbers
+        test!("u8:0x7f i8:-1", &[(b"\x7f\xff", b"\xff\xff")]);
+        test!("u16le:0x1234 u16be:0x1234", &[(b"\x34\x12\x12\x34", b"\xff\xff\xff\xff")]);
+        test!("u32le:0xdeadbeef", &[(b"\xef\xbe\xad\xde", b"\xff\xff\xff\xff")]);
+        test!("i32be:-2", &[(b"\xff\xff\xff\xfe", b"\xff\xff\xff\xff")]);
+        test!("u64le:1+2", &[(b"\x03\0\0\0\0\0\0\0", b"\xff\xff\xff\xff\xff\xff\xff\xff")]);
+        test!("f32le:1.0", &[(b"\0\0\x80\x3f", b"\xff\xff\xff\xff")]);
+        test!(
+            "u64le:0xffff_ffff_ffff_fffe",
+            &[(b"\xfe\xff\xff\xff\xff\xff\xff\xff", b"\xff\xff\xff\xff\xff\xff\xff\xff")]
+        );
+        test!(
+            "u64be:18446744073709551615",
+            &[(b"\xff\xff\xff\xff\xff\xff\xff\xff", b"\xff\xff\xff\xff\xff\xff\xff\xff")]
+        );
+
+        // names
+        let (patterns, labels) = parse_patterns("a=61, \"=\", b=\"=\" ?? , u8:1==1").unwrap();
+        assert_eq!(patterns.iter().map(|x| x.tag).collect::<Vec<_>>(), [1, 0, 2, 0]);
+        assert_eq!(patterns[3].bytes, b"\x01");
+        assert_eq!(labels, ["a", "b"]);
+
+        // errors
+        assert!(parse_patterns("6").is_err());
+        assert!(parse_patterns("6g").is_err());
+        assert!(parse_patterns("80/c?").is_err());
+        assert!(parse_patterns("6162/ff").is_err());
+        assert!(parse_patterns("u64le:0x1_0000_0000_0000_0000").is_err());
+        assert!(parse_patterns("u64le:-1").is_err());
+        assert!(parse_patterns("i64le:0xffffffffffffffff").is_err());
+        assert!(parse_patterns("u8:0x100").is_err());
+        assert!(parse_patterns("=61").is_err());
+        assert!(parse_patterns("\"abc").is_err());
+        assert!(parse_patterns("\"\\q\"").is_err());
+        assert!(parse_patterns("\"\\x4\"").is_err());
+        assert!(parse_patterns("utf32:\"a\"").is_err());
+        assert!(parse_patterns("u8:256").is_err());
+        assert!(parse_patterns("i8:-129").is_err());
+        assert!(parse_patterns("u16:1").is_err());
+        assert!(parse_patterns("u8le:1").is_err());
+        assert!(parse_patterns("u24le:1").is_err());
+        assert!(parse_patterns(":1").is_err());
+    }
+
+    #[test]
+    fn test_parse_replacement() {
+        macro_rules! test {
+            ( $input: expr, $from: expr, $to: expr ) => {{
+                let (from, to) = parse_replacement($input).unwrap();
+                assert_eq!((from.as_str(), to.as_slice()), ($from, $to.as_slice()), "{:?}", $input);
+            }};
+        }
+
+        test!("6f:4f 4f", "6f", b"OO");
+        test!("6f 6f:", "6f 6f", b"");
+        test!("?f:00", "?f", b"\0");
+        test!("\"a:b\":\"c\"", "\"a:b\"", b"c");
+        test!("u16le:1:u16be:1", "u16le:1", b"\0\x01");
+        test!("x=61,62:63", "x=61,62", b"c");
+
+        assert!(parse_replacement("6f").is_err());
+        assert!(parse_replacement(":6f").is_err());
+        assert!(parse_replacement("6f:?f").is_err());
+        assert!(parse_replacement("6f:xyz").is_err());
+    }
+}
+
+// end of pattern.rs
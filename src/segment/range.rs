@@ -91,6 +91,7 @@ impl Cutter {
             v.push(Segment {
                 pos: start,
                 len: end - start,
+                tag: 0,
             });
         }
         v.dedup();
@@ -266,6 +267,7 @@ mod tests {
             v.push(Segment {
                 pos: start,
                 len: end - start,
+                tag: 0,
             });
             let anchor_range = if start < len / 2 { 1 } else { 4 };
 
@@ -3,54 +3,176 @@
 // @brief regex slicer
 
 use super::{Segment, SegmentStream};
-use anyhow::Result;
-use regex::bytes::{Match, Regex};
+use crate::byte::ByteStream;
+use crate::params::BLOCK_SIZE;
+use anyhow::{anyhow, Result};
+use regex::bytes::{CaptureLocations, Regex};
+
+// compiled regex and the capture groups to slice out
+struct Matcher {
+    re: Regex,
+    locs: CaptureLocations,
+
+    // (group index, tag) pairs; the whole match is sliced out if empty
+    groups: Vec<(usize, usize)>,
+    labels: Vec<String>,
+}
+
+impl Matcher {
+    fn new(pattern: &str, groups: Option<&str>) -> Result<Self> {
+        let re = Regex::new(pattern)?;
+        let locs = re.capture_locations();
+
+        // groups specified by name are tagged with the name
+        let mut labels = Vec::new();
+        let mut indices = Vec::new();
+        for group in groups.unwrap_or("").split(',').map(|x| x.trim()).filter(|x| !x.is_empty()) {
+            if let Ok(index) = group.parse::<usize>() {
+                if index >= re.captures_len() {
+                    return Err(anyhow!("group {index} not found in {pattern:?}."));
+                }
+                indices.push((index, 0));
+                continue;
+            }
+
+            let index = re.capture_names().position(|x| x == Some(group));
+            let index = index.ok_or_else(|| anyhow!("group {group:?} not found in {pattern:?}."))?;
+            labels.push(group.to_string());
+            indices.push((index, labels.len()));
+        }
+
+        Ok(Matcher {
+            re,
+            locs,
+            groups: indices,
+            labels,
+        })
+    }
+
+    // finds the non-overlapping matches starting in [start, end) of `haystack`, and returns the
+    // position where the next search should start. the bytes before `start` are visible as the
+    // context of `^`, `\b`, etc. an empty match at `end` is also reported if `is_tail_inclusive`.
+    // the slices are pushed to `v` with their positions offset by `base`.
+    fn find(
+        &mut self,
+        haystack: &[u8],
+        range: (usize, usize),
+        is_tail_inclusive: bool,
+        base: usize,
+        tag: usize,
+        v: &mut Vec<Segment>,
+    ) -> usize {
+        let (start, end) = range;
+        let mut pos = start;
+        while pos <= end {
+            let m = if self.groups.is_empty() {
+                self.re.find_at(haystack, pos)
+            } else {
+                self.re.captures_read_at(&mut self.locs, haystack, pos)
+            };
+            let m = match m {
+                Some(m) if m.start() < end || (is_tail_inclusive && m.is_empty() && m.start() == end) => m,
+                _ => return std::cmp::max(pos, end),
+            };
+
+            if self.groups.is_empty() {
+                v.push(Segment {
+                    pos: base + m.start(),
+                    len: m.len(),
+                    tag,
+                });
+            } else {
+                // groups not participating in the match are skipped
+                let from = v.len();
+                for &(index, tag) in &self.groups {
+                    if let Some((s, e)) = self.locs.get(index) {
+                        v.push(Segment {
+                            pos: base + s,
+                            len: e - s,
+                            tag,
+                        });
+                    }
+                }
+                v[from..].sort_by_key(|x| x.pos);
+            }
+
+            pos = if m.is_empty() { m.end() + 1 } else { m.end() };
+        }
+        pos
+    }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        let label = self.labels.get(tag.checked_sub(1)?)?;
+        Some(label.as_bytes())
+    }
+}
 
 pub struct RegexSlicer {
     src: Box<dyn SegmentStream>,
     matches: Vec<Segment>,
-    scanned: usize,
-    re: Regex,
+
+    // #segments that are not consumed in the source but already matched
+    src_scanned: usize,
+    max_consume: usize,
+
+    matcher: Matcher,
+
+    // the regex sees `window.0` bytes before and `window.1` bytes after each slice
+    window: (usize, usize),
 }
 
 impl RegexSlicer {
-    pub fn new(src: Box<dyn SegmentStream>, pattern: &str) -> Result<Self> {
+    pub fn new(src: Box<dyn SegmentStream>, pattern: &str, window: (usize, usize), groups: Option<&str>) -> Result<Self> {
         let matches = Vec::new();
-        let re = Regex::new(pattern)?;
+        let matcher = Matcher::new(pattern, groups)?;
 
         Ok(RegexSlicer {
             src,
             matches,
-            scanned: 0,
-            re,
+            src_scanned: 0,
+            max_consume: 0,
+            matcher,
+            window,
         })
     }
 }
 
 impl SegmentStream for RegexSlicer {
     fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
-        let to_segment = |m: Match, pos: usize| -> Segment {
-            Segment {
-                pos: pos + m.start(),
-                len: m.range().len(),
-            }
-        };
-
         let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
+        self.max_consume = max_consume;
 
         let (stream, segments) = self.src.as_slices();
-        for s in &segments[..count] {
-            if s.pos < self.scanned {
-                continue;
+        let from = self.matches.len();
+        while self.src_scanned < count {
+            let s = &segments[self.src_scanned];
+
+            // matches start in the slice, and may extend to the look-ahead window after it
+            if !is_eof && s.tail() + self.window.1 > bytes {
+                self.max_consume = std::cmp::min(self.max_consume, s.pos);
+                break;
             }
 
-            self.matches
-                .extend(self.re.find_iter(&stream[s.as_range()]).map(|x| to_segment(x, s.pos)));
+            let head = s.pos.saturating_sub(self.window.0);
+            let tail = std::cmp::min(s.tail() + self.window.1, bytes);
+
+            // the whole matches inherit the tag of the slice
+            let haystack = &stream[head..tail];
+            let range = (s.pos - head, s.tail() - head);
+            self.matcher.find(haystack, range, true, head, s.tag, &mut self.matches);
+            self.src_scanned += 1;
         }
 
-        self.scanned += bytes;
+        // groups in the look-ahead window may precede the matches in the next slice
+        if !self.matcher.groups.is_empty() && self.window.1 > 0 {
+            self.matches[from..].sort_by_key(|x| x.pos);
+        }
 
-        Ok((is_eof, bytes, self.matches.len(), max_consume))
+        // keep the look-behind window of the slices to come
+        if !is_eof {
+            self.max_consume = self.max_consume.saturating_sub(self.window.0);
+        }
+        Ok((is_eof, bytes, self.matches.len(), self.max_consume))
     }
 
     fn as_slices(&self) -> (&[u8], &[Segment]) {
@@ -59,10 +181,10 @@ impl SegmentStream for RegexSlicer {
     }
 
     fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
-        let (bytes, _) = self.src.consume(bytes)?;
-        if bytes == 0 {
-            return Ok((0, 0));
-        }
+        let bytes = std::cmp::min(bytes, self.max_consume);
+        let (bytes, src_count) = self.src.consume(bytes)?;
+        self.src_scanned -= src_count;
+        self.max_consume -= bytes;
 
         // determine how many bytes to consume...
         let from = self.matches.partition_point(|x| x.pos < bytes);
@@ -75,15 +197,107 @@ impl SegmentStream for RegexSlicer {
             m.pos -= bytes;
         }
 
+        Ok((bytes, from))
+    }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        if self.matcher.groups.is_empty() {
+            self.src.label(tag)
+        } else {
+            self.matcher.label(tag)
+        }
+    }
+}
+
+// matches the regex on the entire stream
+pub struct StreamRegexSlicer {
+    src: Box<dyn ByteStream>,
+    matches: Vec<Segment>,
+
+    // the next search starts at `scanned`
+    scanned: usize,
+    max_consume: usize,
+
+    matcher: Matcher,
+
+    // (look-behind, look-ahead); the regex sees at least `window.0` bytes before the search
+    // position, and the matches must not be longer than `window.1` bytes
+    window: (usize, usize),
+}
+
+impl StreamRegexSlicer {
+    pub fn new(src: Box<dyn ByteStream>, pattern: &str, window: (usize, usize), groups: Option<&str>) -> Result<Self> {
+        let matcher = Matcher::new(pattern, groups)?;
+
+        // at least one byte is needed for `^` and `\b` not to match at the head of the buffer
+        let window = (std::cmp::max(window.0, 1), std::cmp::max(window.1, 1));
+
+        Ok(StreamRegexSlicer {
+            src,
+            matches: Vec::new(),
+            scanned: 0,
+            max_consume: 0,
+            matcher,
+            window,
+        })
+    }
+}
+
+impl SegmentStream for StreamRegexSlicer {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        // buffer the windows in addition to the block so that the search always proceeds
+        let (is_eof, bytes) = self.src.fill_buf(BLOCK_SIZE + self.window.0 + self.window.1)?;
+        let stream = self.src.as_slice();
+
+        // matches starting before `end` are not affected by the bytes not in the stream yet
+        let end = if is_eof { bytes } else { bytes.saturating_sub(self.window.1) };
+        if self.scanned < end {
+            let pos = self
+                .matcher
+                .find(&stream[..bytes], (self.scanned, end), false, 0, 0, &mut self.matches);
+            self.scanned = std::cmp::min(pos, bytes);
+        }
+
+        self.max_consume = if is_eof {
+            bytes
+        } else {
+            self.scanned.saturating_sub(self.window.0)
+        };
+        Ok((is_eof, bytes, self.matches.len(), self.max_consume))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        let stream = self.src.as_slice();
+        (stream, &self.matches)
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let bytes = std::cmp::min(bytes, self.max_consume);
+        self.src.consume(bytes);
+
+        let from = self.matches.partition_point(|x| x.pos < bytes);
+        let to = self.matches.len();
+
+        self.matches.copy_within(from..to, 0);
+        self.matches.truncate(to - from);
+
+        for m in &mut self.matches {
+            m.pos -= bytes;
+        }
         self.scanned -= bytes;
+        self.max_consume -= bytes;
 
         Ok((bytes, from))
     }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.matcher.label(tag)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::RegexSlicer;
+    use super::{RegexSlicer, StreamRegexSlicer};
     use crate::segment::tester::*;
 
     macro_rules! bind {
@@ -91,7 +305,14 @@ mod tests {
             |input: &[u8]| -> Box<dyn SegmentStream> {
                 let src = Box::new(MockSource::new(input));
                 let src = Box::new(ConstSlicer::from_raw(src, (0, -3), (false, false), 4, 6));
-                Box::new(RegexSlicer::new(src, $pattern).unwrap())
+                Box::new(RegexSlicer::new(src, $pattern, (0, 0), None).unwrap())
+            }
+        };
+        ( $pattern: expr, $window: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                let src = Box::new(ConstSlicer::from_raw(src, (0, 0), (false, false), 4, 4));
+                Box::new(RegexSlicer::new(src, $pattern, $window, None).unwrap())
             }
         };
     }
@@ -112,7 +333,19 @@ mod tests {
                 $inner(b"abcdabcdab", &bind!("^abc"), &[(0..3).into(), (4..7).into()]);
                 $inner(b"cabcabcabc", &bind!("^abc"), &[(4..7).into()]);
 
-                // TODO: we need a lot more...
+                // look-ahead window; matches may extend beyond the slices
+                $inner(b"abcabcabca", &bind!("abc", (0, 0)), &[(0..3).into()]);
+                $inner(
+                    b"abcabcabca",
+                    &bind!("abc", (0, 2)),
+                    &[(0..3).into(), (3..6).into(), (6..9).into()],
+                );
+                $inner(b"aaaaaaaaaa", &bind!("a+", (0, 3)), &[(0..7).into(), (4..10).into()]);
+
+                // look-behind window; the bytes before the slices are visible as the context
+                $inner(b"abcdabcdab", &bind!("^abc", (0, 0)), &[(0..3).into(), (4..7).into()]);
+                $inner(b"abcdabcdab", &bind!("^abc", (1, 0)), &[(0..3).into()]);
+                $inner(b"abcdabcdab", &bind!("\\bab", (1, 0)), &[(0..2).into()]);
             }
         };
     }
@@ -120,6 +353,137 @@ mod tests {
     test!(test_regex_all_at_once, test_segment_all_at_once);
     test!(test_regex_random_len, test_segment_random_len);
     test!(test_regex_occasional_consume, test_segment_occasional_consume);
+
+    macro_rules! bind_stream {
+        ( $pattern: expr, $window: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                Box::new(StreamRegexSlicer::new(src, $pattern, $window, None).unwrap())
+            }
+        };
+    }
+
+    macro_rules! test_stream {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                $inner(b"", &bind_stream!("abc", (0, 16)), &[]);
+                $inner(b"aaaaaaaaaa", &bind_stream!("a+", (0, 16)), &[(0..10).into()]);
+                $inner(
+                    b"abcabcabca",
+                    &bind_stream!("abc", (0, 16)),
+                    &[(0..3).into(), (3..6).into(), (6..9).into()],
+                );
+                $inner(b"abcdabcdab", &bind_stream!("^abc", (0, 16)), &[(0..3).into()]);
+                $inner(b"cabcabcabc", &bind_stream!("^abc", (0, 16)), &[]);
+                $inner(b"cabcabcabc", &bind_stream!("abc$", (0, 16)), &[(7..10).into()]);
+
+                // matches across the block boundaries
+                let mut input = Vec::new();
+                let mut expected = Vec::new();
+                for i in 0..1000 {
+                    input.extend_from_slice(b"xxabbbcxx");
+                    expected.push((9 * i + 2..9 * i + 7).into());
+                }
+                $inner(&input, &bind_stream!("ab+c", (0, 8)), &expected);
+
+                // word boundaries at the block boundaries
+                let mut input = Vec::new();
+                let mut expected = Vec::new();
+                for i in 0..1000 {
+                    input.extend_from_slice(b"ab abab ");
+                    expected.push((8 * i..8 * i + 2).into());
+                }
+                $inner(&input, &bind_stream!("\\bab\\b", (0, 8)), &expected);
+
+                // matches longer than the block, up to the look-ahead window
+                let mut input = Vec::new();
+                let mut expected = Vec::new();
+                for i in 0..10 {
+                    input.extend_from_slice(b"x");
+                    input.extend_from_slice(&[b'a'; 300]);
+                    expected.push((301 * i + 1..301 * i + 301).into());
+                }
+                $inner(&input, &bind_stream!("a+", (0, 512)), &expected);
+            }
+        };
+    }
+
+    test_stream!(test_stream_regex_all_at_once, test_segment_all_at_once);
+    test_stream!(test_stream_regex_random_len, test_segment_random_len);
+    test_stream!(test_stream_regex_occasional_consume, test_segment_occasional_consume);
+
+    macro_rules! bind_group {
+        ( $pattern: expr, $groups: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                Box::new(StreamRegexSlicer::new(src, $pattern, (0, 16), Some($groups)).unwrap())
+            }
+        };
+        ( $pattern: expr, $window: expr, $groups: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                let src = Box::new(ConstSlicer::from_raw(src, (0, 0), (false, false), 4, 4));
+                Box::new(RegexSlicer::new(src, $pattern, $window, Some($groups)).unwrap())
+            }
+        };
+    }
+
+    macro_rules! test_group {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                let expected = [
+                    Segment { pos: 4, len: 1, tag: 1 },
+                    Segment { pos: 6, len: 3, tag: 0 },
+                    Segment { pos: 14, len: 2, tag: 1 },
+                    Segment { pos: 17, len: 1, tag: 0 },
+                ];
+                let pattern = "len=(?P<n>\\d+) (\\S+)";
+                $inner(b"len=3 abc len=12 x", &bind_group!(pattern, "n,2"), &expected);
+                $inner(b"len=3 abc len=12 x", &bind_group!(pattern, "2,n"), &expected);
+                $inner(
+                    b"len=3 abc len=12 x",
+                    &bind_group!(pattern, "0"),
+                    &[(0..9).into(), (10..18).into()],
+                );
+
+                // groups not participating in the match
+                $inner(b"acabc", &bind_group!("a(b)?c", "1"), &[(3..4).into()]);
+                $inner(
+                    b"acabc",
+                    &bind_group!("a(b)?c", "0,1"),
+                    &[(0..2).into(), (2..5).into(), (3..4).into()],
+                );
+
+                // groups extending to the look-ahead window
+                $inner(b"xxabbbbcxx", &bind_group!("a(b+)c", (0, 0), "1"), &[]);
+                $inner(b"xxabbbbcxx", &bind_group!("a(b+)c", (0, 4), "1"), &[(3..7).into()]);
+                $inner(
+                    b"xaaaabxx",
+                    &bind_group!("a+(b)", (0, 4), "0,1"),
+                    &[(1..6).into(), (4..6).into(), (5..6).into(), (5..6).into()],
+                );
+            }
+        };
+    }
+
+    test_group!(test_regex_group_all_at_once, test_segment_all_at_once);
+    test_group!(test_regex_group_random_len, test_segment_random_len);
+    test_group!(test_regex_group_occasional_consume, test_segment_occasional_consume);
+
+    #[test]
+    fn test_regex_group_parse() {
+        let bind = |groups: &str| {
+            let src = Box::new(MockSource::new(b""));
+            StreamRegexSlicer::new(src, "(?P<x>a)(b)", (0, 0), Some(groups))
+        };
+        assert!(bind("").is_ok());
+        assert!(bind("0,1,2").is_ok());
+        assert!(bind("x, 2").is_ok());
+        assert!(bind("3").is_err());
+        assert!(bind("y").is_err());
+    }
 }
 
 // end of regex.rs
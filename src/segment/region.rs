@@ -0,0 +1,376 @@
+// @file region.rs
+// @author Hajime Suzuki
+// @brief slicer partitioning the stream into fill runs, low-entropy and high-entropy regions
+
+use super::{Segment, SegmentStream};
+use crate::byte::ByteStream;
+use crate::eval::parse_usize;
+use crate::params::BLOCK_SIZE;
+use crate::stats::ByteStats;
+use anyhow::{anyhow, Context, Result};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum RegionClass {
+    Fill(u8),
+    Low,
+    High,
+}
+
+impl RegionClass {
+    // tags 1..=256 for the fill runs of each byte, 257 and 258 for the low- and high-entropy regions,
+    // and 259..=516 for the pieces continued from the preceding ones of the same region
+    fn tag(&self, is_continued: bool) -> usize {
+        let tag = match self {
+            RegionClass::Fill(x) => 1 + *x as usize,
+            RegionClass::Low => 257,
+            RegionClass::High => 258,
+        };
+        if is_continued {
+            tag + 258
+        } else {
+            tag
+        }
+    }
+}
+
+// the length of the run of the first byte
+fn run_length(stream: &[u8]) -> usize {
+    match stream.first() {
+        Some(&x) => stream.iter().position(|&y| y != x).unwrap_or(stream.len()),
+        None => 0,
+    }
+}
+
+// the first position in [from, to) where a run of `min_len` bytes or longer starts
+fn find_run(stream: &[u8], from: usize, to: usize, min_len: usize) -> Option<usize> {
+    let mut start = from;
+    for i in from..stream.len() {
+        if stream[i] != stream[start] {
+            if start + min_len <= i {
+                break;
+            }
+            start = i;
+        }
+        if start >= to {
+            return None;
+        }
+    }
+    if start < to && start + min_len <= stream.len() {
+        Some(start)
+    } else {
+        None
+    }
+}
+
+pub struct RegionSlicer {
+    src: Box<dyn ByteStream>,
+    segments: Vec<Segment>,
+
+    // region not closed yet; extended until a piece of another class comes
+    open: Option<(usize, RegionClass)>,
+
+    // the open region is the rest of a long region already sliced
+    is_continued: bool,
+
+    scanned: usize,
+    max_consume: usize,
+    request: usize,
+
+    // pieces between the fill runs are classified every `window` bytes by the entropy
+    window: usize,
+    threshold: f64,
+    min_fill: usize,
+
+    labels: Vec<Vec<u8>>,
+}
+
+impl RegionSlicer {
+    // "W[,H[,F]]"
+    pub fn new(src: Box<dyn ByteStream>, params: &str) -> Result<Self> {
+        let v: Vec<_> = params.split(',').collect();
+        let parse = || -> Result<(usize, f64, usize)> {
+            let window = parse_usize(v[0])?;
+            let threshold = match v.get(1) {
+                Some(x) => x.trim().parse::<f64>()?,
+                None => 7.0,
+            };
+            let min_fill = match v.get(2) {
+                Some(x) => parse_usize(x)?,
+                None => window,
+            };
+            Ok((window, threshold, min_fill))
+        };
+
+        let (window, threshold, min_fill) = parse().with_context(|| format!("failed to parse {params:?} as W[,H[,F]]"))?;
+        if v.len() > 3 || window == 0 || min_fill == 0 {
+            return Err(anyhow!("window and fill length must be positive in W[,H[,F]] (got: {params:?})"));
+        }
+
+        let mut labels: Vec<Vec<u8>> = (0..=255).map(|x: u8| format!("fill {x:02x}").into_bytes()).collect();
+        labels.push(b"low".to_vec());
+        labels.push(b"high".to_vec());
+        let continued: Vec<Vec<u8>> = labels.iter().map(|x| [x.as_slice(), b" continued"].concat()).collect();
+        labels.extend(continued);
+
+        Ok(RegionSlicer {
+            src,
+            segments: Vec::new(),
+            open: None,
+            is_continued: false,
+            scanned: 0,
+            max_consume: 0,
+            request: BLOCK_SIZE,
+            window,
+            threshold,
+            min_fill,
+            labels,
+        })
+    }
+
+    // the tail and the class of the next piece, or None if more bytes are needed
+    fn next_piece(&self, stream: &[u8], is_eof: bool) -> Option<(usize, RegionClass)> {
+        let pos = self.scanned;
+
+        // a fill run reaching the end of the stream is left `min_fill` bytes, so that it's
+        // classified as a fill run again when the next bytes come
+        let run = run_length(&stream[pos..]);
+        if pos + run == stream.len() && !is_eof {
+            return match run > self.min_fill {
+                true => Some((stream.len() - self.min_fill, RegionClass::Fill(stream[pos]))),
+                false => None,
+            };
+        }
+        if run >= self.min_fill {
+            return Some((pos + run, RegionClass::Fill(stream[pos])));
+        }
+
+        // cut the window at the next fill run
+        let end = pos + self.window;
+        if end + self.min_fill > stream.len() && !is_eof {
+            return None;
+        }
+        let end = std::cmp::min(end, stream.len());
+        let tail = find_run(stream, pos + 1, end, self.min_fill).unwrap_or(end);
+
+        // short pieces are absorbed into the preceding region unless it's a fill run
+        let class = match self.open {
+            Some((_, class @ (RegionClass::Low | RegionClass::High))) if tail - pos < self.window => class,
+            _ if ByteStats::new(&stream[pos..tail]).entropy >= self.threshold => RegionClass::High,
+            _ => RegionClass::Low,
+        };
+        Some((tail, class))
+    }
+
+    fn extend(&mut self, tail: usize, class: RegionClass) {
+        match self.open {
+            Some((_, open)) if open == class => {}
+            Some((start, open)) => {
+                self.segments.push(Segment {
+                    pos: start,
+                    len: self.scanned - start,
+                    tag: open.tag(self.is_continued),
+                });
+                self.open = Some((self.scanned, class));
+                self.is_continued = false;
+            }
+            None => self.open = Some((self.scanned, class)),
+        }
+        self.scanned = tail;
+
+        // long regions are sliced every BLOCK_SIZE bytes not to keep the entire region in the buffer.
+        // the pieces after the first are tagged as continued so that they can be joined downstream.
+        while let Some((start, class)) = self.open {
+            if self.scanned - start <= BLOCK_SIZE {
+                break;
+            }
+            self.segments.push(Segment {
+                pos: start,
+                len: BLOCK_SIZE,
+                tag: class.tag(self.is_continued),
+            });
+            self.open = Some((start + BLOCK_SIZE, class));
+            self.is_continued = true;
+        }
+    }
+}
+
+impl SegmentStream for RegionSlicer {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        let (is_eof, bytes) = self.src.fill_buf(self.request)?;
+
+        self.request = BLOCK_SIZE;
+        while self.scanned < bytes {
+            match self.next_piece(&self.src.as_slice()[..bytes], is_eof) {
+                Some((tail, class)) => self.extend(tail, class),
+                None => {
+                    // the piece is scanned again from its head; grow the unscanned bytes geometrically
+                    self.request = std::cmp::max(BLOCK_SIZE, 2 * (bytes - self.scanned));
+                    break;
+                }
+            }
+        }
+
+        if is_eof {
+            if let Some((start, class)) = self.open.take() {
+                self.segments.push(Segment {
+                    pos: start,
+                    len: bytes - start,
+                    tag: class.tag(self.is_continued),
+                });
+                self.is_continued = false;
+            }
+        }
+
+        self.max_consume = match (is_eof, self.open) {
+            (true, _) => bytes,
+            (false, Some((start, _))) => start,
+            (false, None) => self.scanned,
+        };
+        Ok((is_eof, bytes, self.segments.len(), self.max_consume))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        let stream = self.src.as_slice();
+        (stream, &self.segments)
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let bytes = std::cmp::min(bytes, self.max_consume);
+        self.src.consume(bytes);
+
+        let from = self.segments.partition_point(|x| x.pos < bytes);
+        let to = self.segments.len();
+
+        self.segments.copy_within(from..to, 0);
+        self.segments.truncate(to - from);
+
+        for s in &mut self.segments {
+            s.pos -= bytes;
+        }
+        if let Some((start, _)) = &mut self.open {
+            *start -= bytes;
+        }
+        self.scanned -= bytes;
+        self.max_consume -= bytes;
+
+        Ok((bytes, from))
+    }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.labels.get(tag.wrapping_sub(1)).map(|x| x.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegionSlicer;
+    use crate::params::BLOCK_SIZE;
+    use crate::segment::tester::*;
+
+    macro_rules! bind {
+        ( $params: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                Box::new(RegionSlicer::new(src, $params).unwrap())
+            }
+        };
+    }
+
+    fn seg(pos: usize, len: usize, tag: usize) -> Segment {
+        Segment { pos, len, tag }
+    }
+
+    // the pieces after the first are tagged as continued
+    fn split(pos: usize, len: usize, tag: usize) -> Vec<Segment> {
+        (pos..pos + len)
+            .step_by(BLOCK_SIZE)
+            .map(|x| seg(x, std::cmp::min(BLOCK_SIZE, pos + len - x), if x == pos { tag } else { tag + 258 }))
+            .collect()
+    }
+
+    macro_rules! test {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                $inner(b"", &bind!("4,1.5"), &[]);
+                $inner(b"\0\0\0\0\0\0abcdefgh", &bind!("4,1.5"), &[seg(0, 6, 1), seg(6, 8, 258)]);
+                $inner(
+                    b"aabbaabbabcd\xff\xff\xff\xff",
+                    &bind!("4,1.5"),
+                    &[seg(0, 8, 257), seg(8, 4, 258), seg(12, 4, 256)],
+                );
+
+                // runs shorter than the fill length are classified with the others
+                $inner(b"abcaaabd", &bind!("4,1.5"), &[seg(0, 8, 258)]);
+                $inner(
+                    b"abcaaabd",
+                    &bind!("4,1.5,3"),
+                    &[seg(0, 3, 258), seg(3, 3, 98), seg(6, 2, 257)],
+                );
+
+                // adjacent fill runs of different bytes
+                $inner(b"\0\0\0\0\xff\xff\xff\xff", &bind!("4,1.5"), &[seg(0, 4, 1), seg(4, 4, 256)]);
+
+                // short pieces
+                $inner(b"aabbab\0\0\0\0", &bind!("4,1.5"), &[seg(0, 6, 257), seg(6, 4, 1)]);
+                $inner(b"ab\0\0\0\0", &bind!("4,1.5"), &[seg(0, 2, 257), seg(2, 4, 1)]);
+                $inner(b"\0\0\0\0abc", &bind!("4,1.5"), &[seg(0, 4, 1), seg(4, 3, 258)]);
+
+                // long regions across the blocks, sliced every BLOCK_SIZE bytes
+                let mut input = vec![0u8; 100000];
+                input.extend_from_slice(b"abcd".repeat(10000).as_slice());
+                input.extend_from_slice(&[0xffu8; 3]);
+                let mut expected = split(0, 100000, 1);
+                expected.extend(split(100000, 40003, 258));
+                $inner(&input, &bind!("16,1.5"), &expected);
+            }
+        };
+    }
+
+    test!(test_region_all_at_once, test_segment_all_at_once);
+    test!(test_region_random_len, test_segment_random_len);
+    test!(test_region_occasional_consume, test_segment_occasional_consume);
+
+    #[test]
+    fn test_region_labels() {
+        let slicer = RegionSlicer::new(Box::new(MockSource::new(b"")), "16").unwrap();
+        assert_eq!(slicer.label(1), Some(b"fill 00".as_slice()));
+        assert_eq!(slicer.label(258), Some(b"high".as_slice()));
+        assert_eq!(slicer.label(1 + 258), Some(b"fill 00 continued".as_slice()));
+        assert_eq!(slicer.label(258 + 258), Some(b"high continued".as_slice()));
+        assert_eq!(slicer.label(0), None);
+        assert_eq!(slicer.label(517), None);
+    }
+
+    #[test]
+    fn test_region_bounded_buffer() {
+        // the stream is consumed in the middle of the regions
+        for input in [vec![0u8; 100 * BLOCK_SIZE], b"abcd".repeat(25 * BLOCK_SIZE)] {
+            let mut slicer = RegionSlicer::new(Box::new(MockSource::new(&input)), "16,1.5").unwrap();
+            let mut count = 0;
+            loop {
+                let (is_eof, bytes, _, max_consume) = slicer.fill_segment_buf().unwrap();
+                assert!(bytes < 8 * BLOCK_SIZE, "{}", bytes);
+                if is_eof {
+                    count += slicer.consume(bytes).unwrap().1;
+                    break;
+                }
+                count += slicer.consume(max_consume).unwrap().1;
+            }
+            assert_eq!(count, 100);
+        }
+    }
+
+    #[test]
+    fn test_region_params() {
+        let src = || Box::new(MockSource::new(b""));
+        assert!(RegionSlicer::new(src(), "4096").is_ok());
+        assert!(RegionSlicer::new(src(), "4096,7.5,64").is_ok());
+        assert!(RegionSlicer::new(src(), "0").is_err());
+        assert!(RegionSlicer::new(src(), "16,x").is_err());
+        assert!(RegionSlicer::new(src(), "16,1,0").is_err());
+        assert!(RegionSlicer::new(src(), "16,1,1,1").is_err());
+    }
+}
+
+// end of region.rs
@@ -0,0 +1,216 @@
+// @file split.rs
+// @author Hajime Suzuki
+// @brief delimiter-split slicer
+
+use super::pattern::Pattern;
+use super::{Segment, SegmentStream};
+use anyhow::{anyhow, Context, Result};
+
+pub struct SplitSlicer {
+    // delimiters located by ExactMatchSlicer
+    src: Box<dyn SegmentStream>,
+    src_scanned: usize,
+
+    segments: Vec<Segment>,
+    max_consume: usize,
+
+    // head of the record being extended
+    record_start: usize,
+
+    keeps_delimiter: bool,
+    escape: Option<u8>,
+}
+
+impl SplitSlicer {
+    pub fn new(src: Box<dyn SegmentStream>, keeps_delimiter: bool, escape: Option<&str>) -> Result<Self> {
+        let escape = match escape {
+            Some(escape) => {
+                let pattern = Pattern::from_str(escape, 0).with_context(|| format!("failed to parse {escape:?} into a byte"))?;
+                if pattern.bytes.len() != 1 || pattern.mask[0] != 0xff {
+                    return Err(anyhow!("escape must be a single byte without wildcards (got: {escape:?})"));
+                }
+                Some(pattern.bytes[0])
+            }
+            None => None,
+        };
+
+        Ok(SplitSlicer {
+            src,
+            src_scanned: 0,
+            segments: Vec::new(),
+            max_consume: 0,
+            record_start: 0,
+            keeps_delimiter,
+            escape,
+        })
+    }
+
+    // true if the delimiter at `pos` is preceded by an odd number of the escape bytes
+    fn is_escaped(&self, stream: &[u8], pos: usize) -> bool {
+        let escape = match self.escape {
+            Some(escape) => escape,
+            None => return false,
+        };
+        let count = stream[self.record_start..pos].iter().rev().take_while(|&&x| x == escape).count();
+        count % 2 == 1
+    }
+
+    fn extend_segment_buf(&mut self, count: usize) {
+        let (stream, delimiters) = self.src.as_slices();
+
+        for (i, d) in delimiters[..count].iter().enumerate().skip(self.src_scanned) {
+            // overlapping delimiters are skipped; the longest one is taken among the ones at the same
+            // position, which are adjacent as the delimiters are sorted by their positions
+            if d.pos < self.record_start {
+                continue;
+            }
+            let mut same_pos = delimiters[i + 1..count].iter().take_while(|x| x.pos == d.pos);
+            if same_pos.any(|x| x.len > d.len) {
+                continue;
+            }
+            if self.is_escaped(stream, d.pos) {
+                continue;
+            }
+
+            let tail = if self.keeps_delimiter { d.tail() } else { d.pos };
+            self.segments.push(Segment {
+                pos: self.record_start,
+                len: tail - self.record_start,
+                tag: 0,
+            });
+            self.record_start = d.tail();
+        }
+        self.src_scanned = count;
+    }
+}
+
+impl SegmentStream for SplitSlicer {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
+        self.extend_segment_buf(count);
+
+        // the last record may lack the delimiter
+        if is_eof && self.record_start < bytes {
+            self.segments.push(Segment {
+                pos: self.record_start,
+                len: bytes - self.record_start,
+                tag: 0,
+            });
+            self.record_start = bytes;
+        }
+
+        self.max_consume = if is_eof {
+            bytes
+        } else {
+            std::cmp::min(max_consume, self.record_start)
+        };
+        Ok((is_eof, bytes, self.segments.len(), self.max_consume))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        let (stream, _) = self.src.as_slices();
+        (stream, &self.segments)
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let bytes = std::cmp::min(bytes, self.max_consume);
+        let (bytes, src_count) = self.src.consume(bytes)?;
+        self.src_scanned -= src_count;
+
+        let from = self.segments.partition_point(|x| x.pos < bytes);
+        let to = self.segments.len();
+
+        self.segments.copy_within(from..to, 0);
+        self.segments.truncate(to - from);
+
+        for s in &mut self.segments {
+            s.pos -= bytes;
+        }
+        self.record_start -= bytes;
+        self.max_consume -= bytes;
+
+        Ok((bytes, from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SplitSlicer;
+    use crate::segment::tester::*;
+    use crate::segment::ExactMatchSlicer;
+
+    macro_rules! bind {
+        ( $delimiters: expr, $keeps_delimiter: expr, $escape: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                let src = Box::new(ExactMatchSlicer::new(src, $delimiters).unwrap());
+                Box::new(SplitSlicer::new(src, $keeps_delimiter, $escape).unwrap())
+            }
+        };
+    }
+
+    macro_rules! test {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                $inner(b"", &bind!("0a", false, None), &[]);
+                $inner(b"\n", &bind!("0a", false, None), &[(0..0).into()]);
+                $inner(b"abc", &bind!("0a", false, None), &[(0..3).into()]);
+                $inner(b"ab\ncd\n", &bind!("0a", false, None), &[(0..2).into(), (3..5).into()]);
+                $inner(b"ab\ncd\n", &bind!("0a", true, None), &[(0..3).into(), (3..6).into()]);
+
+                // empty records and the last record without the delimiter
+                $inner(
+                    b"a\0\0bc",
+                    &bind!("00", false, None),
+                    &[(0..1).into(), (2..2).into(), (3..5).into()],
+                );
+
+                // multi-byte delimiters; the longest one wins at the same position
+                $inner(
+                    b"ab\r\ncd\ne",
+                    &bind!("\"\\r\\n\"", false, None),
+                    &[(0..2).into(), (4..8).into()],
+                );
+                $inner(
+                    b"ab\r\ncd\ne",
+                    &bind!("\"\\n\",\"\\r\\n\"", false, None),
+                    &[(0..2).into(), (4..6).into(), (7..8).into()],
+                );
+                $inner(
+                    b"ab\r\ncd\re",
+                    &bind!("\"\\r\",\"\\r\\n\"", false, None),
+                    &[(0..2).into(), (4..6).into(), (7..8).into()],
+                );
+                $inner(
+                    b"aaaaa",
+                    &bind!("\"aa\"", false, None),
+                    &[(0..0).into(), (2..2).into(), (4..5).into()],
+                );
+
+                // escaped delimiters
+                $inner(
+                    b"a\\,b,c\\\\,d",
+                    &bind!("\",\"", false, Some("\"\\\\\"")),
+                    &[(0..4).into(), (5..8).into(), (9..10).into()],
+                );
+
+                // long records across the blocks
+                let mut input = b"a".repeat(100000);
+                input.push(b'\n');
+                input.extend_from_slice(&b"b".repeat(1000));
+                $inner(
+                    &input,
+                    &bind!("0a", false, None),
+                    &[(0..100000).into(), (100001..101001).into()],
+                );
+            }
+        };
+    }
+
+    test!(test_split_all_at_once, test_segment_all_at_once);
+    test!(test_split_random_len, test_segment_random_len);
+    test!(test_split_occasional_consume, test_segment_occasional_consume);
+}
+
+// end of split.rs
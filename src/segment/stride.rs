@@ -22,9 +22,14 @@ impl ConstSlicerParams {
         // parse mapper (if it results in an empty slice it's an error)
         let expr = expr.unwrap_or("s..e");
         let mapper = SegmentMapper::from_str(expr)?;
+        if !mapper.is_anchored() {
+            return Err(anyhow!(
+                "slice-mapping expression (S..E) must be relative to input slice boundaries for the constant-stride slicer."
+            ));
+        }
 
         let segment = [0, pitch];
-        let (start, end) = mapper.evaluate(&segment, &segment);
+        let (start, end) = mapper.evaluate(&segment, &segment, &[])?;
         if start >= end {
             return Err(anyhow!(
                 "map expression {:?} on {}-byte slicer results in an empty stream",
@@ -275,7 +280,7 @@ impl ConstSegments {
         while next_tail + self.tail_offset_margin <= len + self.span {
             let pos = next_tail.saturating_sub(self.span);
             let len = std::cmp::min(next_tail, len) - pos;
-            self.segments.push(Segment { pos, len });
+            self.segments.push(Segment { pos, len, tag: 0 });
 
             next_tail += self.pitch;
         }
@@ -293,7 +298,7 @@ impl ConstSegments {
         while next_tail + self.tail_reserved_bytes <= len {
             let pos = next_tail.saturating_sub(self.span);
             let len = next_tail - pos;
-            self.segments.push(Segment { pos, len });
+            self.segments.push(Segment { pos, len, tag: 0 });
 
             next_tail += self.pitch;
         }
@@ -642,6 +647,7 @@ mod tests {
             v.push(Segment {
                 pos: pos as usize,
                 len: len as usize,
+                tag: 0,
             });
             tail += pitch;
         }
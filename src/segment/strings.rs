@@ -0,0 +1,332 @@
+// @file strings.rs
+// @author Hajime Suzuki
+// @brief printable-string slicer, like strings(1)
+
+use super::{Segment, SegmentStream};
+use crate::byte::ByteStream;
+use crate::params::BLOCK_SIZE;
+use anyhow::{anyhow, Result};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum StringEncoding {
+    Ascii,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl StringEncoding {
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "ascii" => Ok(StringEncoding::Ascii),
+            "utf8" | "utf-8" => Ok(StringEncoding::Utf8),
+            "utf16le" | "utf-16le" => Ok(StringEncoding::Utf16Le),
+            "utf16be" | "utf-16be" => Ok(StringEncoding::Utf16Be),
+            x => Err(anyhow!("unknown string encoding {x:?} (ascii, utf8, utf16le, or utf16be expected)")),
+        }
+    }
+
+    fn unit_size(&self) -> usize {
+        match self {
+            StringEncoding::Utf16Le | StringEncoding::Utf16Be => 2,
+            _ => 1,
+        }
+    }
+}
+
+enum Decoded {
+    Printable(usize), // #bytes of the character
+    Unprintable,
+    Incomplete,
+}
+
+fn is_printable(c: char) -> bool {
+    c == '\t' || !c.is_control()
+}
+
+fn decode_utf8(stream: &[u8]) -> Decoded {
+    let width = match stream[0] {
+        0x00..=0x7f => 1,
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => return Decoded::Unprintable,
+    };
+    if stream.len() < width {
+        // the continuation bytes may be invalid as well; checked when they come
+        return Decoded::Incomplete;
+    }
+
+    match std::str::from_utf8(&stream[..width]) {
+        Ok(s) if is_printable(s.chars().next().unwrap()) => Decoded::Printable(width),
+        _ => Decoded::Unprintable,
+    }
+}
+
+// printable ASCII in 16-bit units, as `strings -el` does; wider ranges pick up too much noise
+// from binaries in the misaligned positions (e.g., "a\0b\0" read as U+6100 U+6200)
+fn decode_utf16(stream: &[u8], is_le: bool) -> Decoded {
+    if stream.len() < 2 {
+        return Decoded::Incomplete;
+    }
+
+    let x = [stream[0], stream[1]];
+    match if is_le { u16::from_le_bytes(x) } else { u16::from_be_bytes(x) } {
+        0x09 | 0x20..=0x7e => Decoded::Printable(2),
+        _ => Decoded::Unprintable,
+    }
+}
+
+pub struct StringsSlicer {
+    src: Box<dyn ByteStream>,
+    segments: Vec<Segment>,
+
+    scanned: usize,
+    request: usize,
+
+    // the run at `scanned` is the rest of a long run sliced every BLOCK_SIZE bytes
+    is_long_run: bool,
+
+    min_len: usize, // in characters
+    encoding: StringEncoding,
+    requires_nul: bool,
+}
+
+impl StringsSlicer {
+    pub fn new(src: Box<dyn ByteStream>, min_len: usize, encoding: &str, requires_nul: bool) -> Result<Self> {
+        if min_len == 0 {
+            return Err(anyhow!("minimum string length must be positive"));
+        }
+
+        Ok(StringsSlicer {
+            src,
+            segments: Vec::new(),
+            scanned: 0,
+            request: BLOCK_SIZE,
+            is_long_run: false,
+            min_len,
+            encoding: StringEncoding::from_str(encoding)?,
+            requires_nul,
+        })
+    }
+
+    fn decode(&self, stream: &[u8]) -> Decoded {
+        match self.encoding {
+            StringEncoding::Ascii => match stream[0] {
+                b'\t' | 0x20..=0x7e => Decoded::Printable(1),
+                _ => Decoded::Unprintable,
+            },
+            StringEncoding::Utf8 => decode_utf8(stream),
+            StringEncoding::Utf16Le => decode_utf16(stream, true),
+            StringEncoding::Utf16Be => decode_utf16(stream, false),
+        }
+    }
+
+    // (tail, #chars, is_cut) of the run starting at `pos`, or None if the run may continue beyond
+    // the stream; the run is cut at BLOCK_SIZE bytes not to keep a long run in the buffer
+    fn scan_run(&self, stream: &[u8], pos: usize, is_eof: bool) -> Option<(usize, usize, bool)> {
+        let limit = pos + BLOCK_SIZE;
+        let mut tail = pos;
+        let mut count = 0;
+        while tail < stream.len() {
+            if tail >= limit {
+                return Some((tail, count, true));
+            }
+            match self.decode(&stream[tail..]) {
+                Decoded::Printable(width) => {
+                    tail += width;
+                    count += 1;
+                }
+                Decoded::Incomplete if !is_eof => return None,
+                _ => return Some((tail, count, false)),
+            }
+        }
+        if is_eof {
+            Some((tail, count, false))
+        } else {
+            None
+        }
+    }
+
+    fn is_terminated(&self, stream: &[u8], tail: usize) -> bool {
+        let unit = self.encoding.unit_size();
+        tail + unit <= stream.len() && stream[tail..tail + unit].iter().all(|&x| x == 0)
+    }
+}
+
+impl SegmentStream for StringsSlicer {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        let (is_eof, bytes) = self.src.fill_buf(self.request)?;
+        let stream = &self.src.as_slice()[..bytes];
+
+        // the terminator must be in the stream to determine the run is terminated
+        let margin = if self.requires_nul && !is_eof {
+            self.encoding.unit_size()
+        } else {
+            0
+        };
+
+        self.request = BLOCK_SIZE;
+        while self.scanned < bytes {
+            let pos = self.scanned;
+            let (tail, count, is_cut) = match self.scan_run(stream, pos, is_eof) {
+                Some((tail, count, is_cut)) if tail + margin <= bytes => (tail, count, is_cut),
+                _ => {
+                    // the piece is scanned again from its head; grow the unscanned bytes geometrically
+                    self.request = std::cmp::max(BLOCK_SIZE, 2 * (bytes - self.scanned));
+                    break;
+                }
+            };
+
+            // long runs are left in pieces; they are dropped for --strings-nul as the terminator
+            // is not determined until the end of the run. the pieces after the first are tagged
+            // as continued so that they can be joined downstream.
+            if is_cut || self.is_long_run {
+                if !self.requires_nul && tail > pos {
+                    self.segments.push(Segment {
+                        pos,
+                        len: tail - pos,
+                        tag: self.is_long_run as usize,
+                    });
+                }
+                self.is_long_run = is_cut;
+                self.scanned = std::cmp::max(tail, pos + 1);
+                continue;
+            }
+
+            if count >= self.min_len && (!self.requires_nul || self.is_terminated(stream, tail)) {
+                self.segments.push(Segment {
+                    pos,
+                    len: tail - pos,
+                    tag: 0,
+                });
+                self.scanned = tail;
+                continue;
+            }
+
+            // runs starting inside the run are shorter. in UTF-16, the units in the other alignment
+            // are not printable ASCII inside the run, and up to the tail, as their upper bytes are not zero
+            self.scanned = std::cmp::max(tail, pos + 1);
+        }
+
+        let max_consume = if is_eof { bytes } else { self.scanned };
+        Ok((is_eof, bytes, self.segments.len(), max_consume))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        let stream = self.src.as_slice();
+        (stream, &self.segments)
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let bytes = std::cmp::min(bytes, self.scanned);
+        self.src.consume(bytes);
+
+        let from = self.segments.partition_point(|x| x.pos < bytes);
+        let to = self.segments.len();
+
+        self.segments.copy_within(from..to, 0);
+        self.segments.truncate(to - from);
+
+        for s in &mut self.segments {
+            s.pos -= bytes;
+        }
+        self.scanned -= bytes;
+
+        Ok((bytes, from))
+    }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        match tag {
+            1 => Some(b"continued"),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StringsSlicer;
+    use crate::params::BLOCK_SIZE;
+    use crate::segment::tester::*;
+
+    macro_rules! bind {
+        ( $min_len: expr, $encoding: expr, $requires_nul: expr ) => {
+            |input: &[u8]| -> Box<dyn SegmentStream> {
+                let src = Box::new(MockSource::new(input));
+                Box::new(StringsSlicer::new(src, $min_len, $encoding, $requires_nul).unwrap())
+            }
+        };
+    }
+
+    macro_rules! test {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                $inner(b"", &bind!(4, "ascii", false), &[]);
+                $inner(b"abc", &bind!(4, "ascii", false), &[]);
+                $inner(b"abcd", &bind!(4, "ascii", false), &[(0..4).into()]);
+                $inner(
+                    b"\x01abcd\x02ab\x03a\tb c\xff",
+                    &bind!(3, "ascii", false),
+                    &[(1..5).into(), (9..14).into()],
+                );
+
+                // NUL-terminated
+                $inner(b"abcd\0xyzw\x01uvwx", &bind!(4, "ascii", true), &[(0..4).into()]);
+                $inner(b"abcd\0xyzw\0", &bind!(4, "ascii", true), &[(0..4).into(), (5..9).into()]);
+
+                // UTF-8; the length is counted in characters
+                $inner(
+                    "\x01あいう\x02abc\u{3000}\u{85}\u{1f600}x".as_bytes(),
+                    &bind!(3, "utf8", false),
+                    &[(1..10).into(), (11..17).into()],
+                );
+                $inner(b"\xe3\x81abcd\xe3", &bind!(3, "utf8", false), &[(2..6).into()]);
+
+                // UTF-16; the other alignment is also scanned
+                $inner(
+                    b"\x01a\0b\0c\0\0\0x\0y\0\x01\0",
+                    &bind!(3, "utf16le", false),
+                    &[(1..7).into()],
+                );
+                $inner(b"\0a\0b\0c\0d", &bind!(3, "utf16le", false), &[(1..7).into()]);
+                $inner(b"\0a\0b\0c\0d", &bind!(3, "utf16be", false), &[(0..8).into()]);
+                $inner(b"\xd8\x3d\xde\x00\0a\0b\0c", &bind!(3, "utf16be", false), &[(4..10).into()]);
+                $inner(b"a\0b\0c\0\0\0d\0e\0f\0g\0", &bind!(3, "utf16le", true), &[(0..6).into()]);
+
+                // strings across the blocks
+                let mut input = b"\x01".repeat(5000);
+                input.extend_from_slice(&b"a".repeat(100));
+                input.push(0);
+                $inner(&input, &bind!(4, "ascii", true), &[(5000..5100).into()]);
+
+                // long strings are sliced every BLOCK_SIZE bytes, or dropped if NUL-terminated
+                let mut input = b"\x01".repeat(5000);
+                input.extend_from_slice(&b"a".repeat(100000));
+                input.push(0);
+                let expected: Vec<Segment> = (5000..105000)
+                    .step_by(BLOCK_SIZE)
+                    .map(|x| Segment {
+                        pos: x,
+                        len: std::cmp::min(BLOCK_SIZE, 105000 - x),
+                        tag: (x > 5000) as usize,
+                    })
+                    .collect();
+                $inner(&input, &bind!(4, "ascii", false), &expected);
+                $inner(&input, &bind!(4, "ascii", true), &[]);
+
+                // the rest of a rejected UTF-16 run is skipped in both alignments
+                let mut input = b"a\0".repeat(50);
+                input.extend_from_slice(b"\x01b\0c\0d\0\0\0");
+                $inner(&input, &bind!(3, "utf16le", true), &[(101..107).into()]);
+            }
+        };
+    }
+
+    test!(test_strings_all_at_once, test_segment_all_at_once);
+    test!(test_strings_random_len, test_segment_random_len);
+    test!(test_strings_occasional_consume, test_segment_occasional_consume);
+}
+
+// end of strings.rs
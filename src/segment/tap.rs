@@ -0,0 +1,101 @@
+// @file tap.rs
+// @author Hajime Suzuki
+
+use super::{Segment, SegmentStream};
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+// absolute ranges of the matches; pushed by MatchTap and pruned by the formatter
+pub type MatchRanges = Arc<Mutex<VecDeque<Range<usize>>>>;
+
+pub struct MatchTap {
+    src: Box<dyn SegmentStream>,
+    ranges: MatchRanges,
+
+    // absolute offset of the head of the source, and #segments already recorded in it
+    offset: usize,
+    src_recorded: usize,
+}
+
+impl MatchTap {
+    pub fn new(src: Box<dyn SegmentStream>, ranges: MatchRanges) -> Self {
+        MatchTap {
+            src,
+            ranges,
+            offset: 0,
+            src_recorded: 0,
+        }
+    }
+}
+
+impl SegmentStream for MatchTap {
+    fn fill_segment_buf(&mut self) -> Result<(bool, usize, usize, usize)> {
+        let (is_eof, bytes, count, max_consume) = self.src.fill_segment_buf()?;
+
+        let (_, segments) = self.src.as_slices();
+        if count > self.src_recorded {
+            let mut ranges = self.ranges.lock().unwrap();
+            ranges.extend(
+                segments[self.src_recorded..count]
+                    .iter()
+                    .map(|s| self.offset + s.pos..self.offset + s.tail()),
+            );
+            self.src_recorded = count;
+        }
+        Ok((is_eof, bytes, count, max_consume))
+    }
+
+    fn as_slices(&self) -> (&[u8], &[Segment]) {
+        self.src.as_slices()
+    }
+
+    fn consume(&mut self, bytes: usize) -> Result<(usize, usize)> {
+        let (bytes, count) = self.src.consume(bytes)?;
+        self.offset += bytes;
+        self.src_recorded = self.src_recorded.saturating_sub(count);
+        Ok((bytes, count))
+    }
+
+    fn label(&self, tag: usize) -> Option<&[u8]> {
+        self.src.label(tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MatchRanges, MatchTap};
+    use crate::segment::tester::*;
+    use std::cell::RefCell;
+
+    macro_rules! test {
+        ( $name: ident, $inner: ident ) => {
+            #[test]
+            fn $name() {
+                let ranges: RefCell<Option<MatchRanges>> = RefCell::new(None);
+                let bind = |pattern: &[u8]| -> Box<dyn SegmentStream> {
+                    let src = Box::new(MockSource::new(pattern));
+                    let src = Box::new(ConstSlicer::from_raw(src, (3, 3), (false, false), 4, 2));
+                    let tap = MatchRanges::default();
+                    *ranges.borrow_mut() = Some(tap.clone());
+                    Box::new(MatchTap::new(src, tap))
+                };
+
+                // segments pass through, and each of them is recorded once
+                let expected = [(3..5).into(), (7..9).into(), (11..13).into(), (15..17).into()];
+                $inner(b"abcdefghijklmnopqrstu", &bind, &expected);
+
+                let ranges = ranges.borrow().clone().unwrap();
+                let ranges: Vec<_> = ranges.lock().unwrap().iter().cloned().collect();
+                assert_eq!(ranges, vec![3..5, 7..9, 11..13, 15..17]);
+            }
+        };
+    }
+
+    test!(test_tap_all_at_once, test_segment_all_at_once);
+    test!(test_tap_random_len, test_segment_random_len);
+    test!(test_tap_occasional_consume, test_segment_occasional_consume);
+}
+
+// end of tap.rs
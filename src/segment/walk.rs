@@ -133,7 +133,11 @@ impl WalkSlicer {
                 eprintln!("slice clipped (span = {span}, remaining bytes = {len}).");
             }
 
-            self.segments.push(Segment { pos: self.pos, len });
+            self.segments.push(Segment {
+                pos: self.pos,
+                len,
+                tag: 0,
+            });
             self.pos += span;
         }
         Ok((is_eof, bytes))
@@ -247,10 +251,10 @@ mod tests {
                 for i in 0..10000 {
                     input.extend_from_slice(&[1u8, 3, 0, 0, 2, 1, 0]);
                     expected.extend_from_slice(&[
-                        Segment { pos: i * 7, len: 1 },
-                        Segment { pos: i * 7 + 1, len: 3 },
-                        Segment { pos: i * 7 + 4, len: 2 },
-                        Segment { pos: i * 7 + 6, len: 1 },
+                        (i * 7..i * 7 + 1).into(),
+                        (i * 7 + 1..i * 7 + 4).into(),
+                        (i * 7 + 4..i * 7 + 6).into(),
+                        (i * 7 + 6..i * 7 + 7).into(),
                     ]);
                 }
                 $inner(&input, &bind!("b[0], b[1]"), &expected);
@@ -260,13 +264,10 @@ mod tests {
                 for i in 0..10000 {
                     input.extend_from_slice(&[1u8, 0, 0, 0, 6, 0, 0, 5, 0, 0, 0, 1, 0]);
                     expected.extend_from_slice(&[
-                        Segment { pos: i * 13, len: 1 },
-                        Segment { pos: i * 13 + 1, len: 6 },
-                        Segment { pos: i * 13 + 7, len: 5 },
-                        Segment {
-                            pos: i * 13 + 12,
-                            len: 1,
-                        },
+                        (i * 13..i * 13 + 1).into(),
+                        (i * 13 + 1..i * 13 + 7).into(),
+                        (i * 13 + 7..i * 13 + 12).into(),
+                        (i * 13 + 12..i * 13 + 13).into(),
                     ]);
                 }
                 $inner(&input, &bind!("i[0], h[2]"), &expected);
@@ -286,7 +287,7 @@ mod tests {
 
         let mut pos = 0;
         let mut push_segment = |len: usize| {
-            segments.push(Segment { pos, len });
+            segments.push((pos..pos + len).into());
             pos += len;
         };
 
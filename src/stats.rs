@@ -0,0 +1,90 @@
+// @file stats.rs
+// @author Hajime Suzuki
+// @brief byte statistics for per-slice reports
+
+use std::io::Write;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ByteStats {
+    pub len: usize,
+    pub entropy: f64, // Shannon entropy in bits per byte
+    pub min: u8,
+    pub max: u8,
+    pub zeros: usize,
+    pub printable: usize, // bytes in [0x20, 0x7f), the ones rendered as-is in the mosaic
+}
+
+impl ByteStats {
+    pub fn new(slice: &[u8]) -> Self {
+        let mut hist = [0usize; 256];
+        for &x in slice {
+            hist[x as usize] += 1;
+        }
+
+        let len = slice.len();
+        let entropy = hist.iter().filter(|&&n| n > 0).fold(0.0, |acc, &n| {
+            let p = n as f64 / len as f64;
+            acc + p * (1.0 / p).log2()
+        });
+
+        ByteStats {
+            len,
+            entropy,
+            min: hist.iter().position(|&n| n > 0).unwrap_or(0) as u8,
+            max: hist.iter().rposition(|&n| n > 0).unwrap_or(0) as u8,
+            zeros: hist[0],
+            printable: hist[0x20..0x7f].iter().sum(),
+        }
+    }
+
+    // "entropy min max zeros printable-ratio"; min and max are "--" for empty slices
+    pub fn format(&self, is_decimal: bool, buf: &mut Vec<u8>) {
+        let ratio = if self.len == 0 {
+            0.0
+        } else {
+            self.printable as f64 / self.len as f64
+        };
+
+        write!(buf, "{:.4} ", self.entropy).unwrap();
+        if self.len == 0 {
+            buf.extend_from_slice(b"-- -- ");
+        } else {
+            write!(buf, "{:02x} {:02x} ", self.min, self.max).unwrap();
+        }
+        if is_decimal {
+            write!(buf, "{} ", self.zeros).unwrap();
+        } else {
+            write!(buf, "{:04x} ", self.zeros).unwrap();
+        }
+        write!(buf, "{ratio:.4}").unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ByteStats;
+
+    #[test]
+    fn test_byte_stats() {
+        macro_rules! test {
+            ( $input: expr, $is_decimal: expr, $expected: expr ) => {{
+                let mut buf = Vec::new();
+                ByteStats::new($input).format($is_decimal, &mut buf);
+                assert_eq!(std::str::from_utf8(&buf).unwrap(), $expected, "{:?}", $input);
+            }};
+        }
+
+        test!(b"", false, "0.0000 -- -- 0000 0.0000");
+        test!(b"o", false, "0.0000 6f 6f 0000 1.0000");
+        test!(b"\0\0\0\0", false, "0.0000 00 00 0004 0.0000");
+        test!(b"ab", false, "1.0000 61 62 0000 1.0000");
+        test!(b"abcd\0\0\xff\n", false, "2.7500 00 ff 0002 0.5000");
+        test!(b"abcd\0\0\xff\n", true, "2.7500 00 ff 2 0.5000");
+
+        let all: Vec<u8> = (0..=255).collect();
+        test!(&all, false, "8.0000 00 ff 0001 0.3711");
+        test!(&all.repeat(256), false, "8.0000 00 ff 0100 0.3711");
+    }
+}
+
+// end of stats.rs
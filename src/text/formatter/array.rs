@@ -0,0 +1,311 @@
+// @file array.rs
+// @author Hajime Suzuki
+// @brief C, Rust, and Python array formatters (the C one is compatible with xxd -i)
+
+use crate::eval::VarAttr;
+use crate::segment::Segment;
+use crate::template::Template;
+use crate::text::Dialect;
+use anyhow::Result;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::Write;
+
+// the same as xxd -i
+const BYTES_PER_LINE: usize = 12;
+
+// keywords separated by spaces
+const C_KEYWORDS: &str = "_Alignas _Alignof _Atomic _Bool _Complex _Generic _Imaginary _Noreturn _Static_assert \
+    _Thread_local alignas alignof auto bool break case char const constexpr continue default do double else enum extern \
+    false float for goto if inline int long nullptr register restrict return short signed sizeof static static_assert \
+    struct switch thread_local true typedef typeof typeof_unqual union unsigned void volatile while";
+
+// strict and reserved keywords of the 2021 edition; `_` is not an identifier either
+const RUST_KEYWORDS: &str = "_ Self abstract as async await become box break const continue crate do dyn else enum \
+    extern false final fn for if impl in let loop macro match mod move mut override priv pub ref return self static \
+    struct super trait true try type typeof unsafe unsized use virtual where while yield";
+
+// soft keywords (`match`, `case`, `type`, and `_`) are valid names
+const PYTHON_KEYWORDS: &str = "False None True and as assert async await break class continue def del elif else \
+    except finally for from global if import in is lambda nonlocal not or pass raise return try while with yield";
+
+// replaces characters not allowed in identifiers with '_', and appends '_' to keywords of the dialect
+fn sanitize_name(dialect: Dialect, name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+        .collect();
+    if sanitized.is_empty() || sanitized.as_bytes()[0].is_ascii_digit() {
+        sanitized.insert(0, '_');
+    }
+
+    let keywords = match dialect {
+        Dialect::C => C_KEYWORDS,
+        Dialect::Rust => RUST_KEYWORDS,
+        Dialect::Python => PYTHON_KEYWORDS,
+        _ => "",
+    };
+    if keywords.split_whitespace().any(|x| x == sanitized) {
+        sanitized.push('_');
+    }
+    sanitized
+}
+
+pub struct ArrayFormatter {
+    dialect: Dialect,
+
+    // name of the arrays; one array per slice if it has variables, or one for the whole stream otherwise
+    name: Template,
+    is_per_slice: bool,
+
+    // name and length of the array left open (whole-stream mode)
+    open: RefCell<Option<String>>,
+    len: Cell<usize>,
+
+    // #slices formatted so far; rendered as {l}
+    count: Cell<usize>,
+}
+
+impl ArrayFormatter {
+    pub fn new(dialect: Dialect, name: &str) -> Result<Self> {
+        let vars = [
+            (b"n", VarAttr { is_array: false, id: 0 }), // byte offset
+            (b"l", VarAttr { is_array: false, id: 1 }), // line
+        ];
+        let vars: HashMap<&[u8], VarAttr> = vars.iter().map(|(x, y)| (x.as_slice(), *y)).collect();
+        let name = Template::from_str(name, Some(&vars))?;
+        let is_per_slice = name.has_variable();
+
+        Ok(ArrayFormatter {
+            dialect,
+            name,
+            is_per_slice,
+            open: RefCell::new(None),
+            len: Cell::new(0),
+            count: Cell::new(0),
+        })
+    }
+
+    pub fn is_per_slice(&self) -> bool {
+        self.is_per_slice
+    }
+
+    fn render_name(&self, offset: usize) -> String {
+        let line = self.count.get();
+        let name = self
+            .name
+            .render(|id, _| match id {
+                0 => offset as i64,
+                1 => line as i64,
+                _ => 0,
+            })
+            .unwrap_or_default();
+        sanitize_name(self.dialect, &name)
+    }
+
+    fn format_header(&self, name: &str, buf: &mut Vec<u8>) {
+        match self.dialect {
+            Dialect::C => writeln!(buf, "unsigned char {name}[] = {{"),
+            Dialect::Rust => writeln!(buf, "pub static {name}: &[u8] = &["),
+            _ => writeln!(buf, "{name} = ("),
+        }
+        .unwrap();
+    }
+
+    // `pos` is the index of the byte in the array
+    fn format_bytes(&self, pos: usize, src: &[u8], buf: &mut Vec<u8>) {
+        for (i, &x) in src.iter().enumerate() {
+            let is_head = (pos + i) % BYTES_PER_LINE == 0;
+            let is_tail = (pos + i) % BYTES_PER_LINE == BYTES_PER_LINE - 1;
+
+            // "  0x48, 0x65,\n  0x6c", "    0x48, 0x65,\n", and "    b\"\\x48\\x65\"\n"
+            match (self.dialect, is_head) {
+                (Dialect::C, true) if pos + i == 0 => buf.extend_from_slice(b"  "),
+                (Dialect::C, true) => buf.extend_from_slice(b",\n  "),
+                (Dialect::C, false) => buf.extend_from_slice(b", "),
+                (Dialect::Rust, true) => buf.extend_from_slice(b"    "),
+                (Dialect::Rust, false) => buf.push(b' '),
+                (_, true) => buf.extend_from_slice(b"    b\""),
+                _ => {}
+            }
+
+            let digits = [b"0123456789abcdef"[(x >> 4) as usize], b"0123456789abcdef"[(x & 0x0f) as usize]];
+            match self.dialect {
+                Dialect::C | Dialect::Rust => buf.extend_from_slice(b"0x"),
+                _ => buf.extend_from_slice(b"\\x"),
+            }
+            buf.extend_from_slice(&digits);
+
+            match self.dialect {
+                Dialect::Rust => buf.push(b','),
+                Dialect::Python if is_tail => buf.push(b'"'),
+                _ => {}
+            }
+            if is_tail && self.dialect != Dialect::C {
+                buf.push(b'\n');
+            }
+        }
+    }
+
+    // closes the last line if it's not full
+    fn format_footer(&self, name: &str, len: usize, buf: &mut Vec<u8>) {
+        let is_open = len % BYTES_PER_LINE != 0;
+        match self.dialect {
+            Dialect::C if len > 0 => buf.push(b'\n'),
+            Dialect::Rust if is_open => buf.push(b'\n'),
+            Dialect::Python if is_open => buf.extend_from_slice(b"\"\n"),
+            Dialect::Python if len == 0 => buf.extend_from_slice(b"    b\"\"\n"),
+            _ => {}
+        }
+
+        match self.dialect {
+            Dialect::C => writeln!(buf, "}};\nunsigned int {name}_len = {len};"),
+            Dialect::Rust => writeln!(buf, "];"),
+            _ => writeln!(buf, ")"),
+        }
+        .unwrap();
+    }
+
+    pub fn format_segments(&self, offset: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
+        for s in segments {
+            let src = &stream[s.as_range()];
+
+            if self.is_per_slice {
+                let name = self.render_name(offset + s.pos);
+                self.format_header(&name, buf);
+                self.format_bytes(0, src, buf);
+                self.format_footer(&name, src.len(), buf);
+            } else {
+                let mut open = self.open.borrow_mut();
+                if open.is_none() {
+                    let name = self.render_name(offset + s.pos);
+                    self.format_header(&name, buf);
+                    *open = Some(name);
+                    self.len.set(0);
+                }
+                self.format_bytes(self.len.get(), src, buf);
+                self.len.set(self.len.get() + src.len());
+            }
+            self.count.set(self.count.get() + 1);
+        }
+    }
+
+    // closes the array of the whole stream; an empty one if nothing has been formatted
+    pub fn format_trailer(&self, buf: &mut Vec<u8>) {
+        if self.is_per_slice {
+            return;
+        }
+
+        let name = match self.open.borrow_mut().take() {
+            Some(name) => name,
+            None => {
+                let name = self.render_name(0);
+                self.format_header(&name, buf);
+                self.len.set(0);
+                name
+            }
+        };
+        self.format_footer(&name, self.len.get(), buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ArrayFormatter;
+    use crate::segment::Segment;
+    use crate::text::Dialect;
+
+    fn format(dialect: Dialect, name: &str, stream: &[u8], segments: &[Segment]) -> String {
+        let formatter = ArrayFormatter::new(dialect, name).unwrap();
+        let mut buf = Vec::new();
+        formatter.format_segments(16, stream, segments, &mut buf);
+        formatter.format_trailer(&mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_format_c() {
+        let stream = b"Hello, world!\n";
+        let segments = [Segment { pos: 0, len: 14, tag: 0 }];
+
+        // the same as `xxd -i hello.txt`
+        assert_eq!(
+            format(Dialect::C, "hello.txt", stream, &segments),
+            "unsigned char hello_txt[] = {\n  \
+             0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64,\n  \
+             0x21, 0x0a\n\
+             };\nunsigned int hello_txt_len = 14;\n"
+        );
+        assert_eq!(
+            format(Dialect::C, "empty", stream, &[]),
+            "unsigned char empty[] = {\n};\nunsigned int empty_len = 0;\n"
+        );
+
+        // one array per slice, and the whole stream continued across the slices
+        let segments = [Segment { pos: 0, len: 2, tag: 0 }, Segment { pos: 7, len: 1, tag: 0 }];
+        assert_eq!(
+            format(Dialect::C, "s_{n:x}_{l}", stream, &segments),
+            "unsigned char s_10_0[] = {\n  0x48, 0x65\n};\nunsigned int s_10_0_len = 2;\n\
+             unsigned char s_17_1[] = {\n  0x77\n};\nunsigned int s_17_1_len = 1;\n"
+        );
+        assert_eq!(
+            format(Dialect::C, "0a", stream, &segments),
+            "unsigned char _0a[] = {\n  0x48, 0x65, 0x77\n};\nunsigned int _0a_len = 3;\n"
+        );
+
+        // keywords are suffixed with '_'
+        assert_eq!(
+            format(Dialect::C, "int", stream, &[]),
+            "unsigned char int_[] = {\n};\nunsigned int int__len = 0;\n"
+        );
+    }
+
+    #[test]
+    fn test_format_rust() {
+        let stream = b"Hello, world!\n";
+        let segments = [Segment { pos: 0, len: 14, tag: 0 }];
+
+        assert_eq!(
+            format(Dialect::Rust, "HELLO", stream, &segments),
+            "pub static HELLO: &[u8] = &[\n    \
+             0x48, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64,\n    \
+             0x21, 0x0a,\n\
+             ];\n"
+        );
+        assert_eq!(format(Dialect::Rust, "E", stream, &[]), "pub static E: &[u8] = &[\n];\n");
+
+        // keywords are suffixed with '_'
+        assert_eq!(format(Dialect::Rust, "fn", stream, &[]), "pub static fn_: &[u8] = &[\n];\n");
+        assert_eq!(format(Dialect::Rust, "type", stream, &[]), "pub static type_: &[u8] = &[\n];\n");
+        assert_eq!(format(Dialect::Rust, "class", stream, &[]), "pub static class: &[u8] = &[\n];\n");
+    }
+
+    #[test]
+    fn test_format_python() {
+        let stream = b"Hello, world!\n";
+        let segments = [Segment { pos: 0, len: 14, tag: 0 }];
+
+        assert_eq!(
+            format(Dialect::Python, "hello", stream, &segments),
+            "hello = (\n    \
+             b\"\\x48\\x65\\x6c\\x6c\\x6f\\x2c\\x20\\x77\\x6f\\x72\\x6c\\x64\"\n    \
+             b\"\\x21\\x0a\"\n\
+             )\n"
+        );
+        assert_eq!(format(Dialect::Python, "e", stream, &[]), "e = (\n    b\"\"\n)\n");
+
+        // keywords are suffixed with '_'; soft keywords are not
+        assert_eq!(format(Dialect::Python, "class", stream, &[]), "class_ = (\n    b\"\"\n)\n");
+        assert_eq!(format(Dialect::Python, "None", stream, &[]), "None_ = (\n    b\"\"\n)\n");
+        assert_eq!(format(Dialect::Python, "type", stream, &[]), "type = (\n    b\"\"\n)\n");
+
+        // lines continued across the slices
+        let segments = [Segment { pos: 0, len: 4, tag: 0 }, Segment { pos: 4, len: 10, tag: 0 }];
+        assert_eq!(
+            format(Dialect::Python, "hello", stream, &segments),
+            format(Dialect::Python, "hello", stream, &[Segment { pos: 0, len: 14, tag: 0 }])
+        );
+    }
+}
+
+// end of array.rs
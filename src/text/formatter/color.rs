@@ -0,0 +1,142 @@
+// @file color.rs
+// @author Hajime Suzuki
+// @brief ANSI colors of the byte classes, and highlights of the matched regions
+
+use crate::segment::MatchRanges;
+use std::io::Write;
+use std::ops::Range;
+
+pub const HEADER: &[u8] = b"\x1b[34m";
+pub const RESET: &[u8] = b"\x1b[0m";
+
+// SGR colors of NUL, whitespace, printable, control, 0xff, and the other (non-ascii) bytes
+fn class_color(x: u8) -> u8 {
+    match x {
+        0x00 => 90,
+        b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r' => 32,
+        0x21..=0x7e => 36,
+        0x01..=0x1f | 0x7f => 35,
+        0xff => 31,
+        _ => 33,
+    }
+}
+
+pub struct Painter {
+    // None if no --find nor --regex
+    highlights: Option<MatchRanges>,
+}
+
+impl Painter {
+    pub fn new(highlights: Option<MatchRanges>) -> Self {
+        Painter { highlights }
+    }
+
+    // the matched regions in the line at `offset`, relative to the line; the ones before it are dropped
+    fn collect_highlights(&self, offset: usize, len: usize) -> Vec<Range<usize>> {
+        let Some(highlights) = &self.highlights else {
+            return Vec::new();
+        };
+
+        let mut ranges = highlights.lock().unwrap();
+        while ranges.front().is_some_and(|x| x.end <= offset) {
+            ranges.pop_front();
+        }
+        ranges
+            .iter()
+            .take_while(|x| x.start < offset + len)
+            .filter(|x| x.end > offset)
+            .map(|x| x.start.saturating_sub(offset)..x.end - offset)
+            .collect()
+    }
+
+    pub fn line<'a>(&self, offset: usize, src: &'a [u8]) -> LinePainter<'a> {
+        LinePainter {
+            src,
+            highlights: self.collect_highlights(offset, src.len()),
+            current: None,
+        }
+    }
+}
+
+pub struct LinePainter<'a> {
+    src: &'a [u8],
+    highlights: Vec<Range<usize>>,
+
+    // (color, is_highlighted) of the last byte painted
+    current: Option<(u8, bool)>,
+}
+
+impl<'a> LinePainter<'a> {
+    fn is_highlighted(&self, i: usize) -> bool {
+        self.highlights.iter().any(|x| x.contains(&i))
+    }
+
+    fn switch(&mut self, style: (u8, bool), buf: &mut Vec<u8>) {
+        if self.current == Some(style) {
+            return;
+        }
+        self.current = Some(style);
+
+        // reverse video for the matched regions
+        let (color, is_highlighted) = style;
+        write!(buf, "\x1b[0;{}{}m", color, if is_highlighted { ";7" } else { "" }).unwrap();
+    }
+
+    // switches the color for the `i`-th byte of the line if it differs from the previous one
+    pub fn paint(&mut self, i: usize, buf: &mut Vec<u8>) {
+        self.switch((class_color(self.src[i]), self.is_highlighted(i)), buf);
+    }
+
+    // for the separator between the `i - 1`-th and `i`-th bytes; highlighted only inside a match
+    pub fn paint_sep(&mut self, i: usize, buf: &mut Vec<u8>) {
+        let is_highlighted = self.is_highlighted(i - 1) && self.is_highlighted(i);
+        self.switch((class_color(self.src[i]), is_highlighted), buf);
+    }
+
+    pub fn reset(&mut self, buf: &mut Vec<u8>) {
+        if self.current.take().is_some() {
+            buf.extend_from_slice(RESET);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Painter;
+    use crate::segment::MatchRanges;
+
+    fn paint(painter: &Painter, offset: usize, src: &[u8]) -> Vec<u8> {
+        let mut line = painter.line(offset, src);
+        let mut buf = Vec::new();
+        for (i, &x) in src.iter().enumerate() {
+            line.paint(i, &mut buf);
+            buf.push(x);
+        }
+        line.reset(&mut buf);
+        buf
+    }
+
+    #[test]
+    fn test_painter() {
+        let painter = Painter::new(None);
+        assert_eq!(paint(&painter, 0, b""), b"");
+        assert_eq!(
+            paint(&painter, 0, b"ab \x00\x01\xff\x80"),
+            b"\x1b[0;36mab\x1b[0;32m \x1b[0;90m\x00\x1b[0;35m\x01\x1b[0;31m\xff\x1b[0;33m\x80\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn test_painter_highlights() {
+        let ranges = MatchRanges::default();
+        ranges.lock().unwrap().extend([2..4, 6..9, 12..13]);
+        let painter = Painter::new(Some(ranges.clone()));
+
+        // the second line at 5 drops the first match
+        assert_eq!(paint(&painter, 0, b"abcd"), b"\x1b[0;36mab\x1b[0;36;7mcd\x1b[0m");
+        assert_eq!(paint(&painter, 5, b"abcd"), b"\x1b[0;36ma\x1b[0;36;7mbcd\x1b[0m");
+        assert_eq!(ranges.lock().unwrap().len(), 2);
+    }
+}
+
+// end of color.rs
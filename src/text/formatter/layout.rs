@@ -0,0 +1,271 @@
+// @file layout.rs
+// @author Hajime Suzuki
+// @brief formatter for the native form with grouped, unseparated, or uppercase hex arrays,
+// arrays of octal, decimal, or binary digits, and non-ascii mosaics
+
+use super::color::{Painter, HEADER, RESET};
+use super::mosaic::MosaicFormatter;
+use crate::segment::{MatchRanges, Segment};
+use crate::text::{ColumnFormat, HexLayout, Mosaic};
+use std::io::Write;
+
+// the array and the mosaic of the lines
+pub struct LineLayout {
+    body: ColumnFormat,
+    layout: HexLayout,
+    mosaic: MosaicFormatter,
+
+    // ANSI colors; None for the plain text
+    painter: Option<Painter>,
+}
+
+impl LineLayout {
+    pub fn new(body: &ColumnFormat, layout: &HexLayout, mosaic: Mosaic) -> Self {
+        LineLayout {
+            body: body.clone(),
+            layout: *layout,
+            mosaic: MosaicFormatter::new(mosaic),
+            painter: None,
+        }
+    }
+
+    pub fn with_mosaic(self, mosaic: Mosaic) -> Self {
+        LineLayout {
+            mosaic: MosaicFormatter::new(mosaic),
+            ..self
+        }
+    }
+
+    pub fn with_colors(self, highlights: Option<MatchRanges>) -> Self {
+        LineLayout {
+            painter: Some(Painter::new(highlights)),
+            ..self
+        }
+    }
+}
+
+// #columns of a byte
+fn cell_width(body: &ColumnFormat) -> usize {
+    match body {
+        ColumnFormat::Octal | ColumnFormat::Decimal => 3,
+        ColumnFormat::Bits => 8,
+        _ => 2,
+    }
+}
+
+// width of the array of `len` bytes
+fn body_width(body: &ColumnFormat, layout: &HexLayout, len: usize) -> usize {
+    let groups = (len + layout.group - 1) / layout.group;
+    let seps = if layout.sep.is_some() { groups.saturating_sub(1) } else { 0 };
+    cell_width(body) * len + seps
+}
+
+// "48", "110", " 72", or "01001000"
+fn push_cell(body: &ColumnFormat, digits: &[u8; 16], x: u8, buf: &mut Vec<u8>) {
+    match body {
+        ColumnFormat::Octal => {
+            buf.extend_from_slice(&[digits[(x >> 6) as usize], digits[((x >> 3) & 7) as usize], digits[(x & 7) as usize]])
+        }
+        ColumnFormat::Decimal => {
+            let (h, t, o) = (x / 100, x / 10 % 10, x % 10);
+            buf.push(if h > 0 { digits[h as usize] } else { b' ' });
+            buf.push(if h > 0 || t > 0 { digits[t as usize] } else { b' ' });
+            buf.push(digits[o as usize]);
+        }
+        ColumnFormat::Bits => buf.extend((0..8).rev().map(|i| digits[((x >> i) & 1) as usize])),
+        _ => buf.extend_from_slice(&[digits[(x >> 4) as usize], digits[(x & 0x0f) as usize]]),
+    }
+}
+
+fn format_line(line: &LineLayout, offset: usize, is_decimal: bool, src: &[u8], width: usize, buf: &mut Vec<u8>) {
+    let (body, layout) = (&line.body, &line.layout);
+    let digits = if layout.is_upper {
+        b"0123456789ABCDEF"
+    } else {
+        b"0123456789abcdef"
+    };
+
+    // header; the same widths as the default formatter
+    let mut painter = line.painter.as_ref().map(|painter| painter.line(offset, src));
+    if painter.is_some() {
+        buf.extend_from_slice(HEADER);
+    }
+    if is_decimal {
+        write!(buf, "{} {}", offset, src.len()).unwrap();
+    } else {
+        let len_cols = 2 * (8 - ((src.len() | 0xffff).leading_zeros() as usize) / 8);
+        if layout.is_upper {
+            write!(buf, "{:012X} {:0len_cols$X}", offset, src.len()).unwrap();
+        } else {
+            write!(buf, "{:012x} {:0len_cols$x}", offset, src.len()).unwrap();
+        }
+    }
+    if painter.is_some() {
+        buf.extend_from_slice(RESET);
+    }
+    buf.extend_from_slice(b" | ");
+
+    // array; the last group may be shorter than the others
+    for (i, group) in src.chunks(layout.group).enumerate() {
+        let base = i * layout.group;
+        if let (true, Some(sep)) = (i > 0, layout.sep) {
+            if let Some(painter) = &mut painter {
+                painter.paint_sep(base, buf);
+            }
+            buf.push(sep);
+        }
+        for j in 0..group.len() {
+            let j = if layout.is_le { group.len() - 1 - j } else { j };
+            if let Some(painter) = &mut painter {
+                painter.paint(base + j, buf);
+            }
+            push_cell(body, digits, group[j], buf);
+        }
+    }
+    if let Some(painter) = &mut painter {
+        painter.reset(buf);
+    }
+    buf.resize(
+        buf.len() + body_width(body, layout, width) - body_width(body, layout, src.len()),
+        b' ',
+    );
+    buf.extend_from_slice(b" | ");
+
+    line.mosaic.format(src, width, &mut painter, buf);
+    buf.push(b'\n');
+}
+
+pub fn format_segments_layout(
+    line: &LineLayout,
+    offset: usize,
+    min_width: usize,
+    is_decimal: bool,
+    stream: &[u8],
+    segments: &[Segment],
+    buf: &mut Vec<u8>,
+) {
+    for s in segments {
+        let src = &stream[s.as_range()];
+        format_line(line, offset + s.pos, is_decimal, src, std::cmp::max(min_width, s.len), buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_segments_layout, LineLayout};
+    use crate::segment::Segment;
+    use crate::text::{HexLayout, InoutFormat, Mosaic};
+
+    fn format(sig: &str, min_width: usize, is_decimal: bool, stream: &[u8]) -> String {
+        format_with_mosaic(sig, Mosaic::Ascii, min_width, is_decimal, stream)
+    }
+
+    fn format_with_mosaic(sig: &str, mosaic: Mosaic, min_width: usize, is_decimal: bool, stream: &[u8]) -> String {
+        let format = InoutFormat::from_str(sig).unwrap();
+        let segments = [Segment {
+            pos: 0,
+            len: stream.len(),
+            tag: 0,
+        }];
+
+        let mut buf = Vec::new();
+        format_segments_layout(
+            &LineLayout::new(&format.body, &format.layout, mosaic),
+            0x10,
+            min_width,
+            is_decimal,
+            stream,
+            &segments,
+            &mut buf,
+        );
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_format_layout() {
+        let stream = b"Hello.\n";
+
+        // the same as the default formatter
+        assert_eq!(
+            format("x", 8, false, stream),
+            "000000000010 0007 | 48 65 6c 6c 6f 2e 0a    | Hello.. \n"
+        );
+        assert_eq!(format("ddx+upper", 0, true, b"\xab"), "16 1 | AB | .\n");
+
+        assert_eq!(
+            format("x+plain", 8, false, stream),
+            "000000000010 0007 | 48656c6c6f2e0a   | Hello.. \n"
+        );
+        assert_eq!(
+            format("x+g2", 8, false, stream),
+            "000000000010 0007 | 4865 6c6c 6f2e 0a   | Hello.. \n"
+        );
+        assert_eq!(
+            format("x+g4le+upper", 8, false, stream),
+            "000000000010 0007 | 6C6C6548 0A2E6F   | Hello.. \n"
+        );
+        assert_eq!(
+            format("x+g8be+sep=:", 0, false, stream),
+            "000000000010 0007 | 48656c6c6f2e0a | Hello..\n"
+        );
+        assert_eq!(
+            format("x+sep=:", 0, false, stream),
+            "000000000010 0007 | 48:65:6c:6c:6f:2e:0a | Hello..\n"
+        );
+        assert_eq!(format("x+g2", 4, false, b""), "000000000010 0000 |           |     \n");
+
+        assert!(!InoutFormat::from_str("x+g4le").unwrap().layout.is_default());
+        assert_eq!(InoutFormat::from_str("xxx").unwrap().layout, HexLayout::default());
+    }
+
+    #[test]
+    fn test_format_digits() {
+        let stream = b"Hi\x00\xff";
+
+        assert_eq!(
+            format("x,x,o", 6, false, stream),
+            "000000000010 0004 | 110 151 000 377         | Hi..  \n"
+        );
+        assert_eq!(format("d,d,d", 0, true, stream), "16 4 |  72 105   0 255 | Hi..\n");
+        assert_eq!(
+            format("n,n,B", 0, false, stream),
+            "000000000010 0004 | 01001000 01101001 00000000 11111111 | Hi..\n"
+        );
+        assert!(InoutFormat::from_str("x,x,o").unwrap().is_digit_array());
+        assert!(!InoutFormat::from_str("x+g2").unwrap().is_digit_array());
+    }
+
+    #[test]
+    fn test_format_mosaic() {
+        // padded to the same width as the ascii one
+        assert_eq!(
+            format_with_mosaic("x", Mosaic::Utf8, 4, false, "é!".as_bytes()),
+            "000000000010 0003 | c3 a9 21    | é ! \n"
+        );
+        assert_eq!(
+            format_with_mosaic("x,x,o", Mosaic::Pictures, 0, false, b"a\n"),
+            "000000000010 0002 | 141 012 | a\u{240a}\n"
+        );
+    }
+
+    #[test]
+    fn test_digits_err() {
+        assert!(InoutFormat::from_str("o,x,x").is_err());
+        assert!(InoutFormat::from_str("x,B,x").is_err());
+        assert!(InoutFormat::from_str("x,x,o+g2").is_err());
+    }
+
+    #[test]
+    fn test_hex_layout_err() {
+        assert!(InoutFormat::from_str("x+g3").is_err());
+        assert!(InoutFormat::from_str("x+g4xe").is_err());
+        assert!(InoutFormat::from_str("x+sep=a").is_err());
+        assert!(InoutFormat::from_str("x+sep=|").is_err());
+        assert!(InoutFormat::from_str("x+sep=::").is_err());
+        assert!(InoutFormat::from_str("x+lower").is_err());
+        assert!(InoutFormat::from_str("b+upper").is_err());
+        assert!(InoutFormat::from_str("ihex+upper").is_err());
+    }
+}
+
+// end of layout.rs
@@ -2,14 +2,28 @@
 // @author Hajime Suzuki
 // @brief formatter implementations
 
+mod array;
+mod color;
 mod dec;
 mod hex;
+mod layout;
+mod mosaic;
+mod record;
+mod table;
 
+use self::array::ArrayFormatter;
 use self::dec::format_dec_single;
 use self::hex::{format_hex_body, format_hex_single, format_mosaic};
-use super::{ColumnFormat, InoutFormat};
+use self::layout::{format_segments_layout, LineLayout};
+use self::record::RecordFormatter;
+use self::table::{format_json_label, format_segments_csv, format_segments_csv_ascii, format_segments_json, format_segments_json_ascii};
+use super::{ColumnFormat, Dialect, HexLayout, InoutFormat, Mosaic};
 use crate::filluninit::FillUninit;
-use crate::segment::Segment;
+use crate::hash::HashAlgorithm;
+use crate::segment::{MatchRanges, Segment};
+use crate::stats::ByteStats;
+use anyhow::Result;
+use std::io::Write;
 
 fn format_segments_binary(_offset: usize, _min_width: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
     for s in segments {
@@ -141,6 +155,25 @@ pub struct TextFormatter {
     formatter: FormatSegments,
     offset: (usize, usize),
     min_width: usize,
+    is_binary: bool,
+    is_decimal: bool,
+    dialect: Dialect,
+
+    // grouped, unseparated, or uppercase hex arrays, arrays of octal, decimal, or binary digits, or
+    // non-ascii mosaics; None for the default hex layout and the ascii mosaic
+    layout: Option<LineLayout>,
+
+    // byte statistics in place of the array and the mosaic
+    stats: bool,
+
+    // digest columns appended after the mosaic
+    hashes: Vec<HashAlgorithm>,
+
+    // Intel HEX or S-record output; bypasses the formatter above
+    records: Option<RecordFormatter>,
+
+    // C, Rust, or Python arrays; bypasses the formatter above as well
+    arrays: Option<ArrayFormatter>,
 }
 
 impl TextFormatter {
@@ -148,10 +181,20 @@ impl TextFormatter {
         let formatter = if format.is_binary() {
             format_segments_binary
         } else {
-            match (&format.offset, &format.span, &format.body) {
-                (ColumnFormat::Hexadecimal, ColumnFormat::Hexadecimal, ColumnFormat::Hexadecimal) => format_segments_hhh,
-                (ColumnFormat::None, ColumnFormat::None, ColumnFormat::Hexadecimal) => format_segments_hhh,
-                (ColumnFormat::Decimal, ColumnFormat::Decimal, ColumnFormat::Hexadecimal) => format_segments_ddh,
+            // octal, decimal, and binary-digit arrays share the headers with the hex ones; see format_lines
+            let body = if format.is_digit_array() {
+                &ColumnFormat::Hexadecimal
+            } else {
+                &format.body
+            };
+            match (format.dialect, &format.offset, &format.span, body) {
+                (Dialect::Json(false), ..) => format_segments_json,
+                (Dialect::Json(true), ..) => format_segments_json_ascii,
+                (Dialect::Csv(false), ..) => format_segments_csv,
+                (Dialect::Csv(true), ..) => format_segments_csv_ascii,
+                (_, ColumnFormat::Hexadecimal, ColumnFormat::Hexadecimal, ColumnFormat::Hexadecimal) => format_segments_hhh,
+                (_, ColumnFormat::None, ColumnFormat::None, ColumnFormat::Hexadecimal) => format_segments_hhh,
+                (_, ColumnFormat::Decimal, ColumnFormat::Decimal, ColumnFormat::Hexadecimal) => format_segments_ddh,
                 _ => panic!("unsupported formatters: {:?}, {:?}, {:?}", format.offset, format.span, format.body),
             }
         };
@@ -160,11 +203,173 @@ impl TextFormatter {
             formatter,
             offset,
             min_width: format.cols,
+            is_binary: format.is_binary(),
+            is_decimal: format.offset == ColumnFormat::Decimal,
+            dialect: format.dialect,
+            layout: (format.is_digit_array() || !format.layout.is_default())
+                .then(|| LineLayout::new(&format.body, &format.layout, Mosaic::Ascii)),
+            stats: false,
+            hashes: Vec::new(),
+            records: match format.dialect {
+                Dialect::Native | Dialect::Json(_) | Dialect::Csv(_) => None,
+                Dialect::IntelHex | Dialect::Srec => Some(RecordFormatter::new(format.dialect, format.cols)),
+                Dialect::C | Dialect::Rust | Dialect::Python => None,
+                dialect => panic!("unsupported formatter: {dialect:?}"),
+            },
+            arrays: match format.dialect {
+                Dialect::C | Dialect::Rust | Dialect::Python => Some(ArrayFormatter::new(format.dialect, "slice_{n:x}").unwrap()),
+                _ => None,
+            },
+        }
+    }
+
+    // name template of the arrays; ignored for the other dialects
+    pub fn with_array_name(self, name: &str) -> Result<Self> {
+        let arrays = match &self.arrays {
+            Some(_) => Some(ArrayFormatter::new(self.dialect, name)?),
+            None => None,
+        };
+        Ok(TextFormatter { arrays, ..self })
+    }
+
+    // true if the output is a single array spanning all the slices
+    pub fn is_whole_array(&self) -> bool {
+        self.arrays.as_ref().is_some_and(|arrays| !arrays.is_per_slice())
+    }
+
+    pub fn with_hashes(self, hashes: &[HashAlgorithm]) -> Self {
+        TextFormatter {
+            hashes: hashes.to_vec(),
+            ..self
+        }
+    }
+
+    // ignored for the formats other than the native ones
+    pub fn with_mosaic(self, mosaic: Mosaic) -> Self {
+        if mosaic == Mosaic::Ascii || self.dialect != Dialect::Native || self.is_binary {
+            return self;
+        }
+        // None is the default hex layout here
+        let layout = match self.layout {
+            Some(layout) => layout.with_mosaic(mosaic),
+            None => LineLayout::new(&ColumnFormat::Hexadecimal, &HexLayout::default(), mosaic),
+        };
+        TextFormatter {
+            layout: Some(layout),
+            ..self
+        }
+    }
+
+    // ANSI colors of the bytes and the matched regions; ignored for the formats other than the native ones
+    pub fn with_colors(self, highlights: Option<MatchRanges>) -> Self {
+        if self.dialect != Dialect::Native || self.is_binary {
+            return self;
+        }
+        let layout = match self.layout {
+            Some(layout) => layout,
+            None => LineLayout::new(&ColumnFormat::Hexadecimal, &HexLayout::default(), Mosaic::Ascii),
+        };
+        TextFormatter {
+            layout: Some(layout.with_colors(highlights)),
+            ..self
+        }
+    }
+
+    pub fn with_stats(self, stats: bool) -> Self {
+        TextFormatter { stats, ..self }
+    }
+
+    // "offset span | | stats\n"; the array is left empty so that the line can be parsed as a record
+    fn format_stats(&self, offset: usize, src: &[u8], buf: &mut Vec<u8>) {
+        if self.is_decimal {
+            write!(buf, "{} {} | | ", offset, src.len()).unwrap();
+        } else {
+            let len_cols = 2 * (8 - ((src.len() | 0xffff).leading_zeros() as usize) / 8);
+            write!(buf, "{:012x} {:0len_cols$x} | | ", offset, src.len()).unwrap();
+        }
+        ByteStats::new(src).format(self.is_decimal, buf);
+        buf.push(b'\n');
+    }
+
+    fn format_lines(&self, offset: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
+        match &self.layout {
+            Some(array) => format_segments_layout(array, offset, self.min_width, self.is_decimal, stream, segments, buf),
+            None => (self.formatter)(offset, self.min_width, stream, segments, buf),
         }
     }
 
     pub fn format_segments(&self, offset: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
-        (self.formatter)(self.offset.0 + offset, self.min_width, stream, segments, buf);
+        if let Some(records) = &self.records {
+            records.format_segments(self.offset.0 + offset, stream, segments, buf);
+            return;
+        }
+        if let Some(arrays) = &self.arrays {
+            arrays.format_segments(self.offset.0 + offset, stream, segments, buf);
+            return;
+        }
+        if (self.hashes.is_empty() && !self.stats) || self.is_binary || self.dialect != Dialect::Native {
+            self.format_lines(self.offset.0 + offset, stream, segments, buf);
+            return;
+        }
+
+        let mut digest = Vec::new();
+        for s in segments {
+            if self.stats {
+                self.format_stats(self.offset.0 + offset + s.pos, &stream[s.as_range()], buf);
+            } else {
+                self.format_lines(self.offset.0 + offset, stream, std::slice::from_ref(s), buf);
+            }
+
+            debug_assert!(buf.last() == Some(&b'\n'));
+            buf.pop();
+            for hash in &self.hashes {
+                digest.clear();
+                hash.digest(&stream[s.as_range()], &mut digest);
+
+                buf.extend_from_slice(b" | ");
+                for x in &digest {
+                    buf.push(b"0123456789abcdef"[(x >> 4) as usize]);
+                    buf.push(b"0123456789abcdef"[(x & 0x0f) as usize]);
+                }
+            }
+            buf.push(b'\n');
+        }
+    }
+
+    // makes the next output independent of the preceding ones (e.g. for a slice dumped to its own file)
+    pub fn reset(&self) {
+        if let Some(records) = &self.records {
+            records.reset();
+        }
+    }
+
+    // end-of-file record after all slices for Intel HEX and S-records, or the end of the array
+    // spanning all the slices
+    pub fn format_trailer(&self, buf: &mut Vec<u8>) {
+        if let Some(records) = &self.records {
+            records.format_trailer(buf);
+        }
+        if let Some(arrays) = &self.arrays {
+            arrays.format_trailer(buf);
+        }
+    }
+
+    // appends a label column after the mosaic of the last record, or a "label" field for JSON; ignored
+    // in the binary mode and for the other dialects
+    pub fn format_label(&self, label: &[u8], buf: &mut Vec<u8>) {
+        if matches!(self.dialect, Dialect::Json(_)) {
+            format_json_label(label, buf);
+            return;
+        }
+        if self.is_binary || self.dialect != Dialect::Native {
+            return;
+        }
+
+        debug_assert!(buf.last() == Some(&b'\n'));
+        buf.pop();
+        buf.extend_from_slice(b" | ");
+        buf.extend_from_slice(label);
+        buf.push(b'\n');
     }
 }
 
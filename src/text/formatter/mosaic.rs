@@ -0,0 +1,194 @@
+// @file mosaic.rs
+// @author Hajime Suzuki
+// @brief mosaic in Latin-1, CP437, EBCDIC, UTF-8, or with control pictures; one column per byte
+
+use super::color::LinePainter;
+use crate::text::Mosaic;
+
+// glyphs of 0x00..=0xff, 32 bytes per line; '.' for the blank ones (NUL and NBSP)
+const CP437: &str = concat!(
+    r##".☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼"##,
+    r##" !"#$%&'()*+,-./0123456789:;<=>?"##,
+    r##"@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\]^_"##,
+    r##"`abcdefghijklmnopqrstuvwxyz{|}~⌂"##,
+    r##"ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒ"##,
+    r##"áíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐"##,
+    r##"└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀"##,
+    r##"αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■."##
+);
+
+// code page 037; '.' for controls, NBSP, and the soft hyphen
+const EBCDIC: &str = concat!(
+    r##"................................"##,
+    r##"................................"##,
+    r##" .âäàáãåçñ¢.<(+|&éêëèíîïìß!$*);¬"##,
+    r##"-/ÂÄÀÁÃÅÇÑ¦,%_>?øÉÊËÈÍÎÏÌ`:#@'=""##,
+    r##"Øabcdefghi«»ðýþ±°jklmnopqrªºæ¸Æ¤"##,
+    r##"µ~stuvwxyz¡¿ÐÝÞ®^£¥·©§¶¼½¾[]¯¨´×"##,
+    r##"{ABCDEFGHI.ôöòóõ}JKLMNOPQR¹ûüùúÿ"##,
+    r##"\÷STUVWXYZ²ÔÖÒÓÕ0123456789³ÛÜÙÚ."##
+);
+
+// non-spacing and invisible characters that would break the column alignment; not exhaustive
+fn is_invisible(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c as u32,
+            0xa0 | 0xad
+                | 0x300..=0x36f
+                | 0x483..=0x489
+                | 0x591..=0x5c7
+                | 0x610..=0x61a
+                | 0x64b..=0x65f
+                | 0x1ab0..=0x1aff
+                | 0x1dc0..=0x1dff
+                | 0x200b..=0x200f
+                | 0x2028..=0x202e
+                | 0x2060..=0x206f
+                | 0x20d0..=0x20ff
+                | 0xfe00..=0xfe0f
+                | 0xfe20..=0xfe2f
+                | 0xfeff
+                | 0xe0000..=0xe0fff
+        )
+}
+
+// East Asian wide characters and emoji take two columns
+fn is_wide(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x115f
+            | 0x2e80..=0x303e
+            | 0x3041..=0x33ff
+            | 0x3400..=0x4dbf
+            | 0x4e00..=0x9fff
+            | 0xa000..=0xa4cf
+            | 0xac00..=0xd7a3
+            | 0xf900..=0xfaff
+            | 0xfe30..=0xfe4f
+            | 0xff00..=0xff60
+            | 0xffe0..=0xffe6
+            | 0x1f300..=0x1f64f
+            | 0x1f680..=0x1f6ff
+            | 0x1f900..=0x1f9ff
+            | 0x20000..=0x3fffd
+    )
+}
+
+fn utf8_len(lead: u8) -> usize {
+    match lead {
+        0xc2..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf4 => 4,
+        _ => 1,
+    }
+}
+
+pub struct MosaicFormatter {
+    mosaic: Mosaic,
+
+    // glyph of each byte; unused for UTF-8
+    glyphs: Vec<char>,
+}
+
+impl MosaicFormatter {
+    pub fn new(mosaic: Mosaic) -> Self {
+        let glyphs = match mosaic {
+            Mosaic::Cp437 => CP437.chars().collect(),
+            Mosaic::Ebcdic => EBCDIC.chars().collect(),
+            _ => (0..=255u8)
+                .map(|x| match (mosaic, x) {
+                    (_, 0x20..=0x7e) => x as char,
+                    (Mosaic::Latin1, 0xa1..=0xff) if x != 0xad => x as char,
+                    (Mosaic::Pictures, 0x00..=0x1f) => char::from_u32(0x2400 + x as u32).unwrap(),
+                    (Mosaic::Pictures, 0x7f) => '\u{2421}',
+                    _ => '.',
+                })
+                .collect(),
+        };
+        MosaicFormatter { mosaic, glyphs }
+    }
+
+    fn format_utf8(&self, src: &[u8], painter: &mut Option<LinePainter>, buf: &mut Vec<u8>) {
+        let mut i = 0;
+        while i < src.len() {
+            if let Some(painter) = painter {
+                painter.paint(i, buf);
+            }
+            let len = utf8_len(src[i]);
+            let c = src
+                .get(i..i + len)
+                .filter(|_| len > 1)
+                .and_then(|x| std::str::from_utf8(x).ok())
+                .and_then(|x| x.chars().next())
+                .filter(|&c| !is_invisible(c));
+
+            // continuation bytes are padded with spaces (one fewer for wide characters)
+            if let Some(c) = c {
+                let mut encoded = [0u8; 4];
+                buf.extend_from_slice(c.encode_utf8(&mut encoded).as_bytes());
+                buf.resize(buf.len() + len - if is_wide(c) { 2 } else { 1 }, b' ');
+                i += len;
+                continue;
+            }
+            buf.push(self.glyphs[src[i] as usize] as u8);
+            i += 1;
+        }
+    }
+
+    // the mosaic of `src` padded to `width` columns, colored by `painter` if any
+    pub fn format(&self, src: &[u8], width: usize, painter: &mut Option<LinePainter>, buf: &mut Vec<u8>) {
+        if self.mosaic == Mosaic::Utf8 {
+            self.format_utf8(src, painter, buf);
+        } else {
+            let mut encoded = [0u8; 4];
+            for (i, &x) in src.iter().enumerate() {
+                if let Some(painter) = painter {
+                    painter.paint(i, buf);
+                }
+                buf.extend_from_slice(self.glyphs[x as usize].encode_utf8(&mut encoded).as_bytes());
+            }
+        }
+        if let Some(painter) = painter {
+            painter.reset(buf);
+        }
+        buf.resize(buf.len() + width - src.len(), b' ');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MosaicFormatter;
+    use crate::text::Mosaic;
+
+    fn format(mosaic: Mosaic, src: &[u8], width: usize) -> String {
+        let mut buf = Vec::new();
+        MosaicFormatter::new(mosaic).format(src, width, &mut None, &mut buf);
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_format_mosaic() {
+        let src = b"A\x00\x1b\x7f\x80\xa0\xe9\xff";
+
+        assert_eq!(format(Mosaic::Ascii, src, 10), "A.......  ");
+        assert_eq!(format(Mosaic::Latin1, src, 8), "A.....\u{e9}\u{ff}");
+        assert_eq!(format(Mosaic::Cp437, src, 8), "A.\u{2190}\u{2302}\u{c7}\u{e1}\u{398}.");
+        assert_eq!(format(Mosaic::Pictures, src, 8), "A\u{2400}\u{241b}\u{2421}....");
+        assert_eq!(format(Mosaic::Ebcdic, b"\xc8\x85\x93\x93\x96\x4b\x40\x25", 8), "Hello. .");
+    }
+
+    #[test]
+    fn test_format_mosaic_utf8() {
+        // one column per byte; "é", "→", and "日" (wide) followed by paddings
+        assert_eq!(format(Mosaic::Utf8, "aé→日".as_bytes(), 10), "aé →  日  ");
+        assert_eq!(format(Mosaic::Utf8, "😀!".as_bytes(), 5), "😀  !");
+
+        // broken, truncated, overlong, and combining sequences
+        assert_eq!(format(Mosaic::Utf8, b"\x80\xe2\x86x\xc0\xaf", 6), "...x..");
+        assert_eq!(format(Mosaic::Utf8, &"→".as_bytes()[..2], 2), "..");
+        assert_eq!(format(Mosaic::Utf8, "e\u{301}".as_bytes(), 3), "e..");
+    }
+}
+
+// end of mosaic.rs
@@ -0,0 +1,190 @@
+// @file record.rs
+// @author Hajime Suzuki
+// @brief Intel HEX and S-record formatters
+
+use crate::segment::Segment;
+use crate::text::Dialect;
+use std::cell::Cell;
+
+fn push_hex(buf: &mut Vec<u8>, x: u8) {
+    buf.push(b"0123456789ABCDEF"[(x >> 4) as usize]);
+    buf.push(b"0123456789ABCDEF"[(x & 0x0f) as usize]);
+}
+
+// ":LLAAAATT[DD...]CC"; the checksum is the two's complement of the sum
+fn format_ihex_record(kind: u8, addr: u16, data: &[u8], buf: &mut Vec<u8>) {
+    let header = [data.len() as u8, (addr >> 8) as u8, addr as u8, kind];
+
+    buf.push(b':');
+    let mut sum = 0u8;
+    for &x in header.iter().chain(data.iter()) {
+        push_hex(buf, x);
+        sum = sum.wrapping_add(x);
+    }
+    push_hex(buf, sum.wrapping_neg());
+    buf.push(b'\n');
+}
+
+// "STCC[AAAA...][DD...]KK"; the checksum is the one's complement of the sum
+fn format_srec_record(kind: u8, addr: usize, addr_len: usize, data: &[u8], buf: &mut Vec<u8>) {
+    let count = (addr_len + data.len() + 1) as u8;
+    let addr = addr.to_be_bytes();
+    let addr = &addr[addr.len() - addr_len..];
+
+    buf.push(b'S');
+    buf.push(b'0' + kind);
+    let mut sum = 0u8;
+    for &x in std::iter::once(&count).chain(addr.iter()).chain(data.iter()) {
+        push_hex(buf, x);
+        sum = sum.wrapping_add(x);
+    }
+    push_hex(buf, !sum);
+    buf.push(b'\n');
+}
+
+pub struct RecordFormatter {
+    dialect: Dialect,
+
+    // maximum number of data bytes in a record
+    width: usize,
+
+    // upper 16 bits of the last extended linear address record (Intel HEX); usize::MAX if unknown
+    base: Cell<usize>,
+
+    // widest address field used so far (S-record); determines the termination record
+    addr_len: Cell<usize>,
+}
+
+impl RecordFormatter {
+    pub fn new(dialect: Dialect, width: usize) -> Self {
+        debug_assert!(dialect != Dialect::Native);
+
+        // a record can't hold more than 255 bytes including the address field and the checksum for S-records
+        let max_width = if dialect == Dialect::IntelHex { 255 } else { 250 };
+        RecordFormatter {
+            dialect,
+            width: width.clamp(1, max_width),
+            base: Cell::new(0),
+            addr_len: Cell::new(2),
+        }
+    }
+
+    // forgets the last extended address so that the next output is valid on its own
+    pub fn reset(&self) {
+        self.base.set(usize::MAX);
+    }
+
+    fn format_ihex(&self, offset: usize, src: &[u8], buf: &mut Vec<u8>) {
+        let mut offset = offset;
+        let mut src = src;
+        while !src.is_empty() {
+            let upper = offset >> 16;
+            if upper != self.base.get() {
+                format_ihex_record(0x04, 0, &(upper as u16).to_be_bytes(), buf);
+                self.base.set(upper);
+            }
+
+            // records don't cross 64 KiB boundaries
+            let len = std::cmp::min(self.width, src.len());
+            let len = std::cmp::min(len, 0x10000 - (offset & 0xffff));
+            format_ihex_record(0x00, offset as u16, &src[..len], buf);
+
+            offset += len;
+            src = &src[len..];
+        }
+    }
+
+    fn format_srec(&self, offset: usize, src: &[u8], buf: &mut Vec<u8>) {
+        for (i, chunk) in src.chunks(self.width).enumerate() {
+            let addr = offset + i * self.width;
+            let last = addr + chunk.len() - 1;
+
+            // S1, S2, or S3, depending on the width of the address
+            let addr_len = if last < 0x10000 {
+                2
+            } else if last < 0x1000000 {
+                3
+            } else {
+                4
+            };
+            format_srec_record(addr_len as u8 - 1, addr, addr_len, chunk, buf);
+            self.addr_len.set(std::cmp::max(self.addr_len.get(), addr_len));
+        }
+    }
+
+    pub fn format_segments(&self, offset: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
+        for s in segments {
+            let src = &stream[s.as_range()];
+            match self.dialect {
+                Dialect::IntelHex => self.format_ihex(offset + s.pos, src, buf),
+                _ => self.format_srec(offset + s.pos, src, buf),
+            }
+        }
+    }
+
+    // end-of-file record for Intel HEX, or termination record (S9, S8, or S7) for S-records
+    pub fn format_trailer(&self, buf: &mut Vec<u8>) {
+        match self.dialect {
+            Dialect::IntelHex => format_ihex_record(0x01, 0, &[], buf),
+            _ => {
+                let addr_len = self.addr_len.get();
+                format_srec_record(11 - addr_len as u8, 0, addr_len, &[], buf);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RecordFormatter;
+    use crate::segment::Segment;
+    use crate::text::Dialect;
+
+    macro_rules! test {
+        ( $dialect: expr, $width: expr, $offset: expr, $input: expr, $expected: expr ) => {{
+            let formatter = RecordFormatter::new($dialect, $width);
+            let input: &[u8] = $input;
+            let segment = Segment {
+                pos: 0,
+                len: input.len(),
+                tag: 0,
+            };
+
+            let mut buf = Vec::new();
+            formatter.format_segments($offset, input, &[segment], &mut buf);
+            formatter.format_trailer(&mut buf);
+            assert_eq!(std::str::from_utf8(&buf).unwrap(), $expected);
+        }};
+    }
+
+    #[test]
+    fn test_ihex_formatter() {
+        test!(Dialect::IntelHex, 16, 0, b"", ":00000001FF\n");
+        test!(Dialect::IntelHex, 16, 0x30, &[0x02, 0x33, 0x7a], ":0300300002337A1E\n:00000001FF\n");
+
+        // split at the width and at 64 KiB boundaries
+        test!(
+            Dialect::IntelHex,
+            2,
+            0x0800_fffd,
+            &[1, 2, 3, 4, 5],
+            ":020000040800F2\n:02FFFD000102FF\n:01FFFF0003FE\n:020000040801F1\n:020000000405F5\n:00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn test_srec_formatter() {
+        test!(Dialect::Srec, 16, 0, b"", "S9030000FC\n");
+        test!(Dialect::Srec, 16, 0x10, &[1, 2, 3], "S1060010010203E3\nS9030000FC\n");
+        test!(
+            Dialect::Srec,
+            2,
+            0xfffe,
+            &[1, 2, 3],
+            "S105FFFE0102FA\nS20501000003F6\nS804000000FB\n"
+        );
+        test!(Dialect::Srec, 16, 0x0800_0000, &[0xff], "S30608000000FFF2\nS70500000000FA\n");
+    }
+}
+
+// end of record.rs
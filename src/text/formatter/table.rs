@@ -0,0 +1,132 @@
+// @file table.rs
+// @author Hajime Suzuki
+// @brief JSON Lines and CSV formatters
+
+use crate::segment::Segment;
+use std::io::Write;
+
+fn push_hex(buf: &mut Vec<u8>, src: &[u8]) {
+    for &x in src {
+        buf.push(b"0123456789abcdef"[(x >> 4) as usize]);
+        buf.push(b"0123456789abcdef"[(x & 0x0f) as usize]);
+    }
+}
+
+// printable ascii as is, others as '.'; bytes in `escapes` are replaced with the escaped sequences
+fn push_ascii(buf: &mut Vec<u8>, src: &[u8], escapes: &[(u8, &[u8])]) {
+    for &x in src {
+        if let Some((_, escaped)) = escapes.iter().find(|(y, _)| *y == x) {
+            buf.extend_from_slice(escaped);
+        } else if (0x20..0x7f).contains(&x) {
+            buf.push(x);
+        } else {
+            buf.push(b'.');
+        }
+    }
+}
+
+const JSON_ESCAPES: [(u8, &[u8]); 2] = [(b'"', b"\\\""), (b'\\', b"\\\\")];
+const CSV_ESCAPES: [(u8, &[u8]); 1] = [(b'"', b"\"\"")];
+
+fn format_json(offset: usize, stream: &[u8], segments: &[Segment], with_ascii: bool, buf: &mut Vec<u8>) {
+    for s in segments {
+        let src = &stream[s.as_range()];
+
+        write!(buf, "{{\"offset\":{},\"length\":{},\"hex\":\"", offset + s.pos, s.len).unwrap();
+        push_hex(buf, src);
+        if with_ascii {
+            buf.extend_from_slice(b"\",\"ascii\":\"");
+            push_ascii(buf, src, &JSON_ESCAPES);
+        }
+        buf.extend_from_slice(b"\"}\n");
+    }
+}
+
+fn format_csv(offset: usize, stream: &[u8], segments: &[Segment], with_ascii: bool, buf: &mut Vec<u8>) {
+    for s in segments {
+        let src = &stream[s.as_range()];
+
+        write!(buf, "{},{},", offset + s.pos, s.len).unwrap();
+        push_hex(buf, src);
+        if with_ascii {
+            buf.extend_from_slice(b",\"");
+            push_ascii(buf, src, &CSV_ESCAPES);
+            buf.push(b'"');
+        }
+        buf.push(b'\n');
+    }
+}
+
+pub fn format_segments_json(offset: usize, _min_width: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
+    format_json(offset, stream, segments, false, buf);
+}
+
+pub fn format_segments_json_ascii(offset: usize, _min_width: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
+    format_json(offset, stream, segments, true, buf);
+}
+
+pub fn format_segments_csv(offset: usize, _min_width: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
+    format_csv(offset, stream, segments, false, buf);
+}
+
+pub fn format_segments_csv_ascii(offset: usize, _min_width: usize, stream: &[u8], segments: &[Segment], buf: &mut Vec<u8>) {
+    format_csv(offset, stream, segments, true, buf);
+}
+
+// appends `"label":"..."` to the last JSON record
+pub fn format_json_label(label: &[u8], buf: &mut Vec<u8>) {
+    debug_assert!(buf.ends_with(b"}\n"));
+    buf.truncate(buf.len() - 2);
+
+    buf.extend_from_slice(b",\"label\":\"");
+    for &x in label {
+        match x {
+            b'"' | b'\\' => buf.extend_from_slice(&[b'\\', x]),
+            0x00..=0x1f => write!(buf, "\\u{x:04x}").unwrap(),
+            _ => buf.push(x),
+        }
+    }
+    buf.extend_from_slice(b"\"}\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_json() {
+        let stream = b"xx\"a\\\x00b";
+        let segments = [Segment { pos: 2, len: 5, tag: 0 }, Segment { pos: 0, len: 0, tag: 0 }];
+
+        let mut buf = Vec::new();
+        format_segments_json(16, 0, stream, &segments, &mut buf);
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            "{\"offset\":18,\"length\":5,\"hex\":\"22615c0062\"}\n{\"offset\":16,\"length\":0,\"hex\":\"\"}\n"
+        );
+
+        buf.clear();
+        format_segments_json_ascii(16, 0, stream, &segments[..1], &mut buf);
+        format_json_label(b"q\"\n", &mut buf);
+        assert_eq!(
+            std::str::from_utf8(&buf).unwrap(),
+            "{\"offset\":18,\"length\":5,\"hex\":\"22615c0062\",\"ascii\":\"\\\"a\\\\.b\",\"label\":\"q\\\"\\u000a\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_format_csv() {
+        let stream = b"xx\"a,\x00b";
+        let segments = [Segment { pos: 2, len: 5, tag: 0 }];
+
+        let mut buf = Vec::new();
+        format_segments_csv(16, 0, stream, &segments, &mut buf);
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), "18,5,22612c0062\n");
+
+        buf.clear();
+        format_segments_csv_ascii(16, 0, stream, &segments, &mut buf);
+        assert_eq!(std::str::from_utf8(&buf).unwrap(), "18,5,22612c0062,\"\"\"a,.b\"\n");
+    }
+}
+
+// end of table.rs
@@ -6,7 +6,6 @@ pub mod formatter;
 pub mod parser;
 
 pub use self::formatter::TextFormatter;
-pub use self::parser::TextParser;
 
 use anyhow::{anyhow, Result};
 
@@ -16,6 +15,8 @@ pub enum ColumnFormat {
     Binary,
     Decimal,
     Hexadecimal,
+    Octal,
+    Bits, // 8-digit binary; only for the array
     Struct(String),
 }
 
@@ -29,17 +30,130 @@ impl ColumnFormat {
             (b'b', false) => Ok(ColumnFormat::Binary),
             (b'd', false) => Ok(ColumnFormat::Decimal),
             (b'x', false) => Ok(ColumnFormat::Hexadecimal),
+            (b'o', false) => Ok(ColumnFormat::Octal),
+            (b'B', false) => Ok(ColumnFormat::Bits),
             (b'@' | b'=' | b'<' | b'>' | b'!', true) => Ok(ColumnFormat::Struct(s.to_string())),
             _ => Err(anyhow!("unrecognized input/output format specifier: {s:?}")),
         }
     }
 }
 
+// line syntax of records; the columns above apply to the native "offset span | array" form
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dialect {
+    Native,
+    IntelHex,
+    Srec,
+    // one record per line with "offset", "length", "hex", and "ascii" (if true) fields
+    Json(bool),
+    Csv(bool),
+    // intervals with optional names; input only
+    Tsv,
+    Bed,
+    // dumps of other tools; input only
+    Xxd,
+    XxdPlain,
+    Od,
+    Hexdump,
+    // source-code arrays; output only
+    C,
+    Rust,
+    Python,
+}
+
+// layout of the hex array of the native form; single bytes separated by spaces by default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HexLayout {
+    pub group: usize, // bytes per group; 1, 2, 4, or 8
+    pub is_le: bool,  // digits of a group in the little-endian order
+    pub is_upper: bool,
+    pub sep: Option<u8>, // between groups; None for no separators
+}
+
+impl Default for HexLayout {
+    fn default() -> Self {
+        HexLayout {
+            group: 1,
+            is_le: false,
+            is_upper: false,
+            sep: Some(b' '),
+        }
+    }
+}
+
+impl HexLayout {
+    // "plain", "upper", "g4", "g4le", "g4be", or "sep=C"
+    fn apply(&mut self, modifier: &str) -> Result<()> {
+        if modifier == "plain" {
+            self.sep = None;
+            return Ok(());
+        }
+        if modifier == "upper" {
+            self.is_upper = true;
+            return Ok(());
+        }
+        if let Some(sep) = modifier.strip_prefix("sep=") {
+            let b = sep.as_bytes();
+            if b.len() != 1 || !(b[0].is_ascii_graphic() || b[0] == b' ') || b[0].is_ascii_hexdigit() || b[0] == b'|' {
+                return Err(anyhow!("separator must be a non-hex printable character except '|': {modifier:?}"));
+            }
+            self.sep = Some(b[0]);
+            return Ok(());
+        }
+        if let Some(group) = modifier.strip_prefix('g') {
+            let (group, is_le) = match (group.strip_suffix("le"), group.strip_suffix("be")) {
+                (Some(group), _) => (group, true),
+                (_, Some(group)) => (group, false),
+                _ => (group, false),
+            };
+            if let Ok(group @ (1 | 2 | 4 | 8)) = group.parse::<usize>() {
+                self.group = group;
+                self.is_le = is_le;
+                return Ok(());
+            }
+        }
+        Err(anyhow!("unrecognized hex layout modifier: {modifier:?}"))
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == HexLayout::default()
+    }
+}
+
+// character set of the mosaic; bytes without glyphs are shown as '.'
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mosaic {
+    Ascii,
+    Latin1,
+    Cp437,
+    Ebcdic, // code page 037
+    Utf8,
+    Pictures, // ascii with control pictures (U+2400..) for the control bytes
+}
+
+impl Mosaic {
+    pub fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "ascii" => Ok(Mosaic::Ascii),
+            "latin1" => Ok(Mosaic::Latin1),
+            "cp437" => Ok(Mosaic::Cp437),
+            "ebcdic" => Ok(Mosaic::Ebcdic),
+            "utf8" => Ok(Mosaic::Utf8),
+            "pictures" => Ok(Mosaic::Pictures),
+            x => Err(anyhow!(
+                "unknown mosaic {x:?} (ascii, latin1, cp437, ebcdic, utf8, or pictures expected)"
+            )),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct InoutFormat {
     pub offset: ColumnFormat,
     pub span: ColumnFormat,
     pub body: ColumnFormat,
+    pub dialect: Dialect,
+    pub layout: HexLayout,
 
     // the minimum number of columns of the body part when formatting;
     // ignored in parsing
@@ -51,8 +165,26 @@ impl InoutFormat {
         let offset = ColumnFormat::from_str(offset)?;
         let span = ColumnFormat::from_str(span)?;
         let body = ColumnFormat::from_str(body)?;
+        if [&offset, &span]
+            .iter()
+            .any(|x| matches!(x, ColumnFormat::Octal | ColumnFormat::Bits))
+        {
+            return Err(anyhow!("octal (o) and binary digits (B) are only supported for the array"));
+        }
+
+        Ok(InoutFormat {
+            offset,
+            span,
+            body,
+            dialect: Dialect::Native,
+            layout: HexLayout::default(),
+            cols,
+        })
+    }
 
-        Ok(InoutFormat { offset, span, body, cols })
+    fn new_with_dialect(dialect: Dialect, cols: usize) -> Result<Self> {
+        let format = InoutFormat::new("x", "x", "x", cols)?;
+        Ok(InoutFormat { dialect, ..format })
     }
 
     pub fn from_str_with_columns(sig: &str, cols: usize) -> Result<Self> {
@@ -66,9 +198,36 @@ impl InoutFormat {
             "d" | "ddx" => return InoutFormat::new("d", "d", "x", cols),
             "x" | "xxx" => return InoutFormat::new("x", "x", "x", cols),
             "nnx" => return InoutFormat::new("n", "n", "x", cols),
+            "ihex" => return InoutFormat::new_with_dialect(Dialect::IntelHex, cols),
+            "srec" => return InoutFormat::new_with_dialect(Dialect::Srec, cols),
+            "json" => return InoutFormat::new_with_dialect(Dialect::Json(false), cols),
+            "json+ascii" => return InoutFormat::new_with_dialect(Dialect::Json(true), cols),
+            "csv" => return InoutFormat::new_with_dialect(Dialect::Csv(false), cols),
+            "csv+ascii" => return InoutFormat::new_with_dialect(Dialect::Csv(true), cols),
+            "tsv" => return InoutFormat::new_with_dialect(Dialect::Tsv, cols),
+            "bed" => return InoutFormat::new_with_dialect(Dialect::Bed, cols),
+            "xxd" => return InoutFormat::new_with_dialect(Dialect::Xxd, cols),
+            "xxd-p" => return InoutFormat::new_with_dialect(Dialect::XxdPlain, cols),
+            "od" => return InoutFormat::new_with_dialect(Dialect::Od, cols),
+            "hexdump" => return InoutFormat::new_with_dialect(Dialect::Hexdump, cols),
+            "c" => return InoutFormat::new_with_dialect(Dialect::C, cols),
+            "rust" => return InoutFormat::new_with_dialect(Dialect::Rust, cols),
+            "python" => return InoutFormat::new_with_dialect(Dialect::Python, cols),
             _ => {}
         }
 
+        // layout modifiers of the hex array, e.g. "x+g4le+upper"
+        if let Some((base, modifiers)) = sig.split_once('+') {
+            let mut format = InoutFormat::from_str_with_columns(base, cols)?;
+            if format.dialect != Dialect::Native || format.body != ColumnFormat::Hexadecimal {
+                return Err(anyhow!("layout modifiers can only be used with hex arrays: {sig:?}"));
+            }
+            for modifier in modifiers.split('+') {
+                format.layout.apply(modifier)?;
+            }
+            return Ok(format);
+        }
+
         // we need ',' delimiters for the complete forms
         let sigs = sig.split(',').collect::<Vec<_>>();
         if sigs.len() > 3 {
@@ -92,9 +251,25 @@ impl InoutFormat {
         self.offset == ColumnFormat::None && self.span == ColumnFormat::None
     }
 
+    pub fn is_input_only(&self) -> bool {
+        matches!(
+            self.dialect,
+            Dialect::Tsv | Dialect::Bed | Dialect::Xxd | Dialect::XxdPlain | Dialect::Od | Dialect::Hexdump
+        )
+    }
+
+    pub fn is_output_only(&self) -> bool {
+        matches!(self.dialect, Dialect::C | Dialect::Rust | Dialect::Python)
+    }
+
     pub fn is_binary(&self) -> bool {
         self.is_gapless() && self.body == ColumnFormat::Binary
     }
+
+    // true if the array is in octal, decimal, or binary digits instead of hex
+    pub fn is_digit_array(&self) -> bool {
+        matches!(self.body, ColumnFormat::Octal | ColumnFormat::Decimal | ColumnFormat::Bits)
+    }
 }
 
 // end of mod.rs
@@ -0,0 +1,366 @@
+// @file csv.rs
+// @author Hajime Suzuki
+// @brief parser for CSV, TSV, and BED records of intervals
+
+use super::lines::{parse_hex_pairs, trim, LineReader};
+use crate::byte::{ByteStream, PatchSource};
+use anyhow::{anyhow, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    Csv,
+    Tsv,
+    Bed,
+}
+
+// splits a line into fields, removing the quotes (RFC 4180); returns None on unbalanced quotes
+fn split_fields(line: &[u8], delim: u8, fields: &mut Vec<Vec<u8>>) -> Option<()> {
+    fields.clear();
+    fields.push(Vec::new());
+
+    let mut is_quoted = false;
+    let mut i = 0;
+    while i < line.len() {
+        let x = line[i];
+        let field = fields.last_mut().unwrap();
+        match (is_quoted, x) {
+            (true, b'"') if line.get(i + 1) == Some(&b'"') => {
+                field.push(b'"');
+                i += 1;
+            }
+            (true, b'"') => is_quoted = false,
+            (false, b'"') if field.is_empty() => is_quoted = true,
+            (false, _) if x == delim => fields.push(Vec::new()),
+            _ => field.push(x),
+        }
+        i += 1;
+    }
+
+    if is_quoted {
+        return None;
+    }
+    Some(())
+}
+
+// BED columns are separated by tabs, but files separated by spaces are also common
+fn split_bed_fields(line: &[u8], fields: &mut Vec<Vec<u8>>) {
+    fields.clear();
+    if line.contains(&b'\t') {
+        fields.extend(line.split(|&x| x == b'\t').map(|x| x.to_vec()));
+        return;
+    }
+    fields.extend(line.split(|&x| x == b' ').filter(|x| !x.is_empty()).map(|x| x.to_vec()));
+}
+
+// decimal, or hexadecimal with the "0x" prefix
+fn parse_num(field: &[u8]) -> Option<usize> {
+    let field = std::str::from_utf8(trim(field)).ok()?;
+    let (digits, radix) = match field.strip_prefix("0x").or_else(|| field.strip_prefix("0X")) {
+        Some(digits) => (digits, 16),
+        None => (field, 10),
+    };
+    if digits.is_empty() || !digits.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    usize::from_str_radix(digits, radix).ok()
+}
+
+// column indices of the fields
+struct Columns {
+    offset: usize,
+    length: Option<usize>,
+    end: Option<usize>,
+    hex: Option<usize>,
+    name: Option<usize>,
+}
+
+impl Columns {
+    fn new(layout: Layout) -> Self {
+        match layout {
+            // "offset,length[,hex]" unless the header says otherwise
+            Layout::Csv | Layout::Tsv => Columns {
+                offset: 0,
+                length: Some(1),
+                end: None,
+                hex: Some(2),
+                name: None,
+            },
+            // "chrom start end [name ...]"
+            Layout::Bed => Columns {
+                offset: 1,
+                length: None,
+                end: Some(2),
+                hex: None,
+                name: Some(3),
+            },
+        }
+    }
+
+    fn from_header(fields: &[Vec<u8>]) -> Option<Self> {
+        let find = |names: &[&str]| {
+            fields
+                .iter()
+                .position(|x| names.iter().any(|name| trim(x).eq_ignore_ascii_case(name.as_bytes())))
+        };
+
+        let columns = Columns {
+            offset: find(&["offset", "start"])?,
+            length: find(&["length", "len", "size"]),
+            end: find(&["end"]),
+            hex: find(&["hex"]),
+            name: find(&["name", "label"]),
+        };
+        if columns.length.is_none() && columns.end.is_none() {
+            return None;
+        }
+        Some(columns)
+    }
+}
+
+pub struct CsvParser {
+    lines: LineReader,
+    layout: Layout,
+    columns: Columns,
+    is_header_checked: bool,
+    fields: Vec<Vec<u8>>,
+    bytes: Vec<u8>,
+
+    // index of the field of the name of the last record
+    label: Option<usize>,
+}
+
+impl CsvParser {
+    pub fn new(src: Box<dyn ByteStream>, layout: Layout) -> Self {
+        CsvParser {
+            lines: LineReader::new(src),
+            layout,
+            columns: Columns::new(layout),
+            is_header_checked: false,
+            fields: Vec::new(),
+            bytes: Vec::new(),
+            label: None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.layout {
+            Layout::Csv => "CSV",
+            Layout::Tsv => "TSV",
+            Layout::Bed => "BED",
+        }
+    }
+
+    fn is_skipped(&self) -> bool {
+        let line = self.lines.line();
+        if line.is_empty() || line[0] == b'#' {
+            return true;
+        }
+        self.layout == Layout::Bed && (line.starts_with(b"track") || line.starts_with(b"browser"))
+    }
+
+    fn split(&mut self) -> Option<()> {
+        match self.layout {
+            Layout::Csv => split_fields(self.lines.line(), b',', &mut self.fields),
+            Layout::Tsv => split_fields(self.lines.line(), b'\t', &mut self.fields),
+            Layout::Bed => {
+                split_bed_fields(self.lines.line(), &mut self.fields);
+                Some(())
+            }
+        }
+    }
+
+    fn parse_error(&self) -> anyhow::Error {
+        anyhow!("failed to parse {} record at {}", self.name(), self.lines.format_cache(false))
+    }
+
+    fn field(&self, index: Option<usize>) -> Option<&[u8]> {
+        self.fields.get(index?).map(|x| x.as_slice())
+    }
+
+    fn parse_span(&self) -> Result<(usize, usize)> {
+        let columns = &self.columns;
+        let offset = self
+            .field(Some(columns.offset))
+            .and_then(parse_num)
+            .ok_or_else(|| self.parse_error())?;
+
+        if columns.length.is_some() {
+            let length = self.field(columns.length).and_then(parse_num).ok_or_else(|| self.parse_error())?;
+            return Ok((offset, length));
+        }
+
+        let end = self.field(columns.end).and_then(parse_num).ok_or_else(|| self.parse_error())?;
+        let length = end.checked_sub(offset).ok_or_else(|| {
+            anyhow!(
+                "end is smaller than start in {} record at {}",
+                self.name(),
+                self.lines.format_cache(false)
+            )
+        })?;
+        Ok((offset, length))
+    }
+
+    pub fn read_record(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        while self.lines.read_line()? {
+            if self.is_skipped() {
+                continue;
+            }
+            self.split().ok_or_else(|| self.parse_error())?;
+
+            // the first line of CSV and TSV is a header if it doesn't start with a number
+            let is_first = !std::mem::replace(&mut self.is_header_checked, true);
+            if is_first && self.layout != Layout::Bed && parse_num(&self.fields[0]).is_none() {
+                self.columns = Columns::from_header(&self.fields).ok_or_else(|| {
+                    anyhow!(
+                        "{} header must have \"offset\" (or \"start\") and \"length\" (or \"end\") columns at {}",
+                        self.name(),
+                        self.lines.format_cache(false)
+                    )
+                })?;
+                continue;
+            }
+
+            let (offset, length) = self.parse_span()?;
+            if let Some(hex) = self.columns.hex.and_then(|index| self.fields.get(index)) {
+                if !parse_hex_pairs(hex, &mut self.bytes) {
+                    return Err(self.parse_error());
+                }
+                buf.extend_from_slice(&self.bytes);
+            }
+
+            // BED records without the name column are named after the first (chrom) column
+            self.label = match self.field(self.columns.name) {
+                Some(name) if !name.is_empty() => self.columns.name,
+                _ if self.layout == Layout::Bed => Some(0),
+                _ => None,
+            };
+            return Ok(Some((offset, length)));
+        }
+        Ok(None)
+    }
+}
+
+impl PatchSource for CsvParser {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.read_record(buf)
+    }
+
+    fn label(&self) -> Option<&[u8]> {
+        self.field(self.label)
+    }
+
+    fn format_cache(&self) -> String {
+        self.lines.format_cache(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvParser, Layout};
+    use crate::byte::tester::*;
+    use crate::byte::PatchSource;
+
+    type Record = (usize, usize, Vec<u8>, Option<String>);
+
+    fn parse_all(input: &[u8], layout: Layout) -> anyhow::Result<Vec<Record>> {
+        let mut parser = CsvParser::new(Box::new(MockSource::new(input)), layout);
+        let mut records = Vec::new();
+
+        let mut buf = Vec::new();
+        while let Some((offset, span)) = parser.read_record(&mut buf)? {
+            let label = parser.label().map(|x| String::from_utf8_lossy(x).to_string());
+            records.push((offset, span, buf.clone(), label));
+            buf.clear();
+        }
+        Ok(records)
+    }
+
+    macro_rules! test {
+        ( $layout: expr, $input: expr, $expected: expr ) => {{
+            let expected: Vec<Record> = $expected
+                .iter()
+                .map(|(x, y, z, w): &(usize, usize, &[u8], Option<&str>)| (*x, *y, z.to_vec(), w.map(|w| w.to_string())))
+                .collect();
+            assert_eq!(parse_all($input, $layout).unwrap(), expected, "{:?}", std::str::from_utf8($input));
+        }};
+    }
+
+    #[test]
+    fn test_csv_parser() {
+        test!(Layout::Csv, b"", []);
+        test!(Layout::Csv, b"16,3,616263\n", [(16, 3, b"abc".as_slice(), None)]);
+
+        // header, no hex, empty hex, quoted ascii column, and CRLF
+        #[rustfmt::skip]
+        test!(
+            Layout::Csv,
+            b"offset,length,hex,ascii\n\
+              1,2\n\
+              \n\
+              4,0,,\"\"\n\
+              5, 3 ,\"616263\",\"a,\"\"b\"\r\n",
+            [(1, 2, b"".as_slice(), None), (4, 0, b"".as_slice(), None), (5, 3, b"abc".as_slice(), None)]
+        );
+
+        // columns named in the header, hexadecimal numbers, and comments
+        #[rustfmt::skip]
+        test!(
+            Layout::Csv,
+            b"# regions\n\
+              name,End,start\n\
+              hdr,0x10,0\n\
+              \"a,b\",0x20,0x18\n\
+              ,32,32\n",
+            [(0, 16, b"".as_slice(), Some("hdr")), (24, 8, b"".as_slice(), Some("a,b")), (32, 0, b"".as_slice(), None)]
+        );
+    }
+
+    #[test]
+    fn test_tsv_parser() {
+        test!(Layout::Tsv, b"16\t3\t616263\n", [(16, 3, b"abc".as_slice(), None)]);
+
+        #[rustfmt::skip]
+        test!(
+            Layout::Tsv,
+            b"start\tlength\tlabel\n\
+              0x10\t3\tfox\n\
+              40\t3\tlazy dog\n",
+            [(16, 3, b"".as_slice(), Some("fox")), (40, 3, b"".as_slice(), Some("lazy dog"))]
+        );
+    }
+
+    #[test]
+    fn test_bed_parser() {
+        // names from the fourth column, or the first one if missing
+        #[rustfmt::skip]
+        test!(
+            Layout::Bed,
+            b"track name=test\n\
+              browser position chr1:1-100\n\
+              # comment\n\
+              chr1\t16\t19\tfox\t0\t+\n\
+              chr1  40 43\n\
+              chr1\t44\t48\tlazy dog\n",
+            [
+                (16, 3, b"".as_slice(), Some("fox")),
+                (40, 3, b"".as_slice(), Some("chr1")),
+                (44, 4, b"".as_slice(), Some("lazy dog")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_csv_parser_err() {
+        assert!(parse_all(b"16\n", Layout::Csv).is_err());
+        assert!(parse_all(b"16,-1\n", Layout::Csv).is_err());
+        assert!(parse_all(b"16,1\nx10,1\n", Layout::Csv).is_err());
+        assert!(parse_all(b"16,1,6\n", Layout::Csv).is_err());
+        assert!(parse_all(b"16,1,61,\"a\n", Layout::Csv).is_err());
+        assert!(parse_all(b"start,name\n16,a\n", Layout::Csv).is_err());
+        assert!(parse_all(b"16,3\n", Layout::Tsv).is_err());
+        assert!(parse_all(b"chr1\t16\n", Layout::Bed).is_err());
+        assert!(parse_all(b"chr1\t16\t15\n", Layout::Bed).is_err());
+    }
+}
+
+// end of csv.rs
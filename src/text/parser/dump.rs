@@ -0,0 +1,329 @@
+// @file dump.rs
+// @author Hajime Suzuki
+// @brief parser for the outputs of xxd, xxd -p, od -A x -t x1, and hexdump -C
+
+use super::lines::LineReader;
+use crate::byte::{ByteStream, PatchSource};
+use crate::params::BLOCK_SIZE;
+use crate::text::Dialect;
+use anyhow::{anyhow, Result};
+
+enum Line {
+    Empty,
+    Data(usize), // offset of the bytes parsed into the buffer
+    Repeat,      // "*"; the previous line is repeated until the offset of the next line
+    End(usize),  // offset without bytes; the end of the dump
+}
+
+fn hex_digit(x: u8) -> Option<u8> {
+    match x {
+        b'0'..=b'9' => Some(x - b'0'),
+        b'a'..=b'f' => Some(x - b'a' + 10),
+        b'A'..=b'F' => Some(x - b'A' + 10),
+        _ => None,
+    }
+}
+
+// "00000010" followed by a delimiter; returns the offset and the rest of the line
+fn parse_offset(line: &[u8], delim: u8) -> Option<(usize, &[u8])> {
+    let len = line.iter().position(|&x| x == delim).unwrap_or(line.len());
+    if len == 0 || len > 16 {
+        return None;
+    }
+
+    let offset = line[..len]
+        .iter()
+        .try_fold(0, |acc, &x| Some((acc << 4) | hex_digit(x)? as usize))?;
+    Some((offset, &line[len..]))
+}
+
+// hex pairs optionally separated by spaces; stops at the `stop` character, or at two consecutive
+// spaces after the first byte if `stops_at_gap` is set (the gap before the ascii column of xxd)
+fn parse_hex_area(line: &[u8], stop: Option<u8>, stops_at_gap: bool, buf: &mut Vec<u8>) -> Option<()> {
+    let mut i = 0;
+    let mut spaces = 0;
+    let mut parsed = 0;
+
+    while i < line.len() {
+        let x = line[i];
+        if x == b' ' || x == b'\t' {
+            spaces += 1;
+            if stops_at_gap && spaces >= 2 && parsed > 0 {
+                break;
+            }
+            i += 1;
+            continue;
+        }
+        if Some(x) == stop {
+            break;
+        }
+
+        let hi = hex_digit(x)?;
+        let lo = hex_digit(*line.get(i + 1)?)?;
+        buf.push((hi << 4) | lo);
+
+        spaces = 0;
+        parsed += 1;
+        i += 2;
+    }
+    Some(())
+}
+
+pub struct DumpParser {
+    lines: LineReader,
+    dialect: Dialect,
+
+    // the last line, kept for "*"
+    prev: Vec<u8>,
+    prev_end: usize,
+
+    // bytes of a line not yet returned, and the end of the pending repeat
+    bytes: Vec<u8>,
+    staged: Option<usize>,
+    is_repeating: bool,
+    repeat_end: usize,
+}
+
+impl DumpParser {
+    pub fn new(src: Box<dyn ByteStream>, dialect: Dialect) -> Self {
+        DumpParser {
+            lines: LineReader::new(src),
+            dialect,
+            prev: Vec::new(),
+            prev_end: 0,
+            bytes: Vec::new(),
+            staged: None,
+            is_repeating: false,
+            repeat_end: 0,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.dialect {
+            Dialect::Xxd => "xxd",
+            Dialect::XxdPlain => "xxd -p",
+            Dialect::Od => "od",
+            _ => "hexdump",
+        }
+    }
+
+    fn parse_line(&mut self) -> Option<Line> {
+        let line = self.lines.line();
+        self.bytes.clear();
+
+        if line.is_empty() {
+            return Some(Line::Empty);
+        }
+        if line == b"*" && self.dialect != Dialect::XxdPlain {
+            return Some(Line::Repeat);
+        }
+
+        // "00000000: 4865 6c6c 6f0a  Hello.", "00000000  48 65 6c 6c 6f 0a  |Hello.|", or "000000 48 65 6c 6c 6f 0a"
+        let (offset, rem, stop, stops_at_gap) = match self.dialect {
+            Dialect::Xxd => {
+                let (offset, rem) = parse_offset(line, b':')?;
+                (offset, rem.get(1..)?, None, true)
+            }
+            Dialect::Hexdump => {
+                let (offset, rem) = parse_offset(line, b' ')?;
+                (offset, rem, Some(b'|'), false)
+            }
+            Dialect::Od => {
+                let (offset, rem) = parse_offset(line, b' ')?;
+                (offset, rem, Some(b'>'), false)
+            }
+            _ => (self.prev_end, line, None, false),
+        };
+
+        parse_hex_area(rem, stop, stops_at_gap, &mut self.bytes)?;
+        if self.bytes.is_empty() && self.dialect != Dialect::XxdPlain {
+            return Some(Line::End(offset));
+        }
+        Some(Line::Data(offset))
+    }
+
+    pub fn read_record(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        loop {
+            // the previous line repeated; up to BLOCK_SIZE bytes at a time
+            if self.prev_end < self.repeat_end {
+                if self.prev.is_empty() {
+                    self.prev_end = self.repeat_end;
+                    continue;
+                }
+
+                // a multiple of the line length so that the next chunk starts at the head of the line
+                let offset = self.prev_end;
+                let chunk = std::cmp::max(BLOCK_SIZE / self.prev.len(), 1) * self.prev.len();
+                let len = std::cmp::min(self.repeat_end - offset, chunk);
+
+                buf.extend(self.prev.iter().cycle().take(len));
+                self.prev_end += len;
+                return Ok(Some((offset, len)));
+            }
+
+            if let Some(offset) = self.staged.take() {
+                std::mem::swap(&mut self.prev, &mut self.bytes);
+                self.prev_end = offset + self.prev.len();
+
+                buf.extend_from_slice(&self.prev);
+                return Ok(Some((offset, self.prev.len())));
+            }
+
+            if !self.lines.read_line()? {
+                return Ok(None);
+            }
+
+            let line = self
+                .parse_line()
+                .ok_or_else(|| anyhow!("failed to parse {} dump at {}", self.name(), self.lines.format_cache(false)))?;
+
+            match line {
+                Line::Empty => {}
+                Line::Repeat => self.is_repeating = true,
+                Line::Data(offset) | Line::End(offset) => {
+                    if std::mem::replace(&mut self.is_repeating, false) {
+                        self.repeat_end = offset;
+                    }
+                    if matches!(line, Line::Data(_)) {
+                        self.staged = Some(offset);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl PatchSource for DumpParser {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.read_record(buf)
+    }
+
+    fn format_cache(&self) -> String {
+        self.lines.format_cache(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DumpParser;
+    use crate::byte::tester::*;
+    use crate::text::Dialect;
+
+    fn parse_all(input: &[u8], dialect: Dialect) -> anyhow::Result<Vec<u8>> {
+        let mut parser = DumpParser::new(Box::new(MockSource::new(input)), dialect);
+
+        // concatenate the records, filling gaps with '.'
+        let mut stream = Vec::new();
+        let mut buf = Vec::new();
+        while let Some((offset, span)) = parser.read_record(&mut buf)? {
+            assert_eq!(span, buf.len());
+            assert!(offset >= stream.len());
+            stream.resize(offset, b'.');
+            stream.extend_from_slice(&buf);
+            buf.clear();
+        }
+        Ok(stream)
+    }
+
+    macro_rules! test {
+        ( $dialect: expr, $input: expr, $expected: expr ) => {{
+            let stream = parse_all($input, $dialect).unwrap();
+            assert_eq!(stream, $expected.to_vec(), "{:?}", std::str::from_utf8($input));
+        }};
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_dump_parser_xxd() {
+        test!(Dialect::Xxd, b"", b"");
+        test!(
+            Dialect::Xxd,
+            b"00000000: 5468 6520 7175 6963 6b20 6272 6f77 6e20  The quick brown \n\
+              00000010: 666f 78                                  fox\n",
+            b"The quick brown fox"
+        );
+
+        // ascii column looking like hex, -g 1, and -s
+        test!(Dialect::Xxd, b"00000002: 61 62 63  abc\n", b"..abc");
+        test!(Dialect::Xxd, b"00000000: 6162636465666768  abcdefgh\n", b"abcdefgh");
+
+        // -a squeezes nul lines into "*"
+        test!(
+            Dialect::Xxd,
+            b"00000000: 0000 0000  ....\n\
+              *\n\
+              0000000c: 4142       AB\n",
+            b"\0\0\0\0\0\0\0\0\0\0\0\0AB"
+        );
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_dump_parser_xxd_plain() {
+        test!(Dialect::XxdPlain, b"", b"");
+        test!(Dialect::XxdPlain, b"54686520717569636b\n2062726f776e\n\n20666f78\n", b"The quick brown fox");
+        test!(Dialect::XxdPlain, b"5468 6520\r\n", b"The ");
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_dump_parser_hexdump() {
+        test!(Dialect::Hexdump, b"", b"");
+        test!(
+            Dialect::Hexdump,
+            b"00000000  54 68 65 20 71 75 69 63  6b 20 62 72 6f 77 6e 20  |The quick brown |\n\
+              00000010  66 6f 78                                          |fox|\n\
+              00000013\n",
+            b"The quick brown fox"
+        );
+        test!(
+            Dialect::Hexdump,
+            b"00000000  61 62 63 64 61 62 63 64  61 62 63 64 61 62 63 64  |abcdabcdabcdabcd|\n\
+              *\n\
+              00000030  65                                                |e|\n\
+              00000031\n",
+            b"abcdabcdabcdabcdabcdabcdabcdabcdabcdabcdabcdabcde"
+        );
+
+        // repeat until the end
+        test!(
+            Dialect::Hexdump,
+            b"00000000  61 62                                             |ab|\n\
+              *\n\
+              00000006\n",
+            b"ababab"
+        );
+    }
+
+    #[test]
+    #[rustfmt::skip]
+    fn test_dump_parser_od() {
+        test!(Dialect::Od, b"", b"");
+        test!(
+            Dialect::Od,
+            b"000000 54 68 65 20 71 75 69 63 6b 20 62 72 6f 77 6e 20\n\
+              000010 66 6f 78\n\
+              000013\n",
+            b"The quick brown fox"
+        );
+        test!(
+            Dialect::Od,
+            b"000000 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  >................<\n\
+              *\n\
+              000020 41  >A<\n\
+              000021\n",
+            [[0u8; 32].as_slice(), b"A"].concat()
+        );
+    }
+
+    #[test]
+    fn test_dump_parser_err() {
+        assert!(parse_all(b"00000000 4865\n", Dialect::Xxd).is_err());
+        assert!(parse_all(b"00000000: 486\n", Dialect::Xxd).is_err());
+        assert!(parse_all(b"0000000g  48 65\n", Dialect::Hexdump).is_err());
+        assert!(parse_all(b"000000 4g\n", Dialect::Od).is_err());
+        assert!(parse_all(b"48x5\n", Dialect::XxdPlain).is_err());
+    }
+}
+
+// end of dump.rs
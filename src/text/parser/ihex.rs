@@ -0,0 +1,152 @@
+// @file ihex.rs
+// @author Hajime Suzuki
+// @brief Intel HEX parser
+
+use super::lines::{parse_hex_pairs, LineReader};
+use crate::byte::{ByteStream, PatchSource};
+use anyhow::{anyhow, Result};
+
+pub struct IntelHexParser {
+    lines: LineReader,
+
+    // base address set by the extended segment / linear address records
+    base: usize,
+    bytes: Vec<u8>,
+    is_eof: bool,
+}
+
+impl IntelHexParser {
+    pub fn new(src: Box<dyn ByteStream>) -> Self {
+        IntelHexParser {
+            lines: LineReader::new(src),
+            base: 0,
+            bytes: Vec::new(),
+            is_eof: false,
+        }
+    }
+
+    fn parse_line(&mut self) -> Result<()> {
+        let line = self.lines.line();
+        if line[0] != b':' || !parse_hex_pairs(&line[1..], &mut self.bytes) {
+            return Err(anyhow!("failed to parse Intel HEX record at {}", self.lines.format_cache(false)));
+        }
+
+        let bytes = &self.bytes;
+        if bytes.len() < 5 || bytes[0] as usize + 5 != bytes.len() {
+            return Err(anyhow!("broken length in Intel HEX record at {}", self.lines.format_cache(false)));
+        }
+        if bytes.iter().fold(0u8, |acc, x| acc.wrapping_add(*x)) != 0 {
+            return Err(anyhow!(
+                "checksum mismatch in Intel HEX record at {}",
+                self.lines.format_cache(false)
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn read_record(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        while !self.is_eof && self.lines.read_line()? {
+            if self.lines.line().is_empty() {
+                continue;
+            }
+            self.parse_line()?;
+
+            // ":LLAAAATT[DD...]CC"
+            let bytes = &self.bytes;
+            let addr = u16::from_be_bytes([bytes[1], bytes[2]]) as usize;
+            let data = &bytes[4..bytes.len() - 1];
+
+            match (bytes[3], data.len()) {
+                (0x00, len) => {
+                    buf.extend_from_slice(data);
+                    return Ok(Some((self.base + addr, len)));
+                }
+                (0x01, 0) => self.is_eof = true,
+                (0x02, 2) => self.base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 4,
+                (0x04, 2) => self.base = (u16::from_be_bytes([data[0], data[1]]) as usize) << 16,
+                (0x03 | 0x05, 4) => {} // start address; ignored
+                _ => return Err(anyhow!("unsupported Intel HEX record type at {}", self.lines.format_cache(false))),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl PatchSource for IntelHexParser {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.read_record(buf)
+    }
+
+    fn format_cache(&self) -> String {
+        self.lines.format_cache(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IntelHexParser;
+    use crate::byte::tester::*;
+
+    fn parse_all(input: &[u8]) -> anyhow::Result<Vec<(usize, Vec<u8>)>> {
+        let mut parser = IntelHexParser::new(Box::new(MockSource::new(input)));
+        let mut records = Vec::new();
+
+        let mut buf = Vec::new();
+        while let Some((offset, span)) = parser.read_record(&mut buf)? {
+            assert_eq!(span, buf.len());
+            records.push((offset, buf.clone()));
+            buf.clear();
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_ihex_parser() {
+        macro_rules! test {
+            ( $input: expr, $expected: expr ) => {{
+                let expected: Vec<(usize, Vec<u8>)> = $expected.iter().map(|(x, y): &(usize, &[u8])| (*x, y.to_vec())).collect();
+                assert_eq!(parse_all($input).unwrap(), expected, "{:?}", std::str::from_utf8($input));
+            }};
+        }
+
+        test!(b"", []);
+        test!(b":00000001FF\n", []);
+        test!(b":0300300002337A1E\n:00000001FF\n", [(0x30, [0x02u8, 0x33, 0x7a].as_slice())]);
+
+        // CRLF, blank lines, and lowercase digits
+        test!(
+            b"\r\n:0300300002337a1e\r\n\r\n:00000001ff\r\n",
+            [(0x30, [0x02u8, 0x33, 0x7a].as_slice())]
+        );
+
+        // extended linear and segment addresses; start addresses are ignored
+        #[rustfmt::skip]
+        test!(
+            b":020000040800F2\n\
+              :0400100001020304E2\n\
+              :020000021000EC\n\
+              :01000200AA53\n\
+              :0400000508000000EF\n\
+              :00000001FF\n",
+            [
+                (0x0800_0010, [1u8, 2, 3, 4].as_slice()),
+                (0x0001_0002, [0xaau8].as_slice()),
+            ]
+        );
+
+        // records after the EOF record are ignored
+        test!(b":00000001FF\n:01000000AA55\n", []);
+    }
+
+    #[test]
+    fn test_ihex_parser_err() {
+        assert!(parse_all(b"0300300002337A1E\n").is_err());
+        assert!(parse_all(b":0300300002337A1\n").is_err());
+        assert!(parse_all(b":0300300002337A1F\n").is_err());
+        assert!(parse_all(b":0400300002337A1D\n").is_err());
+        assert!(parse_all(b":03003000023G7A1E\n").is_err());
+        assert!(parse_all(b":0000000AF6\n").is_err());
+    }
+}
+
+// end of ihex.rs
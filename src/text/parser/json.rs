@@ -0,0 +1,292 @@
+// @file json.rs
+// @author Hajime Suzuki
+// @brief JSON Lines parser for records with "offset", "length", and "hex" fields
+
+use super::lines::{parse_hex_pairs, LineReader};
+use crate::byte::{ByteStream, PatchSource};
+use anyhow::{anyhow, Result};
+
+// minimal JSON reader; just enough to pick up the fields of a flat object
+struct Cursor<'a> {
+    src: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(src: &'a [u8]) -> Self {
+        Cursor { src, pos: 0 }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        while self.pos < self.src.len() && self.src[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        self.src.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, x: u8) -> Option<()> {
+        if self.peek()? != x {
+            return None;
+        }
+        self.pos += 1;
+        Some(())
+    }
+
+    fn string(&mut self, buf: &mut Vec<u8>) -> Option<()> {
+        self.expect(b'"')?;
+        loop {
+            let x = *self.src.get(self.pos)?;
+            self.pos += 1;
+            match x {
+                b'"' => return Some(()),
+                b'\\' => {
+                    let x = *self.src.get(self.pos)?;
+                    self.pos += 1;
+                    match x {
+                        b'"' | b'\\' | b'/' => buf.push(x),
+                        b'b' => buf.push(0x08),
+                        b'f' => buf.push(0x0c),
+                        b'n' => buf.push(b'\n'),
+                        b'r' => buf.push(b'\r'),
+                        b't' => buf.push(b'\t'),
+                        b'u' => {
+                            let digits = std::str::from_utf8(self.src.get(self.pos..self.pos + 4)?).ok()?;
+                            let c = char::from_u32(u32::from_str_radix(digits, 16).ok()?).unwrap_or('\u{fffd}');
+                            buf.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                            self.pos += 4;
+                        }
+                        _ => return None,
+                    }
+                }
+                _ => buf.push(x),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<usize> {
+        self.peek()?;
+        let len = self.src[self.pos..].iter().take_while(|x| x.is_ascii_digit()).count();
+        let val = std::str::from_utf8(&self.src[self.pos..self.pos + len]).ok()?.parse().ok()?;
+        self.pos += len;
+        Some(val)
+    }
+
+    fn skip_value(&mut self) -> Option<()> {
+        match self.peek()? {
+            b'"' => self.string(&mut Vec::new()),
+            b'{' | b'[' => {
+                let close = if self.peek()? == b'{' { b'}' } else { b']' };
+                self.pos += 1;
+                if self.peek()? == close {
+                    self.pos += 1;
+                    return Some(());
+                }
+                loop {
+                    if close == b'}' {
+                        self.string(&mut Vec::new())?;
+                        self.expect(b':')?;
+                    }
+                    self.skip_value()?;
+                    if self.peek()? == close {
+                        self.pos += 1;
+                        return Some(());
+                    }
+                    self.expect(b',')?;
+                }
+            }
+            _ => {
+                // numbers, true, false, and null
+                let len = self.src[self.pos..]
+                    .iter()
+                    .take_while(|x| x.is_ascii_alphanumeric() || b"+-.".contains(x))
+                    .count();
+                if len == 0 {
+                    return None;
+                }
+                self.pos += len;
+                Some(())
+            }
+        }
+    }
+}
+
+pub struct JsonParser {
+    lines: LineReader,
+    key: Vec<u8>,
+    hex: Vec<u8>,
+    bytes: Vec<u8>,
+
+    // "label" of the last record, if any
+    label: Vec<u8>,
+    has_label: bool,
+}
+
+impl JsonParser {
+    pub fn new(src: Box<dyn ByteStream>) -> Self {
+        JsonParser {
+            lines: LineReader::new(src),
+            key: Vec::new(),
+            hex: Vec::new(),
+            bytes: Vec::new(),
+            label: Vec::new(),
+            has_label: false,
+        }
+    }
+
+    // returns (offset, length, has_hex)
+    fn parse_line(&mut self) -> Option<(Option<usize>, Option<usize>, bool)> {
+        let mut cursor = Cursor::new(self.lines.line());
+        let (mut offset, mut length, mut has_hex) = (None, None, false);
+        self.has_label = false;
+
+        cursor.expect(b'{')?;
+        if cursor.peek()? != b'}' {
+            loop {
+                self.key.clear();
+                cursor.string(&mut self.key)?;
+                cursor.expect(b':')?;
+
+                match self.key.as_slice() {
+                    b"offset" => offset = Some(cursor.number()?),
+                    b"length" => length = Some(cursor.number()?),
+                    b"hex" => {
+                        self.hex.clear();
+                        cursor.string(&mut self.hex)?;
+                        has_hex = true;
+                    }
+                    b"label" => {
+                        self.label.clear();
+                        cursor.string(&mut self.label)?;
+                        self.has_label = true;
+                    }
+                    _ => cursor.skip_value()?,
+                }
+
+                if cursor.peek()? == b'}' {
+                    break;
+                }
+                cursor.expect(b',')?;
+            }
+        }
+        cursor.expect(b'}')?;
+        if cursor.peek().is_some() {
+            return None;
+        }
+        Some((offset, length, has_hex))
+    }
+
+    pub fn read_record(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        while self.lines.read_line()? {
+            if self.lines.line().is_empty() {
+                continue;
+            }
+
+            let (offset, length, has_hex) = self
+                .parse_line()
+                .ok_or_else(|| anyhow!("failed to parse JSON record at {}", self.lines.format_cache(false)))?;
+            let (offset, length) = match (offset, length) {
+                (Some(offset), Some(length)) => (offset, length),
+                _ => {
+                    return Err(anyhow!(
+                        "\"offset\" and \"length\" are required in JSON record at {}",
+                        self.lines.format_cache(false)
+                    ))
+                }
+            };
+
+            if has_hex {
+                if !parse_hex_pairs(&self.hex, &mut self.bytes) {
+                    return Err(anyhow!(
+                        "failed to parse \"hex\" in JSON record at {}",
+                        self.lines.format_cache(false)
+                    ));
+                }
+                buf.extend_from_slice(&self.bytes);
+            }
+            return Ok(Some((offset, length)));
+        }
+        Ok(None)
+    }
+}
+
+impl PatchSource for JsonParser {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.read_record(buf)
+    }
+
+    fn label(&self) -> Option<&[u8]> {
+        self.has_label.then_some(self.label.as_slice())
+    }
+
+    fn format_cache(&self) -> String {
+        self.lines.format_cache(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::JsonParser;
+    use crate::byte::tester::*;
+    use crate::byte::PatchSource;
+
+    fn parse_all(input: &[u8]) -> anyhow::Result<Vec<(usize, usize, Vec<u8>)>> {
+        let mut parser = JsonParser::new(Box::new(MockSource::new(input)));
+        let mut records = Vec::new();
+
+        let mut buf = Vec::new();
+        while let Some((offset, span)) = parser.read_record(&mut buf)? {
+            records.push((offset, span, buf.clone()));
+            buf.clear();
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_json_parser() {
+        macro_rules! test {
+            ( $input: expr, $expected: expr ) => {{
+                let expected: Vec<(usize, usize, Vec<u8>)> = $expected
+                    .iter()
+                    .map(|(x, y, z): &(usize, usize, &[u8])| (*x, *y, z.to_vec()))
+                    .collect();
+                assert_eq!(parse_all($input).unwrap(), expected, "{:?}", std::str::from_utf8($input));
+            }};
+        }
+
+        test!(b"", []);
+        test!(b"{\"offset\":16,\"length\":3,\"hex\":\"616263\"}\n", [(16, 3, b"abc".as_slice())]);
+
+        // without hex, in any order, with whitespaces and extra fields
+        #[rustfmt::skip]
+        test!(
+            b"{\"length\":2,\"offset\":1}\n\
+              \n\
+              { \"offset\" : 4 , \"ascii\" : \"a\\\"b\\\\c\\u0041\" , \"length\" : 0 , \"hex\" : \"\" , \"x\" : [1, {\"y\": null}, true, -1.5e3] }\r\n",
+            [(1, 2, b"".as_slice()), (4, 0, b"".as_slice())]
+        );
+    }
+
+    #[test]
+    fn test_json_parser_label() {
+        let input = b"{\"offset\":1,\"length\":2,\"label\":\"f\\\"x\"}\n{\"offset\":4,\"length\":0}\n";
+        let mut parser = JsonParser::new(Box::new(MockSource::new(input)));
+
+        let mut buf = Vec::new();
+        assert_eq!(parser.read_record(&mut buf).unwrap(), Some((1, 2)));
+        assert_eq!(parser.label(), Some(b"f\"x".as_slice()));
+        assert_eq!(parser.read_record(&mut buf).unwrap(), Some((4, 0)));
+        assert_eq!(parser.label(), None);
+    }
+
+    #[test]
+    fn test_json_parser_err() {
+        assert!(parse_all(b"{\"offset\":16}\n").is_err());
+        assert!(parse_all(b"{\"offset\":16,\"length\":-1}\n").is_err());
+        assert!(parse_all(b"{\"offset\":16,\"length\":1,\"hex\":\"6\"}\n").is_err());
+        assert!(parse_all(b"{\"offset\":16,\"length\":1,\"hex\":\"61\"\n").is_err());
+        assert!(parse_all(b"{\"offset\":16,\"length\":1}}\n").is_err());
+        assert!(parse_all(b"[1, 2]\n").is_err());
+    }
+}
+
+// end of json.rs
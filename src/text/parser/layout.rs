@@ -0,0 +1,234 @@
+// @file layout.rs
+// @author Hajime Suzuki
+// @brief parser for the native form with grouped, unseparated, or uppercase hex arrays, and
+// arrays of octal, decimal, or binary digits
+
+use super::lines::{parse_hex_pairs, trim, LineReader};
+use crate::byte::{ByteStream, PatchSource};
+use crate::text::{ColumnFormat, HexLayout, InoutFormat};
+use anyhow::{anyhow, Result};
+
+fn parse_column(field: &[u8], format: &ColumnFormat) -> Option<usize> {
+    let radix = if *format == ColumnFormat::Decimal { 10 } else { 16 };
+    let field = std::str::from_utf8(field).ok()?;
+    if field.is_empty() || !field.bytes().all(|x| x.is_ascii_hexdigit()) {
+        return None;
+    }
+    usize::from_str_radix(field, radix).ok()
+}
+
+// appends the bytes of a group of up to `layout.group` bytes; `bytes` is a working buffer
+fn parse_group(layout: &HexLayout, group: &[u8], bytes: &mut Vec<u8>, buf: &mut Vec<u8>) -> Option<()> {
+    if group.is_empty() || group.len() > 2 * layout.group || !parse_hex_pairs(group, bytes) {
+        return None;
+    }
+    if layout.is_le {
+        bytes.reverse();
+    }
+    buf.extend_from_slice(bytes);
+    Some(())
+}
+
+// "110", "72", or "01001000"; the decimal ones may be shorter than three digits
+fn parse_cell(body: &ColumnFormat, cell: &[u8]) -> Option<u8> {
+    let (radix, max_len) = match body {
+        ColumnFormat::Octal => (8, 3),
+        ColumnFormat::Decimal => (10, 3),
+        _ => (2, 8),
+    };
+    if cell.is_empty() || cell.len() > max_len || !cell.iter().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(cell).ok()?, radix).ok()
+}
+
+pub struct LayoutParser {
+    lines: LineReader,
+    offset: ColumnFormat,
+    span: ColumnFormat,
+    body: ColumnFormat,
+    layout: HexLayout,
+    bytes: Vec<u8>,
+}
+
+impl LayoutParser {
+    pub fn new(src: Box<dyn ByteStream>, format: &InoutFormat) -> Self {
+        LayoutParser {
+            lines: LineReader::new(src),
+            offset: format.offset.clone(),
+            span: format.span.clone(),
+            body: format.body.clone(),
+            layout: format.layout,
+            bytes: Vec::new(),
+        }
+    }
+
+    fn parse_body(format: &ColumnFormat, layout: &HexLayout, body: &[u8], bytes: &mut Vec<u8>, buf: &mut Vec<u8>) -> Option<()> {
+        if body.is_empty() {
+            return Some(());
+        }
+        if *format != ColumnFormat::Hexadecimal {
+            for cell in body.split(|&x| x == b' ').filter(|x| !x.is_empty()) {
+                buf.push(parse_cell(format, cell)?);
+            }
+            return Some(());
+        }
+        match layout.sep {
+            // runs of spaces are the padding
+            Some(b' ') => body
+                .split(|&x| x == b' ')
+                .filter(|x| !x.is_empty())
+                .try_for_each(|group| parse_group(layout, group, bytes, buf)),
+            Some(sep) => body
+                .split(|&x| x == sep)
+                .try_for_each(|group| parse_group(layout, trim(group), bytes, buf)),
+            None => body
+                .chunks(2 * layout.group)
+                .try_for_each(|group| parse_group(layout, group, bytes, buf)),
+        }
+    }
+
+    pub fn read_record(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        while self.lines.read_line()? {
+            let line = self.lines.line();
+            if line.is_empty() {
+                continue;
+            }
+
+            // "offset span | array | mosaic"
+            let mut fields = line.splitn(3, |&x| x == b' ');
+            let offset = fields.next().and_then(|x| parse_column(x, &self.offset));
+            let span = fields.next().and_then(|x| parse_column(x, &self.span));
+            let (offset, span) = offset
+                .zip(span)
+                .ok_or_else(|| anyhow!("failed to parse the header at record {}", self.lines.format_cache(false)))?;
+
+            let rem = fields.next().unwrap_or_default();
+            if rem.is_empty() {
+                return Ok(Some((offset, span)));
+            }
+            let body = rem.strip_prefix(b"|").ok_or_else(|| {
+                anyhow!(
+                    "invalid delimiter found after the header at record {}",
+                    self.lines.format_cache(false)
+                )
+            })?;
+            let body = trim(body.split(|&x| x == b'|').next().unwrap());
+
+            let len = buf.len();
+            if Self::parse_body(&self.body, &self.layout, body, &mut self.bytes, buf).is_none() {
+                buf.truncate(len);
+                return Err(anyhow!("failed to parse array at {}", self.lines.format_cache(false)));
+            }
+            return Ok(Some((offset, span)));
+        }
+        Ok(None)
+    }
+}
+
+impl PatchSource for LayoutParser {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.read_record(buf)
+    }
+
+    fn format_cache(&self) -> String {
+        self.lines.format_cache(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LayoutParser;
+    use crate::byte::tester::*;
+    use crate::text::InoutFormat;
+
+    fn parse_all(sig: &str, input: &[u8]) -> anyhow::Result<Vec<(usize, usize, Vec<u8>)>> {
+        let format = InoutFormat::from_str(sig).unwrap();
+        let mut parser = LayoutParser::new(Box::new(MockSource::new(input)), &format);
+        let mut records = Vec::new();
+
+        let mut buf = Vec::new();
+        while let Some((offset, span)) = parser.read_record(&mut buf)? {
+            records.push((offset, span, buf.clone()));
+            buf.clear();
+        }
+        Ok(records)
+    }
+
+    macro_rules! test {
+        ( $sig: expr, $input: expr, $expected: expr ) => {{
+            let expected: Vec<(usize, usize, Vec<u8>)> = $expected
+                .iter()
+                .map(|(x, y, z): &(usize, usize, &[u8])| (*x, *y, z.to_vec()))
+                .collect();
+            assert_eq!(parse_all($sig, $input).unwrap(), expected, "{:?}", std::str::from_utf8($input));
+        }};
+    }
+
+    #[test]
+    fn test_layout_parser() {
+        let hello = b"Hello.\n".as_slice();
+
+        test!("x+plain", b"", []);
+        test!("x+plain", b"000000000010 0007 | 48656c6c6f2e0a   | Hello.. \n", [(16, 7, hello)]);
+        test!("x+g2", b"000000000010 0007 | 4865 6c6c 6f2e 0a   | Hello.. \n", [(16, 7, hello)]);
+        test!(
+            "x+g4le+upper",
+            b"000000000010 0007 | 6C6C6548 0A2E6F   | Hello.. \n",
+            [(16, 7, hello)]
+        );
+        test!("x+g8be+sep=:", b"000000000010 0007 | 48656c6c6f2e0a | Hello..\n", [(16, 7, hello)]);
+        test!("x+sep=:", b"000000000010 0007 | 48:65:6c:6c:6f:2e:0a | Hello..\n", [(16, 7, hello)]);
+        test!("ddx+g2le", b"16 7 | 6548 6c6c 2e6f 0a | Hello..\n", [(16, 7, hello)]);
+        test!("x+sep=:", b"000000000010 0000 |   |\n", [(16, 0, b"".as_slice())]);
+
+        // without the array and the mosaic, an empty array, and a mosaic with '|'
+        #[rustfmt::skip]
+        test!(
+            "x+g2",
+            b"000000000010 0002\n\
+              \n\
+              000000000020 0000 |      |   \n\
+              000000000030 0002 | 7c7c | ||\n",
+            [(16, 2, b"".as_slice()), (32, 0, b"".as_slice()), (48, 2, b"||".as_slice())]
+        );
+    }
+
+    #[test]
+    fn test_digits_parser() {
+        let hi = b"Hi\x00\xff".as_slice();
+
+        test!("x,x,o", b"000000000010 0004 | 110 151 000 377         | Hi..  \n", [(16, 4, hi)]);
+        test!("d,d,d", b"16 4 |  72 105   0 255 | Hi..\n", [(16, 4, hi)]);
+        test!("d,d,d", b"16 4 | 72 105 0 255\n", [(16, 4, hi)]);
+        test!(
+            "n,n,B",
+            b"000000000010 0004 | 01001000 01101001 00000000 11111111 | Hi..\n",
+            [(16, 4, hi)]
+        );
+        test!("x,x,o", b"000000000010 0000 |  |\n", [(16, 0, b"".as_slice())]);
+    }
+
+    #[test]
+    fn test_digits_parser_err() {
+        assert!(parse_all("x,x,o", b"000000000010 0001 | 400\n").is_err());
+        assert!(parse_all("x,x,o", b"000000000010 0001 | 18\n").is_err());
+        assert!(parse_all("x,x,o", b"000000000010 0001 | 0101\n").is_err());
+        assert!(parse_all("x,x,d", b"000000000010 0001 | 256\n").is_err());
+        assert!(parse_all("x,x,d", b"000000000010 0001 | +1\n").is_err());
+        assert!(parse_all("x,x,B", b"000000000010 0001 | 00000002\n").is_err());
+        assert!(parse_all("x,x,B", b"000000000010 0001 | 100000000\n").is_err());
+    }
+
+    #[test]
+    fn test_layout_parser_err() {
+        assert!(parse_all("x+g2", b"00000000001g 0002 | 4865\n").is_err());
+        assert!(parse_all("x+g2", b"000000000010 0002 + 4865\n").is_err());
+        assert!(parse_all("x+g2", b"000000000010 0003 | 48656c\n").is_err());
+        assert!(parse_all("x+g2", b"000000000010 0002 | 486\n").is_err());
+        assert!(parse_all("x+sep=:", b"000000000010 0002 | 48::65\n").is_err());
+        assert!(parse_all("x+plain", b"000000000010 0002 | 48 65\n").is_err());
+    }
+}
+
+// end of layout.rs
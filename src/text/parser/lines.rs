@@ -0,0 +1,155 @@
+// @file lines.rs
+// @author Hajime Suzuki
+// @brief line reader for record formats that are parsed line by line
+
+use crate::byte::ByteStream;
+use crate::params::BLOCK_SIZE;
+use anyhow::Result;
+
+pub struct LineReader {
+    src: Box<dyn ByteStream>,
+
+    // the current and the previous lines without surrounding whitespaces; kept for error messages
+    curr: Vec<u8>,
+    prev: Vec<u8>,
+    lines: usize,
+}
+
+impl LineReader {
+    pub fn new(src: Box<dyn ByteStream>) -> Self {
+        LineReader {
+            src,
+            curr: Vec::new(),
+            prev: Vec::new(),
+            lines: 0,
+        }
+    }
+
+    pub fn line(&self) -> &[u8] {
+        &self.curr
+    }
+
+    // reads the next line into the internal buffer; returns false at EOF
+    pub fn read_line(&mut self) -> Result<bool> {
+        let mut request = BLOCK_SIZE;
+        let line = loop {
+            let (is_eof, len) = self.src.fill_buf(request)?;
+            let stream = &self.src.as_slice()[..len];
+
+            if let Some(pos) = stream.iter().position(|&x| x == b'\n') {
+                break (pos, pos + 1);
+            }
+            if is_eof && len == 0 {
+                return Ok(false);
+            }
+            if is_eof {
+                break (len, len);
+            }
+
+            // the line continues beyond the buffer
+            request = std::cmp::max(BLOCK_SIZE, 2 * len);
+        };
+
+        std::mem::swap(&mut self.curr, &mut self.prev);
+        self.curr.clear();
+        self.curr.extend_from_slice(trim(&self.src.as_slice()[..line.0]));
+        self.src.consume(line.1);
+        self.lines += 1;
+
+        Ok(true)
+    }
+
+    pub fn format_cache(&self, include_prev: bool) -> String {
+        let append = |input: &[u8], line: usize, v: &mut String| {
+            if line == 0 {
+                v.push_str("(none)");
+                return;
+            }
+
+            let len = std::cmp::min(input.len(), 31);
+            v.push('"');
+            v.push_str(&String::from_utf8_lossy(&input[..len]));
+            if input.len() > len {
+                v.push_str("...");
+            }
+            v.push_str(&format!("\" (line {line})"));
+        };
+
+        let mut s = String::new();
+        if include_prev {
+            append(&self.prev, self.lines.saturating_sub(1), &mut s);
+            s.push_str(" and ");
+        }
+        append(&self.curr, self.lines, &mut s);
+
+        s
+    }
+}
+
+pub fn trim(line: &[u8]) -> &[u8] {
+    let head = line.iter().position(|x| !x.is_ascii_whitespace()).unwrap_or(line.len());
+    let tail = line.iter().rposition(|x| !x.is_ascii_whitespace()).map_or(head, |x| x + 1);
+    &line[head..tail]
+}
+
+// decodes pairs of hex digits into bytes; returns false on odd-length inputs or non-hex characters
+pub fn parse_hex_pairs(src: &[u8], buf: &mut Vec<u8>) -> bool {
+    fn digit(x: u8) -> Option<u8> {
+        match x {
+            b'0'..=b'9' => Some(x - b'0'),
+            b'a'..=b'f' => Some(x - b'a' + 10),
+            b'A'..=b'F' => Some(x - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    buf.clear();
+    if src.len() % 2 != 0 {
+        return false;
+    }
+    for x in src.chunks(2) {
+        match (digit(x[0]), digit(x[1])) {
+            (Some(hi), Some(lo)) => buf.push((hi << 4) | lo),
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_hex_pairs, LineReader};
+    use crate::byte::tester::*;
+
+    #[test]
+    fn test_line_reader() {
+        let src = Box::new(MockSource::new(b" abc \r\n\ndef\r\nghi"));
+        let mut reader = LineReader::new(src);
+
+        let mut lines = Vec::new();
+        while reader.read_line().unwrap() {
+            lines.push(reader.line().to_vec());
+        }
+        assert_eq!(lines, [b"abc".to_vec(), b"".to_vec(), b"def".to_vec(), b"ghi".to_vec()]);
+        assert_eq!(reader.format_cache(true), "\"def\" (line 3) and \"ghi\" (line 4)");
+
+        let src = Box::new(MockSource::new(b""));
+        let reader = LineReader::new(src);
+        assert_eq!(reader.format_cache(true), "(none) and (none)");
+    }
+
+    #[test]
+    fn test_parse_hex_pairs() {
+        let mut buf = Vec::new();
+        assert!(parse_hex_pairs(b"", &mut buf));
+        assert_eq!(buf, b"");
+        assert!(parse_hex_pairs(b"00aBfF10", &mut buf));
+        assert_eq!(buf, [0x00, 0xab, 0xff, 0x10]);
+
+        assert!(!parse_hex_pairs(b"0", &mut buf));
+        assert!(!parse_hex_pairs(b"0g", &mut buf));
+        assert!(!parse_hex_pairs(b"00 1", &mut buf));
+    }
+}
+
+// end of lines.rs
@@ -17,8 +17,23 @@ use x86_64::*;
 mod naive;
 use naive::*;
 
-use super::{ColumnFormat, InoutFormat};
-use crate::byte::ByteStream;
+mod csv;
+mod dump;
+mod ihex;
+mod json;
+mod layout;
+mod lines;
+mod srec;
+
+pub use self::csv::{CsvParser, Layout};
+pub use self::dump::DumpParser;
+pub use self::ihex::IntelHexParser;
+pub use self::json::JsonParser;
+pub use self::layout::LayoutParser;
+pub use self::srec::SrecParser;
+
+use super::{ColumnFormat, Dialect, InoutFormat};
+use crate::byte::{ByteStream, PatchSource};
 use crate::filluninit::FillUninit;
 use crate::params::{BLOCK_SIZE, MARGIN_SIZE};
 use anyhow::{anyhow, Context, Result};
@@ -39,7 +54,7 @@ fn parse_hex_single(src: &[u8]) -> Option<(u64, usize)> {
 }
 
 #[cfg(test)]
-fn test_parse_hex_single_impl(f: &dyn Fn(&[u8]) -> Option<(u64, usize)>) {
+fn test_parse_hex_single_impl(f: ParseSingle) {
     macro_rules! test {
         ( $input: expr, $expected: expr ) => {
             assert_eq!(f($input.as_bytes()), $expected);
@@ -107,13 +122,13 @@ fn test_parse_hex_single_impl(f: &dyn Fn(&[u8]) -> Option<(u64, usize)>) {
 #[test]
 fn test_parse_hex_single() {
     #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-    test_parse_hex_single_impl(&parse_hex_single_neon);
+    test_parse_hex_single_impl(parse_hex_single_neon);
 
     #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
-    test_parse_hex_single_impl(&parse_hex_single_avx2);
+    test_parse_hex_single_impl(parse_hex_single_avx2);
 
-    test_parse_hex_single_impl(&parse_hex_single_naive);
-    test_parse_hex_single_impl(&parse_hex_single);
+    test_parse_hex_single_impl(parse_hex_single_naive);
+    test_parse_hex_single_impl(parse_hex_single);
 }
 
 #[allow(unreachable_code)]
@@ -130,7 +145,7 @@ pub fn parse_hex_body(is_in_tail: bool, src: &[u8], dst: &mut [u8]) -> Option<((
 
 #[cfg(test)]
 #[rustfmt::skip]
-fn test_parse_hex_body_impl(f: &dyn Fn(bool, &[u8], &mut [u8]) -> Option<((usize, usize), usize)>) {
+fn test_parse_hex_body_impl(f: ParseBody) {
     macro_rules! test {
         ( $input: expr, $expected_arr: expr, $expected_counts: expr ) => {{
             let mut input = $input.as_bytes().to_vec();
@@ -138,8 +153,8 @@ fn test_parse_hex_body_impl(f: &dyn Fn(bool, &[u8], &mut [u8]) -> Option<((usize
             let mut buf = [0; 256];
             let counts = f(false, &input, &mut buf);
             assert_eq!(counts, $expected_counts);
-            if counts.is_some() {
-                assert_eq!(&buf[..counts.unwrap().1], $expected_arr);
+            if let Some((_, filled)) = counts {
+                assert_eq!(&buf[..filled], $expected_arr);
             }
         }};
     }
@@ -213,8 +228,8 @@ fn test_parse_hex_body_impl(f: &dyn Fn(bool, &[u8], &mut [u8]) -> Option<((usize
             let mut buf = [0; 256];
             let counts = f(true, &input, &mut buf);
             assert_eq!(counts, $expected_counts);
-            if counts.is_some() {
-                assert_eq!(&buf[..counts.unwrap().1], $expected_arr);
+            if let Some((_, filled)) = counts {
+                assert_eq!(&buf[..filled], $expected_arr);
             }
         }};
     }
@@ -228,13 +243,13 @@ fn test_parse_hex_body_impl(f: &dyn Fn(bool, &[u8], &mut [u8]) -> Option<((usize
 #[test]
 fn test_parse_hex_body() {
     #[cfg(all(target_arch = "aarch64", target_feature = "neon"))]
-    test_parse_hex_body_impl(&parse_hex_body_neon);
+    test_parse_hex_body_impl(parse_hex_body_neon);
 
     #[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
-    test_parse_hex_body_impl(&parse_hex_body_avx2);
+    test_parse_hex_body_impl(parse_hex_body_avx2);
 
-    test_parse_hex_body_impl(&parse_hex_body_naive);
-    test_parse_hex_body_impl(&parse_hex_body);
+    test_parse_hex_body_impl(parse_hex_body_naive);
+    test_parse_hex_body_impl(parse_hex_body);
 }
 
 type ParseSingle = fn(&[u8]) -> Option<(u64, usize)>;
@@ -532,6 +547,22 @@ impl TextParser {
     }
 }
 
+// parser for the records of any dialect; the native records are parsed by TextParser
+pub fn new_record_parser(src: Box<dyn ByteStream>, format: &InoutFormat) -> Box<dyn PatchSource> {
+    match format.dialect {
+        Dialect::Native if format.layout.is_default() && !format.is_digit_array() => Box::new(TextParser::new(src, format)),
+        Dialect::Native => Box::new(LayoutParser::new(src, format)),
+        Dialect::IntelHex => Box::new(IntelHexParser::new(src)),
+        Dialect::Srec => Box::new(SrecParser::new(src)),
+        Dialect::Json(_) => Box::new(JsonParser::new(src)),
+        Dialect::Csv(_) => Box::new(CsvParser::new(src, Layout::Csv)),
+        Dialect::Tsv => Box::new(CsvParser::new(src, Layout::Tsv)),
+        Dialect::Bed => Box::new(CsvParser::new(src, Layout::Bed)),
+        Dialect::Xxd | Dialect::XxdPlain | Dialect::Od | Dialect::Hexdump => Box::new(DumpParser::new(src, format.dialect)),
+        dialect => panic!("unsupported parser: {dialect:?}"),
+    }
+}
+
 #[test]
 fn test_text_parser_hex_err() {
     macro_rules! test {
@@ -0,0 +1,144 @@
+// @file srec.rs
+// @author Hajime Suzuki
+// @brief Motorola S-record parser
+
+use super::lines::{parse_hex_pairs, LineReader};
+use crate::byte::{ByteStream, PatchSource};
+use anyhow::{anyhow, Result};
+
+pub struct SrecParser {
+    lines: LineReader,
+    bytes: Vec<u8>,
+    is_eof: bool,
+}
+
+impl SrecParser {
+    pub fn new(src: Box<dyn ByteStream>) -> Self {
+        SrecParser {
+            lines: LineReader::new(src),
+            bytes: Vec::new(),
+            is_eof: false,
+        }
+    }
+
+    // returns the record type and the length of the address field
+    fn parse_line(&mut self) -> Result<(u8, usize)> {
+        let line = self.lines.line();
+        if line.len() < 2 || line[0] != b'S' || !parse_hex_pairs(&line[2..], &mut self.bytes) {
+            return Err(anyhow!("failed to parse S-record at {}", self.lines.format_cache(false)));
+        }
+
+        let (kind, addr_len) = match line[1] {
+            b'0' | b'1' | b'5' | b'9' => (line[1] - b'0', 2),
+            b'2' | b'6' | b'8' => (line[1] - b'0', 3),
+            b'3' | b'7' => (line[1] - b'0', 4),
+            _ => return Err(anyhow!("unsupported S-record type at {}", self.lines.format_cache(false))),
+        };
+
+        let bytes = &self.bytes;
+        if bytes.len() < addr_len + 2 || bytes[0] as usize + 1 != bytes.len() {
+            return Err(anyhow!("broken length in S-record at {}", self.lines.format_cache(false)));
+        }
+        if bytes.iter().fold(0u8, |acc, x| acc.wrapping_add(*x)) != 0xff {
+            return Err(anyhow!("checksum mismatch in S-record at {}", self.lines.format_cache(false)));
+        }
+        Ok((kind, addr_len))
+    }
+
+    pub fn read_record(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        while !self.is_eof && self.lines.read_line()? {
+            if self.lines.line().is_empty() {
+                continue;
+            }
+            let (kind, addr_len) = self.parse_line()?;
+
+            // "STCC[AAAA...][DD...]KK"
+            let bytes = &self.bytes;
+            let addr = bytes[1..1 + addr_len].iter().fold(0, |acc, x| (acc << 8) | *x as usize);
+            let data = &bytes[1 + addr_len..bytes.len() - 1];
+
+            match kind {
+                1..=3 => {
+                    buf.extend_from_slice(data);
+                    return Ok(Some((addr, data.len())));
+                }
+                7..=9 => self.is_eof = true,
+                _ => {} // header and counts; ignored
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl PatchSource for SrecParser {
+    fn read_patch(&mut self, buf: &mut Vec<u8>) -> Result<Option<(usize, usize)>> {
+        self.read_record(buf)
+    }
+
+    fn format_cache(&self) -> String {
+        self.lines.format_cache(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SrecParser;
+    use crate::byte::tester::*;
+
+    fn parse_all(input: &[u8]) -> anyhow::Result<Vec<(usize, Vec<u8>)>> {
+        let mut parser = SrecParser::new(Box::new(MockSource::new(input)));
+        let mut records = Vec::new();
+
+        let mut buf = Vec::new();
+        while let Some((offset, span)) = parser.read_record(&mut buf)? {
+            assert_eq!(span, buf.len());
+            records.push((offset, buf.clone()));
+            buf.clear();
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_srec_parser() {
+        macro_rules! test {
+            ( $input: expr, $expected: expr ) => {{
+                let expected: Vec<(usize, Vec<u8>)> = $expected.iter().map(|(x, y): &(usize, &[u8])| (*x, y.to_vec())).collect();
+                assert_eq!(parse_all($input).unwrap(), expected, "{:?}", std::str::from_utf8($input));
+            }};
+        }
+
+        test!(b"", []);
+        test!(b"S9030000FC\n", []);
+
+        // header, data in all address widths, count, and termination
+        #[rustfmt::skip]
+        test!(
+            b"S00600004844521B\n\
+              S1060010010203E3\n\
+              S206012345AABB2B\n\
+              S30608000000FFF2\n\
+              S5030003F9\n\
+              S70508000000F2\n",
+            [
+                (0x0010, [1u8, 2, 3].as_slice()),
+                (0x012345, [0xaau8, 0xbb].as_slice()),
+                (0x0800_0000, [0xffu8].as_slice()),
+            ]
+        );
+
+        // CRLF and records after the termination
+        test!(b"S1040000AA51\r\nS9030000FC\r\nS1040000AA51\r\n", [(0, [0xaau8].as_slice())]);
+    }
+
+    #[test]
+    fn test_srec_parser_err() {
+        assert!(parse_all(b"1040000AA51\n").is_err());
+        assert!(parse_all(b"S1040000AA52\n").is_err());
+        assert!(parse_all(b"S1050000AA50\n").is_err());
+        assert!(parse_all(b"S4040000AA51\n").is_err());
+        assert!(parse_all(b"S10200FD\n").is_err());
+        assert!(parse_all(b"S\n").is_err());
+    }
+}
+
+// end of srec.rs